@@ -4,9 +4,9 @@
 //! interface can only be 1/0.
 use crate::prog::Prog;
 use crate::target::Target;
-use fots::types::{FnInfo, Group, GroupId, PtrDir, TypeId, TypeInfo};
+use fots::types::{FnId, FnInfo, Group, GroupId, PtrDir, TypeId, TypeInfo};
 use ndarray::{Array2, Axis};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Display, Error, Formatter};
 use std::ops::{Deref, DerefMut};
 
@@ -91,10 +91,7 @@ pub fn static_analyze(target: &Target) -> HashMap<GroupId, RTable> {
 }
 
 fn res_analyze(g: &Group, r: &mut RTable, t: &Target) {
-    let mut uses = HashMap::new();
-    g.iter_fn()
-        .enumerate()
-        .for_each(|(i, f)| res_use(i, f, t, &mut uses));
+    let uses = res_uses(g, t);
     for u in uses.values() {
         for &p in &u.producer {
             for &c in &u.consumer {
@@ -120,10 +117,20 @@ fn attr_analyze(g: &Group, r: &mut RTable) {
     }
 }
 
+/// Interfaces consume and produce a resource, by index in group.
 #[derive(Default)]
-struct Use {
-    consumer: Vec<usize>,
-    producer: Vec<usize>,
+pub(crate) struct Use {
+    pub consumer: Vec<usize>,
+    pub producer: Vec<usize>,
+}
+
+/// Consumers and producers of each resource type used by group.
+pub(crate) fn res_uses(g: &Group, t: &Target) -> HashMap<TypeId, Use> {
+    let mut uses = HashMap::new();
+    g.iter_fn()
+        .enumerate()
+        .for_each(|(i, f)| res_use(i, f, t, &mut uses));
+    uses
 }
 
 fn res_use(index: usize, f: &FnInfo, t: &Target, uses: &mut HashMap<TypeId, Use>) {
@@ -158,6 +165,60 @@ fn record_use(uses: &mut HashMap<TypeId, Use>, res: TypeId, fn_index: usize, in_
     }
 }
 
+/// Interfaces depending on `disabled` ones, with reason.
+///
+/// An interface is disabled too if it consumes a resource whose producers
+/// are all disabled, transitively. Resources without any producer are
+/// left alone, they are generated as plain values anyway.
+pub fn disabled_dependents(t: &Target, disabled: &HashSet<FnId>) -> HashMap<FnId, String> {
+    let mut dependents = HashMap::new();
+    for g in t.iter_group() {
+        let uses = res_uses(g, t);
+        let is_disabled = |i: usize, dependents: &HashMap<FnId, String>| {
+            let id = g.fns[i].id;
+            disabled.contains(&id) || dependents.contains_key(&id)
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (&res, u) in uses.iter() {
+                if u.producer.is_empty() || !u.producer.iter().all(|&p| is_disabled(p, &dependents))
+                {
+                    continue;
+                }
+                for &c in u.consumer.iter() {
+                    if !is_disabled(c, &dependents) {
+                        let res = t.type_of(res).ident().unwrap_or("resource");
+                        let reason = format!("all producers of {} are disabled", res);
+                        dependents.insert(g.fns[c].id, reason);
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    dependents
+}
+
+/// Least number of relation steps from each interface to interface `to`,
+/// `None` if it has no impact on `to` even transitively.
+pub fn distances(r: &RTable, to: usize) -> Vec<Option<usize>> {
+    let mut dist = vec![None; r.len()];
+    dist[to] = Some(0);
+    let mut queue = VecDeque::new();
+    queue.push_back(to);
+    while let Some(c) = queue.pop_front() {
+        let d = dist[c].unwrap();
+        for (p, rel) in r.index_axis(Axis(0), c).iter().enumerate() {
+            if *rel == Relation::Some && dist[p].is_none() {
+                dist[p] = Some(d + 1);
+                queue.push_back(p);
+            }
+        }
+    }
+    dist
+}
+
 /// Analyze call seq of prog, update RTable
 ///
 /// Analysis is based on the order of target in a prog.
@@ -181,3 +242,43 @@ pub fn prog_analyze(g: &Group, r: &mut RTable, p: &Prog) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::target;
+
+    #[test]
+    fn disable_dependents() {
+        let t = target();
+        let id = |name| {
+            t.fns
+                .keys()
+                .copied()
+                .find(|&fid| t.fn_of(fid).dec_name == name)
+                .unwrap()
+        };
+
+        let disabled = hashset! {id("foo_open")};
+        let dependents = disabled_dependents(&t, &disabled);
+        let mut names = dependents
+            .keys()
+            .map(|&fid| t.fn_of(fid).dec_name.as_str())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, ["foo_close", "foo_connect", "foo_read", "foo_send"]);
+        assert_eq!(
+            dependents[&id("foo_send")],
+            "all producers of conn are disabled"
+        );
+
+        let mut disabled = disabled;
+        disabled.extend(dependents.keys());
+        disabled.insert(id("node_ioctl"));
+        let t = t.without(&disabled);
+        assert_eq!(t.groups.len(), 2);
+        assert_eq!(t.fns.len(), 7);
+        let rs = static_analyze(&t);
+        assert_eq!(rs[&t.fn_of(id("foo_sync")).gid].len(), 4);
+    }
+}
@@ -6,7 +6,7 @@ use crate::prog::{ArgIndex, ArgPos, Call, Prog};
 use crate::target::Target;
 use crate::value::Value;
 use fots::types::{Field, NumInfo, NumLimit, PtrDir, StrType, TypeId, TypeInfo};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::{Display, Error, Formatter};
 
 use std::fmt::Write;
@@ -26,7 +26,7 @@ impl Display for Script {
 }
 
 pub fn to_script(p: &Prog, t: &Target) -> Script {
-    let mut s: State = Default::default();
+    let mut s = State::new(p, t);
 
     // for each prototype and call
     for (i, c) in p.calls.iter().enumerate() {
@@ -60,12 +60,14 @@ pub fn to_prog(p: &Prog, t: &Target) -> String {
 
         writeln!(c_stmts, "{}", stmts.to_string()).unwrap();
     }
+    includes.extend(group_includes(p, t));
 
     let mut incs = String::new();
     writeln!(incs, "#define _GNU_SOURCE").unwrap();
     for header in includes.into_iter() {
         writeln!(incs, "#include<{}>", header).unwrap();
     }
+    write!(incs, "{}", type_defs(p, t)).unwrap();
     format!(
         r#"{}
 
@@ -77,6 +79,170 @@ return 0;
     )
 }
 
+/// Headers declared with `#[inc(...)]` on group of prog.
+pub fn group_includes(p: &Prog, t: &Target) -> Vec<String> {
+    group_attr_vals(p, t, "inc")
+}
+
+/// Shared libraries declared with `#[lib(...)]` on group of prog.
+///
+/// Functions of such group come from user-supplied library instead of
+/// kernel, the names are passed to linker as `-l` options.
+pub fn group_libs(p: &Prog, t: &Target) -> Vec<String> {
+    group_attr_vals(p, t, "lib")
+}
+
+/// Definitions of structs and unions used by prog, for group declared with
+/// `#[defs]`, empty for other groups.
+///
+/// Such group doesn't rely on headers to define its types: each one is
+/// defined from its FOTS fields and named with `healer_` prefix, not to
+/// clash with the definition of a header. All types are declared first and
+/// defined after types they contain by value, pointers to a type need the
+/// declaration only.
+pub fn type_defs(p: &Prog, t: &Target) -> String {
+    let mut defs = String::new();
+    if !has_defs(p, t) {
+        return defs;
+    }
+    let mut reachable = BTreeSet::new();
+    for c in p.calls.iter() {
+        let f = t.fn_of(c.fid);
+        let params = f.params.iter().flatten().map(|p| p.tid);
+        for tid in params.chain(f.r_tid) {
+            reach(tid, t, &mut reachable);
+        }
+    }
+    if reachable.is_empty() {
+        return defs;
+    }
+
+    let mut ordered = Vec::new();
+    let mut visited = HashSet::new();
+    for &tid in reachable.iter() {
+        order_by_val(tid, t, &mut visited, &mut ordered);
+    }
+    writeln!(defs).unwrap();
+    for &tid in ordered.iter() {
+        let (kind, ident, _) = compound_of(tid, t).unwrap();
+        writeln!(defs, "{} {};", kind, type_name(ident, true)).unwrap();
+    }
+    for &tid in ordered.iter() {
+        let (kind, ident, fields) = compound_of(tid, t).unwrap();
+        writeln!(defs, "{} {} {{", kind, type_name(ident, true)).unwrap();
+        for field in fields {
+            let (ts, decl) = field_map(field, t);
+            writeln!(defs, "    {} {};", ts, decl).unwrap();
+        }
+        writeln!(defs, "}}{};", layout_attrs(t.type_of(tid))).unwrap();
+    }
+    defs
+}
+
+/// Whether group of prog is declared with `#[defs]`, see `type_defs`.
+fn has_defs(p: &Prog, t: &Target) -> bool {
+    t.groups[&p.gid].get_attr("defs").is_some()
+}
+
+/// Collect structs and unions reachable from `tid`.
+fn reach(tid: TypeId, t: &Target, reachable: &mut BTreeSet<TypeId>) {
+    match t.type_of(tid) {
+        TypeInfo::Struct { fields, .. } | TypeInfo::Union { fields, .. } => {
+            if reachable.insert(tid) {
+                for field in fields.iter() {
+                    reach(field.tid, t, reachable);
+                }
+            }
+        }
+        TypeInfo::Ptr { tid, .. }
+        | TypeInfo::Slice { tid, .. }
+        | TypeInfo::Alias { tid, .. }
+        | TypeInfo::Res { tid }
+        | TypeInfo::Len { tid, .. } => reach(*tid, t, reachable),
+        TypeInfo::Num(_) | TypeInfo::Str { .. } | TypeInfo::Flag { .. } => (),
+    }
+}
+
+/// Push structs and unions contained by `tid` by value, then `tid` itself.
+fn order_by_val(tid: TypeId, t: &Target, visited: &mut HashSet<TypeId>, ordered: &mut Vec<TypeId>) {
+    match t.type_of(tid) {
+        TypeInfo::Struct { fields, .. } | TypeInfo::Union { fields, .. } => {
+            if visited.insert(tid) {
+                for field in fields.iter() {
+                    order_by_val(field.tid, t, visited, ordered);
+                }
+                ordered.push(tid);
+            }
+        }
+        TypeInfo::Slice { tid, .. }
+        | TypeInfo::Alias { tid, .. }
+        | TypeInfo::Res { tid }
+        | TypeInfo::Len { tid, .. } => order_by_val(*tid, t, visited, ordered),
+        TypeInfo::Ptr { .. } | TypeInfo::Num(_) | TypeInfo::Str { .. } | TypeInfo::Flag { .. } => {}
+    }
+}
+
+fn compound_of(tid: TypeId, t: &Target) -> Option<(&'static str, &str, &[Field])> {
+    match t.type_of(tid) {
+        TypeInfo::Struct { ident, fields, .. } => Some(("struct", ident, fields)),
+        TypeInfo::Union { ident, fields, .. } => Some(("union", ident, fields)),
+        _ => None,
+    }
+}
+
+/// Field of struct or union definition, slice of fixed length is mapped to
+/// array of that length, slice of range to array of max length. Slice
+/// without length is rejected by fots in `#[defs]` groups.
+fn field_map(field: &Field, t: &Target) -> (TypeSpecifier, Declarator) {
+    match t.type_of(field.tid) {
+        TypeInfo::Slice { tid, l, h } => {
+            let len = if *h == -1 { *l } else { *h };
+            map_array(*tid, &field.ident, Some(len.max(0) as usize), t, true)
+        }
+        _ => declarator_map(field.tid, &field.ident, t, true),
+    }
+}
+
+/// `#[packed]` and `#[align(n)]` of struct or union as gcc attributes, so that
+/// definitions are laid out as `layout::layout_of` computes.
+fn layout_attrs(info: &TypeInfo) -> String {
+    let mut attrs = Vec::new();
+    if info.get_attr("packed").is_some() {
+        attrs.push("packed".to_string());
+    }
+    if let Some(n) = info
+        .get_attr("align")
+        .and_then(|a| a.vals.as_ref()?.first())
+    {
+        attrs.push(format!("aligned({})", n));
+    }
+    if attrs.is_empty() {
+        String::new()
+    } else {
+        format!(" __attribute__(({}))", attrs.join(", "))
+    }
+}
+
+/// Name of struct or union in C, see `type_defs`.
+fn type_name(ident: &str, mangle: bool) -> String {
+    if mangle {
+        let ident = ident
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        format!("healer_{}", ident)
+    } else {
+        ident.to_string()
+    }
+}
+
+fn group_attr_vals(p: &Prog, t: &Target, name: &str) -> Vec<String> {
+    t.groups[&p.gid]
+        .get_attr(name)
+        .and_then(|attr| attr.vals.clone())
+        .unwrap_or_default()
+}
+
 pub struct IterTranslate<'a> {
     p: &'a Prog,
     t: &'a Target,
@@ -105,7 +271,7 @@ pub fn iter_trans<'a>(p: &'a Prog, t: &'a Target) -> IterTranslate<'a> {
     IterTranslate {
         p,
         t,
-        s: State::default(),
+        s: State::new(p, t),
         call_index: 0,
     }
 }
@@ -129,7 +295,7 @@ fn translate_call(call_index: usize, c: &Call, t: &Target, s: &mut State) {
         let var_name = s.var_names.next_r();
         s.res.insert((call_index, ArgPos::Ret), var_name.clone());
 
-        let (ts, decl) = declarator_map(tid, &var_name, t);
+        let (ts, decl) = declarator_map(tid, &var_name, t, s.defs);
         s.add_decl(ts, decl, Some(call));
     } else {
         s.stmts.push(Stmt::SimpleExp(call))
@@ -219,8 +385,8 @@ fn decl_var(tid: TypeId, val: &Value, t: &Target, s: &mut State) -> String {
         TypeInfo::Flag { .. } => decl_num(&NumInfo::U32(NumLimit::None), val, s),
         TypeInfo::Len { tid, .. } => decl_var(*tid, val, t, s),
         TypeInfo::Str { str_type, .. } => decl_str(str_type, val, s),
-        TypeInfo::Struct { ident, fields } => decl_struct(ident, fields, val, t, s),
-        TypeInfo::Union { ident, fields } => decl_union(ident, fields, val, t, s),
+        TypeInfo::Struct { ident, fields, .. } => decl_struct(ident, fields, val, t, s),
+        TypeInfo::Union { ident, fields, .. } => decl_union(ident, fields, val, t, s),
         TypeInfo::Alias { tid, .. } => decl_var(*tid, val, t, s),
         TypeInfo::Res { tid } => decl_var(*tid, &Value::default_val(*tid, t), t, s),
         TypeInfo::Slice { tid: under_tid, .. } => {
@@ -263,7 +429,7 @@ fn decl_slice(under_tid: TypeId, val: &Value, t: &Target, s: &mut State) -> Stri
         panic!("Value type not match")
     };
 
-    let (ts, decl) = map_array(under_tid, &name, Some(len), t);
+    let (ts, decl) = map_array(under_tid, &name, Some(len), t, s.defs);
     let exp = translate_slice(under_tid, val, t, s);
     s.add_decl(ts, decl, Some(exp));
     name
@@ -271,7 +437,7 @@ fn decl_slice(under_tid: TypeId, val: &Value, t: &Target, s: &mut State) -> Stri
 
 fn decl_struct(ident: &str, fields: &[Field], val: &Value, t: &Target, s: &mut State) -> String {
     let var_name = s.var_names.next_p(ident);
-    let (ts, decl) = map_struct(ident, &var_name);
+    let (ts, decl) = map_struct(ident, &var_name, s.defs);
     s.add_decl(ts, decl, None);
 
     let vals = if let Value::Group(v) = val {
@@ -290,7 +456,7 @@ fn decl_struct(ident: &str, fields: &[Field], val: &Value, t: &Target, s: &mut S
 
 fn decl_union(ident: &str, fields: &[Field], val: &Value, t: &Target, s: &mut State) -> String {
     let var_name = s.var_names.next_p(ident);
-    let (ts, decl) = map_union(ident, &var_name);
+    let (ts, decl) = map_union(ident, &var_name, s.defs);
     s.add_decl(ts, decl, None);
 
     let (val, choice) = if let Value::Opt { choice, val } = val {
@@ -314,41 +480,42 @@ fn decl_union(ident: &str, fields: &[Field], val: &Value, t: &Target, s: &mut St
 //    s.stmts.push(Stmt::VarDecl(decl));
 //}
 
-fn declarator_map(tid: TypeId, var_name: &str, t: &Target) -> (TypeSpecifier, Declarator) {
+/// Map type to C declarator, `defs` if names of structs and unions are
+/// mangled, see `type_defs`.
+fn declarator_map(
+    tid: TypeId,
+    var_name: &str,
+    t: &Target,
+    defs: bool,
+) -> (TypeSpecifier, Declarator) {
     match t.type_of(tid) {
         TypeInfo::Num(n) => map_num(n, var_name),
-        TypeInfo::Slice { tid, .. } => map_array(*tid, var_name, None, t),
+        TypeInfo::Slice { tid, .. } => map_array(*tid, var_name, None, t, defs),
         TypeInfo::Str { str_type, .. } => map_str(str_type, var_name, None),
-        TypeInfo::Struct { ident, .. } => (
-            TypeSpecifier::Struct(ident.clone()),
-            Declarator::Ident(var_name.to_string()),
-        ),
-        TypeInfo::Union { ident, .. } => (
-            TypeSpecifier::Union(ident.clone()),
-            Declarator::Ident(var_name.to_string()),
-        ),
+        TypeInfo::Struct { ident, .. } => map_struct(ident, var_name, defs),
+        TypeInfo::Union { ident, .. } => map_union(ident, var_name, defs),
         TypeInfo::Flag { .. } => map_flag(var_name),
-        TypeInfo::Alias { tid, .. } => declarator_map(*tid, var_name, t),
-        TypeInfo::Res { tid } => declarator_map(*tid, var_name, t),
-        TypeInfo::Len { tid, .. } => declarator_map(*tid, var_name, t),
+        TypeInfo::Alias { tid, .. } => declarator_map(*tid, var_name, t, defs),
+        TypeInfo::Res { tid } => declarator_map(*tid, var_name, t, defs),
+        TypeInfo::Len { tid, .. } => declarator_map(*tid, var_name, t, defs),
 
         TypeInfo::Ptr { tid, depth, .. } => {
             assert_eq!(*depth, 1);
-            map_ptr(*tid, var_name, t)
+            map_ptr(*tid, var_name, t, defs)
         }
     }
 }
 
-fn map_struct(ident: &str, var_name: &str) -> (TypeSpecifier, Declarator) {
+fn map_struct(ident: &str, var_name: &str, defs: bool) -> (TypeSpecifier, Declarator) {
     (
-        TypeSpecifier::Struct(ident.to_string()),
+        TypeSpecifier::Struct(type_name(ident, defs)),
         Declarator::Ident(var_name.to_string()),
     )
 }
 
-fn map_union(ident: &str, var_name: &str) -> (TypeSpecifier, Declarator) {
+fn map_union(ident: &str, var_name: &str, defs: bool) -> (TypeSpecifier, Declarator) {
     (
-        TypeSpecifier::Union(ident.to_string()),
+        TypeSpecifier::Union(type_name(ident, defs)),
         Declarator::Ident(var_name.to_string()),
     )
 }
@@ -360,8 +527,8 @@ fn map_flag(var_name: &str) -> (TypeSpecifier, Declarator) {
     )
 }
 
-fn map_ptr(tid: TypeId, var_name: &str, t: &Target) -> (TypeSpecifier, Declarator) {
-    let (ts, decl) = declarator_map(tid, var_name, t);
+fn map_ptr(tid: TypeId, var_name: &str, t: &Target, defs: bool) -> (TypeSpecifier, Declarator) {
+    let (ts, decl) = declarator_map(tid, var_name, t, defs);
     let decl = if t.is_str(tid) || t.is_slice(tid) {
         decl
     } else {
@@ -389,8 +556,9 @@ fn map_array(
     var_name: &str,
     len: Option<usize>,
     t: &Target,
+    defs: bool,
 ) -> (TypeSpecifier, Declarator) {
-    let (ts, decl) = declarator_map(tid, var_name, t);
+    let (ts, decl) = declarator_map(tid, var_name, t, defs);
     (
         ts,
         Declarator::Array {
@@ -430,9 +598,18 @@ struct State {
     stmts: Vec<Stmt>,
     var_names: VarName,
     res: HashMap<ArgIndex, String>,
+    /// Types are defined by `type_defs`, use mangled names.
+    defs: bool,
 }
 
 impl State {
+    fn new(p: &Prog, t: &Target) -> Self {
+        Self {
+            defs: has_defs(p, t),
+            ..Self::default()
+        }
+    }
+
     pub fn add_decl(&mut self, ts: TypeSpecifier, decl: Declarator, val: Option<Exp>) {
         self.stmts.push(Stmt::VarDecl(Declaration {
             ts,
@@ -678,3 +855,56 @@ impl Display for Asignment {
         write!(f, "{} = {}", self.ident, self.init)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::target;
+
+    #[test]
+    fn defs() {
+        let t = target();
+        let prog = |ident| {
+            let g = t.iter_group().find(|g| g.ident == ident).unwrap();
+            let mut p = Prog::new(g.id);
+            p.add_call(Call::new(g.fns[0].id));
+            p
+        };
+
+        let defs = type_defs(&prog("Node"), &t);
+        let pos = |def: &str| defs.find(def).unwrap();
+        for ident in &["node_arg", "node", "node_list"] {
+            assert!(pos(&format!("struct healer_{};", ident)) < pos("struct healer_node {"));
+        }
+        assert!(pos("struct healer_node {") < pos("struct healer_node_list {"));
+        assert!(pos("struct healer_node {") < pos("union healer_node_val {"));
+        assert!(pos("union healer_node_val {") < pos("struct healer_node_arg {"));
+        assert!(defs.contains("    struct healer_node_list *next;\n"));
+        assert!(defs.contains("    union healer_node_val val;\n"));
+
+        assert!(type_defs(&prog("Bar"), &t).is_empty());
+        let (ts, _) = map_struct("foo_arg", "arg", false);
+        assert_eq!(ts.to_string(), "struct foo_arg");
+    }
+
+    #[test]
+    fn defs_unbounded_slice() {
+        let fots = |buf: &str| {
+            r#"
+struct foo_buf {
+    size i32
+    buf BUF
+}
+#[defs]
+group Foo {
+    fn foo_ioctl(arg *In foo_buf)
+}
+"#
+            .replace("BUF", buf)
+        };
+        assert!(fots::parse_items(&fots("[i8; 8]")).is_ok());
+        assert!(fots::parse_items(&fots("[i8; (1, 8)]")).is_ok());
+        let e = fots::parse_items(&fots("[i8]")).unwrap_err();
+        assert!(e.to_string().contains("foo_buf.buf"));
+    }
+}
@@ -20,10 +20,12 @@ use rand::prelude::*;
 use rand::{random, thread_rng, Rng};
 
 use fots::types::{
-    Field, Flag, FnInfo, GroupId, NumInfo, NumLimit, PtrDir, StrType, TypeId, TypeInfo,
+    Field, Flag, FnId, FnInfo, GroupId, LenUnit, NumInfo, NumLimit, PtrDir, StrType, TypeId,
+    TypeInfo,
 };
 
-use crate::analyze::{RTable, Relation};
+use crate::analyze::{res_uses, RTable, Relation, Use};
+use crate::layout::{len_of_val, Arch};
 use crate::prog::{Arg, ArgIndex, ArgPos, Call, Prog};
 use crate::target::Target;
 use crate::value::{NumValue, Value};
@@ -36,6 +38,8 @@ pub struct Config {
     pub str_max_len: usize,
     pub path_max_depth: usize,
     pub sp_delta: f64,
+    /// Arch of guest, sizes of `bytesize` and `bitsize` depend on it
+    pub arch: Arch,
 }
 
 impl Default for Config {
@@ -47,6 +51,7 @@ impl Default for Config {
             str_max_len: 32,
             path_max_depth: 4,
             sp_delta: 0.4,
+            arch: Arch::default(),
         }
     }
 }
@@ -73,6 +78,67 @@ pub fn gen_prog(gid: GroupId, r: &RTable, t: &Target, conf: &Config) -> Prog {
     gen_seq(&seq, gid, t, conf)
 }
 
+/// Generate prog ending with call of `fid`, to exercise it deeply.
+///
+/// Sequence is built backward from `fid`: for each call, one producer is
+/// pulled in for every resource it consumes that no earlier call produces,
+/// and calls having impact on it in relation table are pulled in with
+/// selection probability, transitively until `prog_max_len`.
+pub fn gen_prog_for<S: std::hash::BuildHasher>(
+    fid: FnId,
+    rs: &HashMap<GroupId, RTable, S>,
+    t: &Target,
+    conf: &Config,
+) -> Prog {
+    let gid = t.fn_of(fid).gid;
+    let g = &t.groups[&gid];
+    let target = g.index_by_id(fid).unwrap();
+    let seq = choose_seq_for(target, &rs[&gid], &res_uses(g, t), conf);
+    gen_seq(&seq, gid, t, conf)
+}
+
+fn choose_seq_for(
+    target: usize,
+    r: &RTable,
+    uses: &HashMap<TypeId, Use>,
+    conf: &Config,
+) -> Vec<usize> {
+    let mut rng = thread_rng();
+    let mut sps = vec![1.0; r.len()];
+    sps[target] *= conf.sp_delta;
+    // Built backward, calls after i are before seq[i] in prog.
+    let mut seq = vec![target];
+    let mut i = 0;
+    while i < seq.len() && seq.len() < conf.prog_max_len {
+        let c = seq[i];
+        for u in uses.values() {
+            if seq.len() >= conf.prog_max_len {
+                break;
+            }
+            if !u.consumer.contains(&c) || seq[i + 1..].iter().any(|j| u.producer.contains(j)) {
+                continue;
+            }
+            let producers = u.producer.iter().filter(|&&p| p != c).collect::<Vec<_>>();
+            if let Some(&&p) = producers.choose(&mut rng) {
+                sps[p] *= conf.sp_delta;
+                seq.push(p);
+            }
+        }
+        for (j, rel) in r.index_axis(Axis(0), c).iter().enumerate() {
+            if seq.len() >= conf.prog_max_len {
+                break;
+            }
+            if j != c && *rel == Relation::Some && random::<f64>() < sps[j] {
+                sps[j] *= conf.sp_delta;
+                seq.push(j);
+            }
+        }
+        i += 1;
+    }
+    seq.reverse();
+    seq
+}
+
 pub fn gen_seq(seq: &[usize], gid: GroupId, t: &Target, conf: &Config) -> Prog {
     let g = &t.groups[&gid];
     assert!(!g.fns.is_empty());
@@ -82,40 +148,41 @@ pub fn gen_seq(seq: &[usize], gid: GroupId, t: &Target, conf: &Config) -> Prog {
     for &i in seq.iter() {
         gen_call(t, &g.fns[i], &mut s);
     }
-    adjust_size_param(&mut s.prog, t);
+    adjust_size_param(&mut s.prog, t, conf.arch);
     s.prog
 }
 
-fn adjust_size_param(p: &mut Prog, t: &Target) {
+fn adjust_size_param(p: &mut Prog, t: &Target, arch: Arch) {
     for c in &mut p.calls.iter_mut() {
         let f = t.fn_of(c.fid);
         if f.has_params() {
             for (i, p) in f.iter_param().enumerate() {
-                if let Some(path) = t.len_info_of(p.tid) {
+                if let Some((path, unit)) = t.len_info_of(p.tid) {
                     if let Some(j) = f.iter_param().position(|p| p.ident == path) {
-                        if let Some(l) = c.args[j].val.len() {
+                        let tid = f.params.as_ref().unwrap()[j].tid;
+                        if let Some(l) = len_of_val(unit, tid, &c.args[j].val, t, arch) {
                             c.args[i].val = Value::Num(NumValue::Unsigned(l as u64));
                         }
                     }
                 } else {
-                    adjust_size(p.tid, &mut c.args[i].val, t);
+                    adjust_size(p.tid, &mut c.args[i].val, t, arch);
                 }
             }
         }
     }
 }
 
-fn adjust_size(tid: TypeId, v: &mut Value, t: &Target) {
+fn adjust_size(tid: TypeId, v: &mut Value, t: &Target, arch: Arch) {
     match t.type_of(tid) {
         TypeInfo::Ptr { tid, .. } => {
             if v != &Value::None {
-                adjust_size(*tid, v, t);
+                adjust_size(*tid, v, t, arch);
             }
         }
         TypeInfo::Slice { tid, .. } => {
             if let Value::Group(vals) = v {
                 for v in vals.iter_mut() {
-                    adjust_size(*tid, v, t);
+                    adjust_size(*tid, v, t, arch);
                 }
             }
         }
@@ -125,29 +192,37 @@ fn adjust_size(tid: TypeId, v: &mut Value, t: &Target) {
             } else {
                 panic!()
             };
-            asign_struct(fields, vals, t);
+            asign_struct(fields, vals, t, arch);
         }
-        TypeInfo::Alias { tid, .. } => adjust_size(*tid, v, t),
+        TypeInfo::Alias { tid, .. } => adjust_size(*tid, v, t, arch),
         _ => (),
     }
 }
 
-fn asign_struct(fields: &[Field], vals: &mut [Value], t: &Target) {
+fn asign_struct(fields: &[Field], vals: &mut [Value], t: &Target, arch: Arch) {
     for (i, f) in fields.iter().enumerate() {
-        if let Some(p) = t.len_info_of(f.tid) {
-            asign_len_val(i, p, fields, vals, t)
+        if let Some((p, unit)) = t.len_info_of(f.tid) {
+            asign_len_val(i, p, unit, fields, vals, t, arch)
         } else if let Some((_, fields)) = t.struct_info_of(f.tid) {
             let vals = if let Value::Group(val) = &mut vals[i] {
                 val
             } else {
                 panic!()
             };
-            asign_struct(fields, vals, t)
+            asign_struct(fields, vals, t, arch)
         }
     }
 }
 
-fn asign_len_val(index: usize, path: &str, fields: &[Field], vals: &mut [Value], t: &Target) {
+fn asign_len_val(
+    index: usize,
+    path: &str,
+    unit: LenUnit,
+    fields: &[Field],
+    vals: &mut [Value],
+    t: &Target,
+    arch: Arch,
+) {
     let mut sub_paths = path.split('.');
     let mut p = sub_paths.next().unwrap();
 
@@ -168,7 +243,7 @@ fn asign_len_val(index: usize, path: &str, fields: &[Field], vals: &mut [Value],
                 };
                 p = n_p;
             } else {
-                if let Some(l) = crt_vals[i].len() {
+                if let Some(l) = len_of_val(unit, crt_field[i].tid, &crt_vals[i], t, arch) {
                     vals[index] = Value::Num(NumValue::Unsigned(l as u64));
                 }
                 break;
@@ -599,3 +674,39 @@ fn push_deps(rs: &RTable, seq: &mut Vec<usize>, mut i: usize, sps: &mut [f64], c
         i += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyze::{distances, static_analyze};
+    use crate::test_util::target;
+
+    #[test]
+    fn gen_for_target() {
+        let t = target();
+        let rs = static_analyze(&t);
+        let g = t.iter_group().find(|g| g.ident == "Foo").unwrap();
+        let index = |name| g.index_by_name(name).unwrap();
+        let send = g.fns[index("foo_send")].id;
+
+        let dist = distances(&rs[&g.id], index("foo_send"));
+        assert_eq!(dist[index("foo_send")], Some(0));
+        assert_eq!(dist[index("foo_connect")], Some(1));
+        assert_eq!(dist[index("foo_open")], Some(2));
+        assert_eq!(dist[index("foo_close")], None);
+
+        for _ in 0..64 {
+            let p = gen_prog_for(send, &rs, &t, &Default::default());
+            let names = p
+                .calls
+                .iter()
+                .map(|c| t.fn_of(c.fid).dec_name.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(names.last(), Some(&"foo_send"));
+            let open = names.iter().position(|&n| n == "foo_open").unwrap();
+            let connect = names.iter().position(|&n| n == "foo_connect").unwrap();
+            assert!(open < connect);
+            assert!(!names.contains(&"foo_close"));
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Size, alignment and field offsets of types.
+//!
+//! Types are laid out as C compilers of the architecture do: each field is
+//! placed at the next offset aligned to its alignment, struct is as aligned
+//! as its most aligned field and padded to multiple of it. `#[packed]` drops
+//! alignment of fields and `#[align(n)]` raises alignment of the whole type.
+//! Layout of `#[defs]` definitions emitted by `c::type_defs` follows the same
+//! rules, so `bytesize` and `bitsize` lengths match what the call sees.
+use crate::target::Target;
+use crate::value::Value;
+use fots::types::{Field, LenUnit, NumInfo, StrType, TypeId, TypeInfo};
+use std::str::FromStr;
+
+/// Architecture of guest, decides size of pointers and alignment of 64-bit nums.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    #[default]
+    Amd64,
+    I386,
+    Arm64,
+    Arm,
+}
+
+impl FromStr for Arch {
+    type Err = String;
+
+    /// Parse arch named as in guest config, e.g. `amd64`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "amd64" => Ok(Arch::Amd64),
+            "386" => Ok(Arch::I386),
+            "arm64" => Ok(Arch::Arm64),
+            "arm" => Ok(Arch::Arm),
+            _ => Err(format!("unknown arch: {}", s)),
+        }
+    }
+}
+
+impl Arch {
+    pub fn ptr_size(self) -> usize {
+        match self {
+            Arch::Amd64 | Arch::Arm64 => 8,
+            Arch::I386 | Arch::Arm => 4,
+        }
+    }
+
+    /// Alignment of `i64` and `u64`, i386 ABI aligns them to 4 only.
+    fn align_64(self) -> usize {
+        match self {
+            Arch::I386 => 4,
+            _ => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub size: usize,
+    pub align: usize,
+    /// Offset of each field of struct, zeros for union, empty for other types.
+    pub offsets: Vec<usize>,
+}
+
+impl Layout {
+    fn scalar(size: usize, align: usize) -> Self {
+        Self {
+            size,
+            align,
+            offsets: Vec::new(),
+        }
+    }
+}
+
+/// Layout of `tid` on `arch`.
+///
+/// Slice of fixed length is an array of that length, slice of range is an
+/// array of max length and slice without length has size 0, like flexible
+/// array member. `str` is an array of chars with size 0 as well, `cstr`
+/// and `filename` are pointers.
+pub fn layout_of(tid: TypeId, t: &Target, arch: Arch) -> Layout {
+    match t.type_of(tid) {
+        TypeInfo::Num(n) => {
+            let size = num_size(n, arch);
+            let align = if size == 8 { arch.align_64() } else { size };
+            Layout::scalar(size, align)
+        }
+        TypeInfo::Ptr { .. } => Layout::scalar(arch.ptr_size(), arch.ptr_size()),
+        TypeInfo::Flag { .. } => Layout::scalar(4, 4),
+        TypeInfo::Str { str_type, .. } => match str_type {
+            StrType::Str => Layout::scalar(0, 1),
+            StrType::CStr | StrType::FileName => Layout::scalar(arch.ptr_size(), arch.ptr_size()),
+        },
+        TypeInfo::Slice { tid, l, h } => {
+            let elem = layout_of(*tid, t, arch);
+            let len = match (*l, *h) {
+                (l, -1) if l >= 0 => l as usize,
+                (_, h) if h >= 0 => h as usize,
+                _ => 0,
+            };
+            Layout::scalar(elem.size * len, elem.align)
+        }
+        TypeInfo::Struct { fields, .. } => compound_layout(tid, fields, false, t, arch),
+        TypeInfo::Union { fields, .. } => compound_layout(tid, fields, true, t, arch),
+        TypeInfo::Alias { tid, .. } | TypeInfo::Res { tid } | TypeInfo::Len { tid, .. } => {
+            layout_of(*tid, t, arch)
+        }
+    }
+}
+
+fn num_size(n: &NumInfo, arch: Arch) -> usize {
+    match n {
+        NumInfo::I8(_) | NumInfo::U8(_) => 1,
+        NumInfo::I16(_) | NumInfo::U16(_) => 2,
+        NumInfo::I32(_) | NumInfo::U32(_) => 4,
+        NumInfo::I64(_) | NumInfo::U64(_) => 8,
+        NumInfo::Usize(_) | NumInfo::Isize(_) => arch.ptr_size(),
+    }
+}
+
+fn compound_layout(tid: TypeId, fields: &[Field], union: bool, t: &Target, arch: Arch) -> Layout {
+    let info = t.type_of(tid);
+    let packed = info.get_attr("packed").is_some();
+    let mut align = 1;
+    let mut size = 0;
+    let mut offsets = Vec::with_capacity(fields.len());
+    for field in fields.iter() {
+        let l = layout_of(field.tid, t, arch);
+        let field_align = if packed { 1 } else { l.align };
+        align = align.max(field_align);
+        if union {
+            offsets.push(0);
+            size = size.max(l.size);
+        } else {
+            let offset = align_up(size, field_align);
+            offsets.push(offset);
+            size = offset + l.size;
+        }
+    }
+    if let Some(n) = info
+        .get_attr("align")
+        .and_then(|a| a.vals.as_ref()?.first())
+    {
+        // Checked by parser.
+        align = align.max(fots::num::parse::<usize>(n).unwrap());
+    }
+    Layout {
+        size: align_up(size, align),
+        align,
+        offsets,
+    }
+}
+
+fn align_up(n: usize, align: usize) -> usize {
+    n.div_ceil(align) * align
+}
+
+/// Size of `val` of type `tid` in bytes, for `bytesize` and `bitsize`.
+///
+/// Unlike `layout_of`, slices and strings are sized by their value: elements
+/// of slice times size of element, chars of `str`, chars of `cstr` and
+/// `filename` with the terminating NUL. Pointer is sized by its pointee.
+pub fn size_of_val(tid: TypeId, val: &Value, t: &Target, arch: Arch) -> usize {
+    match (t.type_of(tid), val) {
+        (TypeInfo::Ptr { .. }, Value::None) => 0,
+        (TypeInfo::Ptr { tid, .. }, val) => size_of_val(*tid, val, t, arch),
+        (TypeInfo::Slice { tid, .. }, Value::Group(vals)) => {
+            vals.len() * layout_of(*tid, t, arch).size
+        }
+        (TypeInfo::Str { str_type, .. }, Value::Str(s)) => match str_type {
+            StrType::Str => s.len(),
+            StrType::CStr | StrType::FileName => s.len() + 1,
+        },
+        (TypeInfo::Alias { tid, .. }, val) | (TypeInfo::Res { tid }, val) => {
+            size_of_val(*tid, val, t, arch)
+        }
+        _ => layout_of(tid, t, arch).size,
+    }
+}
+
+/// Length of `val` of type `tid` in `unit`, `None` if it can't be counted.
+pub fn len_of_val(
+    unit: LenUnit,
+    tid: TypeId,
+    val: &Value,
+    t: &Target,
+    arch: Arch,
+) -> Option<usize> {
+    match unit {
+        LenUnit::Count => val.len(),
+        LenUnit::Bytes => Some(size_of_val(tid, val, t, arch)),
+        LenUnit::Bits => Some(size_of_val(tid, val, t, arch) * 8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::target;
+    use crate::value::NumValue;
+
+    #[test]
+    fn layout() {
+        let t = target();
+        let tid = |ident| {
+            t.types
+                .iter()
+                .find(|(_, info)| info.ident() == Some(ident))
+                .map(|(&tid, _)| tid)
+                .unwrap()
+        };
+        let layout = |ident, arch| layout_of(tid(ident), &t, arch);
+
+        let hdr = layout("foo_hdr", Arch::Amd64);
+        assert_eq!((hdr.size, hdr.align), (24, 8));
+        assert_eq!(hdr.offsets, vec![0, 8, 16]);
+        let hdr = layout("foo_hdr", Arch::I386);
+        assert_eq!((hdr.size, hdr.align), (16, 4));
+        assert_eq!(hdr.offsets, vec![0, 4, 12]);
+
+        let packed = layout("foo_packed", Arch::Amd64);
+        assert_eq!((packed.size, packed.align), (11, 1));
+        assert_eq!(packed.offsets, vec![0, 1, 9]);
+        let aligned = layout("foo_aligned", Arch::Amd64);
+        assert_eq!((aligned.size, aligned.align), (32, 32));
+        let val = layout("foo_val", Arch::Amd64);
+        assert_eq!((val.size, val.align), (6, 2));
+
+        let arg = layout("foo_arg", Arch::Amd64);
+        assert_eq!(arg.offsets, vec![0, 24, 32]);
+        assert_eq!((arg.size, arg.align), (40, 8));
+        assert_eq!(layout("foo_arg", Arch::Arm).size, 40);
+        assert_eq!(layout("foo_arg", Arch::I386).size, 28);
+    }
+
+    #[test]
+    fn len_of_buffer() {
+        let t = target();
+        let f = t.fns.values().map(|f| unsafe { f.as_ref() });
+        let f = f.into_iter().find(|f| f.dec_name == "foo_write").unwrap();
+        let params = f.params.as_ref().unwrap();
+        let hdr = Value::Group(vec![Value::Num(NumValue::Unsigned(0)); 3]);
+        let buf = Value::Group(vec![hdr; 2]);
+
+        let len = |i: usize| match t.type_of(params[i].tid) {
+            TypeInfo::Len { unit, .. } => len_of_val(*unit, params[0].tid, &buf, &t, Arch::Amd64),
+            _ => unreachable!(),
+        };
+        assert_eq!(len(1), Some(2));
+        assert_eq!(len(2), Some(48));
+        assert_eq!(len(3), Some(384));
+        assert_eq!(size_of_val(params[0].tid, &Value::None, &t, Arch::Amd64), 0);
+    }
+}
@@ -8,8 +8,11 @@ extern crate lazy_static;
 pub mod analyze;
 pub mod c;
 pub mod gen;
+pub mod layout;
 pub mod minimize;
 pub mod mutate;
 pub mod prog;
 pub mod target;
+#[doc(hidden)]
+pub mod test_util;
 pub mod value;
@@ -26,9 +26,8 @@ pub fn remove(p: &mut Prog, i: usize) -> bool {
         return false;
     }
     // adjust ref arg
-    let last_call = p.len() - 1;
     for (j, call) in p.calls.iter_mut().enumerate().skip(i + 1) {
-        if !calls.contains(&j) && j != last_call {
+        if !calls.contains(&j) {
             for arg in call.args.iter_mut() {
                 for_each_ref_mut(&mut arg.val, |(ref mut cid, _)| {
                     let count = calls
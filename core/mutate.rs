@@ -4,26 +4,47 @@ use crate::prog::Prog;
 use crate::target::Target;
 use fots::types::GroupId;
 use rand::prelude::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+
+/// Names of mutation operators, index of operator is passed to `mutate_by`.
+pub const OPERATORS: [&str; 2] = ["seq_reuse", "merge_seq"];
 
 #[allow(clippy::type_complexity)]
-const MUTATE_METHOD: [fn(&Prog, &Target, &RTable, &HashSet<Prog>, &Config) -> Prog; 2] =
+const MUTATE_METHOD: [fn(&Prog, &Target, &RTable, &[Prog], &Config) -> Prog; 2] =
     [seq_reuse, merge_seq /*remove_call*/];
 
-pub fn mutate(
-    corpus: &HashSet<Prog>,
+pub fn mutate(corpus: &[Prog], t: &Target, rt: &HashMap<GroupId, RTable>, conf: &Config) -> Prog {
+    let p = corpus.iter().choose(&mut thread_rng()).unwrap();
+    mutate_seed(p, corpus, t, rt, conf)
+}
+
+/// Mutate `p` chosen by caller with a random operator, `corpus` provides
+/// progs to merge with.
+pub fn mutate_seed(
+    p: &Prog,
+    corpus: &[Prog],
+    t: &Target,
+    rt: &HashMap<GroupId, RTable>,
+    conf: &Config,
+) -> Prog {
+    let op = thread_rng().gen_range(0, OPERATORS.len());
+    mutate_by(op, p, corpus, t, rt, conf)
+}
+
+/// Mutate `p` with operator `OPERATORS[op]`.
+pub fn mutate_by(
+    op: usize,
+    p: &Prog,
+    corpus: &[Prog],
     t: &Target,
     rt: &HashMap<GroupId, RTable>,
     conf: &Config,
 ) -> Prog {
-    let mut rng = thread_rng();
-    let p = corpus.iter().choose(&mut rng).unwrap();
     let rt = &rt[&p.gid];
-    let method = MUTATE_METHOD.choose(&mut rng).unwrap();
-    method(p, t, rt, corpus, conf)
+    MUTATE_METHOD[op](p, t, rt, corpus, conf)
 }
 
-fn seq_reuse(p: &Prog, t: &Target, _rt: &RTable, _corpus: &HashSet<Prog>, conf: &Config) -> Prog {
+fn seq_reuse(p: &Prog, t: &Target, _rt: &RTable, _corpus: &[Prog], conf: &Config) -> Prog {
     let seq = extract_seq(p, t);
     gen_seq(&seq, p.gid, t, conf)
 }
@@ -37,7 +58,7 @@ fn extract_seq(p: &Prog, t: &Target) -> Vec<usize> {
     seq
 }
 
-fn merge_seq(p0: &Prog, t: &Target, _rt: &RTable, corpus: &HashSet<Prog>, conf: &Config) -> Prog {
+fn merge_seq(p0: &Prog, t: &Target, _rt: &RTable, corpus: &[Prog], conf: &Config) -> Prog {
     let mut rng = thread_rng();
     let merge_point = rng.gen_range(0, p0.len());
     let mut s0 = extract_seq(p0, t);
@@ -56,7 +77,7 @@ fn merge_seq(p0: &Prog, t: &Target, _rt: &RTable, corpus: &HashSet<Prog>, conf:
 //     todo!()
 // }
 
-// fn remove_call(p: &Prog, t: &Target, rt: &RTable, corpus: &HashSet<Prog>, conf: &Config) -> Prog {
+// fn remove_call(p: &Prog, t: &Target, rt: &RTable, corpus: &[Prog], conf: &Config) -> Prog {
 //     let mut rng = thread_rng();
 //     if p.len() <= 3 {
 //         let (_, methods) = MUTATE_METHOD.split_last().unwrap();
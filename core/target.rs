@@ -1,6 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use fots::types::{Field, FnId, FnInfo, Group, GroupId, Items, NumInfo, TypeId, TypeInfo};
+use fots::types::{Field, FnId, FnInfo, Group, GroupId, Items, LenUnit, NumInfo, TypeId, TypeInfo};
 use std::ptr::NonNull;
 
 pub struct Target {
@@ -11,14 +11,40 @@ pub struct Target {
 
 impl Target {
     pub fn from(items: Items) -> Self {
-        let mut types = items
+        let types = items
             .types
             .into_iter()
             .map(|t| (t.tid, t.info))
             .collect::<HashMap<_, _>>();
+        Self::build(types, items.groups)
+    }
+
+    /// Target of FOTS source `text`, panics if it's invalid, for tests.
+    #[doc(hidden)]
+    pub fn from_fots(text: &str) -> Self {
+        Self::from(fots::parse_items(text).unwrap())
+    }
+
+    /// Same target without interfaces in `disabled`, groups left empty are dropped.
+    pub fn without(&self, disabled: &HashSet<FnId>) -> Self {
+        let groups = self.groups.values().filter_map(|g| {
+            let mut g = g.clone();
+            g.fns.retain(|f| !disabled.contains(&f.id));
+            if g.fns.is_empty() {
+                None
+            } else {
+                Some(g)
+            }
+        });
+        Self::build(self.types.clone(), groups)
+    }
+
+    fn build(
+        mut types: HashMap<TypeId, TypeInfo>,
+        groups: impl IntoIterator<Item = Group>,
+    ) -> Self {
         types.shrink_to_fit();
-        let mut groups = items
-            .groups
+        let mut groups = groups
             .into_iter()
             .map(|g| (g.id, g))
             .collect::<HashMap<_, _>>();
@@ -76,10 +102,10 @@ impl Target {
         }
     }
 
-    pub fn len_info_of(&self, tid: TypeId) -> Option<&str> {
+    pub fn len_info_of(&self, tid: TypeId) -> Option<(&str, LenUnit)> {
         match self.type_of(tid) {
             TypeInfo::Alias { tid, .. } => self.len_info_of(*tid),
-            TypeInfo::Len { path, .. } => Some(path),
+            TypeInfo::Len { path, unit, .. } => Some((path, *unit)),
             _ => None,
         }
     }
@@ -94,14 +120,14 @@ impl Target {
 
     pub fn struct_info_of(&self, tid: TypeId) -> Option<(&str, &[Field])> {
         match self.type_of(tid) {
-            TypeInfo::Struct { fields, ident } => Some((ident, fields)),
+            TypeInfo::Struct { fields, ident, .. } => Some((ident, fields)),
             TypeInfo::Alias { tid, .. } => self.struct_info_of(*tid),
             _ => None,
         }
     }
 
     pub fn get_len_path_unchecked(&self, tid: TypeId) -> &str {
-        self.len_info_of(tid).unwrap()
+        self.len_info_of(tid).unwrap().0
     }
 
     pub fn group_name_of(&self, gid: GroupId) -> &str {
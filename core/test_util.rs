@@ -0,0 +1,14 @@
+//! Fixture shared by tests of all crates, see `testdata/foo.fots`.
+//!
+//! Group `Foo` chains calls with resources `fd` and `conn` and passes structs
+//! with layout attributes, `Node` is `#[defs]` with recursive structs and
+//! `Bar` declares calls of the same resource and types in another group.
+
+use crate::target::Target;
+
+pub const FOTS: &str = include_str!("testdata/foo.fots");
+
+/// Target of `FOTS`.
+pub fn target() -> Target {
+    Target::from_fots(FOTS)
+}
@@ -0,0 +1,5 @@
+fn main() {
+    // Libraries instrumented with SanitizerCoverage resolve their callbacks
+    // from executor, see `sancov.rs`.
+    println!("cargo:rustc-link-arg-bins=-Wl,--export-dynamic");
+}
@@ -25,7 +25,7 @@ use std::time::Duration;
 
 pub fn fork_exec(p: Prog, t: &Target, conf: &Config) -> ExecResult {
     if conf.concurrency || random::<f64>() < 0.0025 {
-        bg_run(&p, t);
+        bg_run(&p, t, conf);
     }
     // transfer usefull data
     let (mut rp, mut wp) = os_pipe::pipe()
@@ -73,7 +73,7 @@ pub fn fork_exec(p: Prog, t: &Target, conf: &Config) -> ExecResult {
             #[cfg(feature = "kcov")]
             sync_exec(&p, t, &mut wp, waiter, conf);
             #[cfg(not(feature = "kcov"))]
-            sync_exec(&p, t);
+            sync_exec(&p, t, conf);
             // subprocess exits here
             exit(exitcode::OK)
         }
@@ -95,7 +95,7 @@ pub fn fork_exec(p: Prog, t: &Target, conf: &Config) -> ExecResult {
     }
 }
 
-fn bg_run(p: &Prog, t: &Target) {
+fn bg_run(p: &Prog, t: &Target, conf: &Config) {
     match fork() {
         Ok(ForkResult::Child) => match fork() {
             Ok(ForkResult::Child) => {
@@ -108,7 +108,7 @@ fn bg_run(p: &Prog, t: &Target) {
                         Ok(ForkResult::Parent { child }) => {
                             childs.insert(child);
                         }
-                        Ok(ForkResult::Child) => bg_fork_run(p, t),
+                        Ok(ForkResult::Child) => bg_fork_run(p, t, conf),
                         Err(_) => break,
                     }
                 }
@@ -144,7 +144,7 @@ fn bg_run(p: &Prog, t: &Target) {
     }
 }
 
-fn bg_fork_run(p: &Prog, t: &Target) {
+fn bg_fork_run(p: &Prog, t: &Target, conf: &Config) {
     #[cfg(feature = "jit")]
     use jit::bg_exec;
     #[cfg(feature = "syscall")]
@@ -174,7 +174,7 @@ fn bg_fork_run(p: &Prog, t: &Target) {
         }
         Ok(ForkResult::Child) => {
             for _ in 0..4 {
-                bg_exec(p, t);
+                bg_exec(p, t, conf);
             }
             exit(0)
         }
@@ -383,15 +383,15 @@ pub fn sync_exec(
     use jit::exec;
     #[cfg(feature = "syscall")]
     use syscall::exec;
-    exec(p, t, out, waiter);
+    exec(p, t, out, waiter, conf);
 }
 
 #[cfg(not(feature = "kcov"))]
-fn sync_exec(p: &Prog, t: &Target) {
+fn sync_exec(p: &Prog, t: &Target, conf: &Config) {
     #[cfg(feature = "jit")]
     use jit::exec;
     #[cfg(feature = "syscall")]
     use syscall::exec;
 
-    exec(p, t);
+    exec(p, t, conf);
 }
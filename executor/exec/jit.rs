@@ -1,35 +1,48 @@
+use crate::sancov;
 use crate::utils::Waiter;
+use crate::Config;
 use core::c;
 use core::c::cths::CTHS;
 use core::c::iter_trans;
 use core::prog::Prog;
 use core::target::Target;
+use fots::types::FnInfo;
+use gag::Gag;
+use nix::errno::Errno;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{fork, ForkResult};
 use os_pipe::PipeWriter;
-use std::ffi::CString;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
 use std::fmt::Write;
 use std::fs::{create_dir_all, write};
-use std::io::ErrorKind;
 use std::os::raw::c_int;
 use std::os::unix::io::*;
 use std::path::PathBuf;
 use std::process::exit;
+use std::sync::OnceLock;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 use tcc::{Context, Guard};
 
 #[cfg(feature = "kcov")]
-pub fn exec(p: &Prog, t: &Target, out: &mut PipeWriter, waiter: Waiter) {
-    prepare_env();
+pub fn exec(p: &Prog, t: &Target, out: &mut PipeWriter, waiter: Waiter, conf: &Config) {
+    prepare_env().unwrap_or_else(|e| exits!(exitcode::CANTCREAT, "{}", e));
+    let libs = c::group_libs(p, t);
     let p = {
-        instrument_prog(p, t, out.as_raw_fd(), waiter.as_raw_fd()).unwrap_or_else(|e| {
-            eprintln!("{}", e);
-            exit(exitcode::SOFTWARE);
-        })
+        instrument_prog(p, t, out.as_raw_fd(), waiter.as_raw_fd(), conf.sancov).unwrap_or_else(
+            |e| {
+                eprintln!("{}", e);
+                exit(exitcode::SOFTWARE);
+            },
+        )
     };
 
     let p = CString::new(p.as_bytes()).unwrap();
     let sym = CString::new("execute").unwrap();
 
     let mut g = Guard::new().unwrap();
-    let mut cc = new_tcc(&mut g);
+    let mut cc = new_tcc(&mut g, &libs, conf);
     cc.compile_string(&p).unwrap_or_else(|_| {
         exits!(
             exitcode::SOFTWARE,
@@ -56,14 +69,15 @@ pub fn exec(p: &Prog, t: &Target, out: &mut PipeWriter, waiter: Waiter) {
 }
 
 #[cfg(not(feature = "kcov"))]
-pub fn exec(p: &Prog, t: &Target) {
-    prepare_env();
+pub fn exec(p: &Prog, t: &Target, conf: &Config) {
+    prepare_env().unwrap_or_else(|e| exits!(exitcode::CANTCREAT, "{}", e));
+    let libs = c::group_libs(p, t);
     let p = c::to_prog(p, t);
     let p = CString::new(p.as_bytes()).unwrap();
     let sym = CString::new("main").unwrap();
 
     let mut g = Guard::new().unwrap();
-    let mut cc = new_tcc(&mut g);
+    let mut cc = new_tcc(&mut g, &libs, conf);
     cc.compile_string(&p).unwrap_or_else(|_| {
         exits!(
             exitcode::SOFTWARE,
@@ -81,13 +95,14 @@ pub fn exec(p: &Prog, t: &Target) {
     execute();
 }
 
-pub fn bg_exec(p: &Prog, t: &Target) {
+pub fn bg_exec(p: &Prog, t: &Target, conf: &Config) {
+    let libs = c::group_libs(p, t);
     let p = c::to_prog(p, t);
     if !p.is_empty() {
         let p = CString::new(p.as_bytes()).unwrap();
         let sym = CString::new("main").unwrap();
         let mut g = Guard::new().unwrap();
-        let mut cc = new_tcc(&mut g);
+        let mut cc = new_tcc(&mut g, &libs, conf);
         cc.compile_string(&p).unwrap();
         let mut p = cc
             .relocate()
@@ -100,11 +115,141 @@ pub fn bg_exec(p: &Prog, t: &Target) {
     }
 }
 
+/// Time a test call of `check` may take.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Check whether `f` is available in guest.
+///
+/// A stub taking address of the call is compiled and linked, which fails
+/// if header, wrapper or library is missing. Then the call is made with
+/// zero args in a child, ENOSYS means kernel doesn't support it. The test
+/// call is skipped with sancov, user library doesn't return ENOSYS.
+pub fn check(f: &FnInfo, t: &Target, conf: &Config) -> Result<(), String> {
+    prepare_env()?;
+    let p = Prog::new(f.gid);
+    let libs = c::group_libs(&p, t);
+    let mut includes = hashset! {"errno.h".to_string()};
+    if let Some(h) = CTHS.get(&f.call_name as &str) {
+        includes.extend(h.iter().map(|h| (*h).to_string()));
+    }
+    if let Some(vals) = f.get_attr("inc").and_then(|attr| attr.vals.as_ref()) {
+        includes.extend(vals.iter().cloned());
+    }
+    includes.extend(c::group_includes(&p, t));
+
+    let mut stub = String::new();
+    writeln!(stub, "#define _GNU_SOURCE").unwrap();
+    for header in includes.into_iter() {
+        writeln!(stub, "#include<{}>", header).unwrap();
+    }
+    write!(
+        stub,
+        r#"
+typedef long (*healer_probe_t)(long, long, long, long, long, long);
+int healer_probe(){{
+    healer_probe_t f = (healer_probe_t)&{};
+    errno = 0;
+    f(0, 0, 0, 0, 0, 0);
+    return errno;
+}}"#,
+        f.call_name
+    )
+    .unwrap();
+    let stub = CString::new(stub.as_bytes()).unwrap();
+    let sym = CString::new("healer_probe").unwrap();
+
+    let err = RefCell::new(String::new());
+    let mut g = Guard::new().unwrap();
+    let mut cc = base_tcc(&mut g, conf);
+    cc.set_call_back(|e| collect_err(&err, e));
+    for lib in libs.iter() {
+        let name = CString::new(lib.as_bytes()).unwrap();
+        cc.add_library(&name)
+            .map_err(|_| format!("library {} not found", lib))?;
+    }
+    cc.compile_string(&stub)
+        .map_err(|_| format!("fail to compile: {}", err.borrow()))?;
+    let mut p = cc
+        .relocate()
+        .map_err(|_| format!("fail to link: {}", err.borrow()))?;
+    if conf.sancov {
+        return Ok(());
+    }
+    let probe: fn() -> c_int = unsafe {
+        let symbol = p.get_symbol(&sym).unwrap();
+        std::mem::transmute(symbol)
+    };
+
+    match fork() {
+        Ok(ForkResult::Child) => {
+            let _stdout = Gag::stdout().unwrap();
+            let _stderr = Gag::stderr().unwrap();
+            exit(probe())
+        }
+        Ok(ForkResult::Parent { child }) => {
+            let start = Instant::now();
+            while start.elapsed() < CHECK_TIMEOUT {
+                match waitpid(child, Some(WaitPidFlag::WNOHANG)) {
+                    Ok(WaitStatus::Exited(_, code)) if code == Errno::ENOSYS as i32 => {
+                        return Err("ENOSYS".to_string())
+                    }
+                    Ok(WaitStatus::StillAlive) => sleep(Duration::from_millis(10)),
+                    _ => return Ok(()),
+                }
+            }
+            // Blocked call, e.g. pause, is there.
+            super::kill_and_wait(child);
+            Ok(())
+        }
+        Err(e) => exits!(exitcode::OSERR, "Fail to fork: {}", e),
+    }
+}
+
+/// Compile translated `p` without executing it, libraries of its group are
+/// linked too if `link`. Errors reported by tcc are returned.
+pub fn compile(p: &Prog, t: &Target, conf: &Config, link: bool) -> Result<(), String> {
+    prepare_env()?;
+    let libs = c::group_libs(p, t);
+    let p = CString::new(c::to_prog(p, t).as_bytes()).unwrap();
+
+    let err = RefCell::new(String::new());
+    let mut g = Guard::new().unwrap();
+    let mut cc = base_tcc(&mut g, conf);
+    cc.set_call_back(|e| collect_err(&err, e));
+    if link {
+        for lib in libs.iter() {
+            let name = CString::new(lib.as_bytes()).unwrap();
+            cc.add_library(&name)
+                .map_err(|_| format!("library {} not found", lib))?;
+        }
+    }
+    cc.compile_string(&p)
+        .map_err(|_| format!("fail to compile: {}", err.borrow()))?;
+    if link {
+        cc.relocate()
+            .map_err(|_| format!("fail to link: {}", err.borrow()))?;
+    }
+    Ok(())
+}
+
+/// Append error reported by tcc to `err`, warnings are ignored.
+fn collect_err(err: &RefCell<String>, e: &CStr) {
+    let e = e.to_string_lossy();
+    if e.contains("error") {
+        let mut err = err.borrow_mut();
+        if !err.is_empty() {
+            err.push('\n');
+        }
+        err.push_str(e.trim());
+    }
+}
+
 pub fn instrument_prog(
     p: &Prog,
     t: &Target,
     data_fd: RawFd,
     sync_fd: RawFd,
+    sancov: bool,
 ) -> Result<String, String> {
     let mut includes = hashset! {
         "stdio.h".to_string(),
@@ -166,8 +311,19 @@ int sync_send(unsigned long *cover, uint32_t len){{
         sync_fd, data_fd
     );
 
-    let kcov_open = format!(
-        r#"
+    // With sancov, cover is filled by callbacks of instrumented library in
+    // executor, see `sancov.rs`, so there is nothing to open or enable.
+    let cover_open = if sancov {
+        format!(
+            r#"
+    unsigned long *cover = {};
+    uint32_t len = 0;
+    "#,
+            sancov::COVER_SYMBOL
+        )
+    } else {
+        format!(
+            r#"
     int fd;
     unsigned long *cover;
     uint32_t len = 0;
@@ -182,10 +338,27 @@ int sync_send(unsigned long *cover, uint32_t len){{
     if ((void*)cover == MAP_FAILED)
             return {};
     "#,
-        StatusCode::KcovOpenErr as i32,
-        StatusCode::KcovInitErr as i32,
-        StatusCode::MmapErr as i32
-    );
+            StatusCode::KcovOpenErr as i32,
+            StatusCode::KcovInitErr as i32,
+            StatusCode::MmapErr as i32
+        )
+    };
+    let (cover_enable, cover_disable) = if sancov {
+        (String::new(), String::new())
+    } else {
+        (
+            format!(
+                r#"if (ioctl(fd, KCOV_ENABLE, KCOV_TRACE_PC))
+            return {};"#,
+                StatusCode::KcovEnableErr as i32
+            ),
+            format!(
+                r#"if (ioctl(fd, KCOV_DISABLE, 0))
+            return {};"#,
+                StatusCode::KcovDisableErr as i32
+            ),
+        )
+    };
 
     let mut stmts = Vec::new();
     for (i, s) in iter_trans(p, t).enumerate() {
@@ -209,39 +382,42 @@ int sync_send(unsigned long *cover, uint32_t len){{
         let generated_call = s.to_string();
         let s = format!(
             r#"
-    if (ioctl(fd, KCOV_ENABLE, KCOV_TRACE_PC))
-            return {};
+    {}
     cover[0] = 0;
     {}
     len = cover[0];
-    if (ioctl(fd, KCOV_DISABLE, 0))
-            return {};
+    {}
     if (sync_send(cover, len) == -1)
         return {};"#,
-            StatusCode::KcovEnableErr as i32,
+            cover_enable,
             generated_call,
-            StatusCode::KcovDisableErr as i32,
+            cover_disable,
             StatusCode::CovSendErr as i32
         );
         stmts.push(s);
     }
+    includes.extend(c::group_includes(p, t));
 
-    let clean = format!(
-        r#"
+    let clean = if sancov {
+        format!("    return {};", StatusCode::Ok as i32)
+    } else {
+        format!(
+            r#"
     if (munmap(cover, COVER_SIZE * sizeof(unsigned long)))
             return {};
     if (close(fd))
             return {};
     return {};
     "#,
-        StatusCode::MmapErr as i32,
-        StatusCode::KcovCloseErr as i32,
-        StatusCode::Ok as i32
-    );
+            StatusCode::MmapErr as i32,
+            StatusCode::KcovCloseErr as i32,
+            StatusCode::Ok as i32
+        )
+    };
 
     let execute = {
         let mut buf = String::new();
-        writeln!(buf, "{}", kcov_open).unwrap();
+        writeln!(buf, "{}", cover_open).unwrap();
         for s in stmts {
             writeln!(buf, "{}", s).unwrap();
         }
@@ -255,7 +431,11 @@ int sync_send(unsigned long *cover, uint32_t len){{
     for header in includes.into_iter() {
         writeln!(buf, "#include<{}>", header).unwrap();
     }
+    write!(buf, "{}", c::type_defs(p, t)).unwrap();
     writeln!(buf, "{}", macros).unwrap();
+    if sancov {
+        writeln!(buf, "extern unsigned long {}[];", sancov::COVER_SYMBOL).unwrap();
+    }
     writeln!(buf, "{}", sync_send).unwrap();
     writeln!(buf, "{}", execute).unwrap();
     Ok(buf)
@@ -293,7 +473,18 @@ impl From<i32> for StatusCode {
 
 const TCC_INCLUDE: &str = "/usr/local/include/healer/tcc";
 
-fn new_tcc<'a, 'b>(g: &'a mut Guard) -> Context<'a, 'b> {
+fn new_tcc<'a, 'b>(g: &'a mut Guard, libs: &[String], conf: &Config) -> Context<'a, 'b> {
+    let mut cc = base_tcc(g, conf);
+    for lib in libs {
+        let name = CString::new(lib.as_bytes()).unwrap();
+        cc.add_library(&name)
+            .unwrap_or_else(|_| exits!(exitcode::SOFTWARE, "Fail to add library: {}", lib));
+    }
+    cc
+}
+
+/// Context without libraries of target.
+fn base_tcc<'a, 'b>(g: &'a mut Guard, conf: &Config) -> Context<'a, 'b> {
     let mut cc = tcc::Context::new(g).unwrap();
     cc.add_sys_include_path(TCC_INCLUDE);
     if cfg!(target_os = "linux") {
@@ -302,7 +493,16 @@ fn new_tcc<'a, 'b>(g: &'a mut Guard) -> Context<'a, 'b> {
         cc.add_library_path("/usr/lib");
         cc.add_library_path("/usr/local/lib");
     }
+    for path in conf.lib_paths.iter() {
+        cc.add_library_path(path);
+    }
     cc.set_output_type(tcc::OutputType::Memory);
+    if conf.sancov {
+        let sym = CString::new(sancov::COVER_SYMBOL).unwrap();
+        unsafe {
+            cc.add_symbol(&sym, sancov::cover_area());
+        }
+    }
     // ignore wraning
     cc.set_call_back(|e| {
         let e = e.to_str().unwrap();
@@ -313,23 +513,82 @@ fn new_tcc<'a, 'b>(g: &'a mut Guard) -> Context<'a, 'b> {
     cc
 }
 
-fn prepare_env() {
-    let float_h = include_str!("../tcc-0.9.27/include/float.h");
-    let stdarg_h = include_str!("../tcc-0.9.27/include/stdarg.h");
-    let stdbool_h = include_str!("../tcc-0.9.27/include/stdbool.h");
-    let stddef_h = include_str!("../tcc-0.9.27/include/stddef.h");
-    let varargs_h = include_str!("../tcc-0.9.27/include/varargs.h");
+/// Write headers of tcc to `TCC_INCLUDE`, only once for a process.
+fn prepare_env() -> Result<(), String> {
+    static ENV: OnceLock<Result<(), String>> = OnceLock::new();
+    ENV.get_or_init(write_headers).clone()
+}
+
+fn write_headers() -> Result<(), String> {
+    let headers = [
+        ("float.h", include_str!("../tcc-0.9.27/include/float.h")),
+        ("stdarg.h", include_str!("../tcc-0.9.27/include/stdarg.h")),
+        ("stdbool.h", include_str!("../tcc-0.9.27/include/stdbool.h")),
+        ("stddef.h", include_str!("../tcc-0.9.27/include/stddef.h")),
+        ("varargs.h", include_str!("../tcc-0.9.27/include/varargs.h")),
+    ];
     let tcc_include = PathBuf::from(TCC_INCLUDE);
-    if let Err(e) = create_dir_all(&tcc_include) {
-        if ErrorKind::AlreadyExists != e.kind() {
-            panic!("Fail to create: {} :{}", tcc_include.display(), e);
-        } else {
-            return;
+    create_dir_all(&tcc_include)
+        .map_err(|e| format!("Fail to create {}: {}", tcc_include.display(), e))?;
+    for (name, header) in headers.iter() {
+        let path = tcc_include.join(name);
+        write(&path, header).map_err(|e| format!("Fail to write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::gen::gen_seq;
+    use core::test_util::target;
+
+    fn conf() -> Config {
+        Config {
+            memleak_check: false,
+            concurrency: false,
+            sancov: false,
+            lib_paths: Vec::new(),
+            compress: false,
         }
     }
-    write(tcc_include.join("float.h"), float_h).unwrap();
-    write(tcc_include.join("stdarg.h"), stdarg_h).unwrap();
-    write(tcc_include.join("stdbool.h"), stdbool_h).unwrap();
-    write(tcc_include.join("stddef.h"), stddef_h).unwrap();
-    write(tcc_include.join("varargs.h"), varargs_h).unwrap();
+
+    #[test]
+    fn compile_prog() {
+        let t = target();
+        let conf = conf();
+        let g = t.groups.values().find(|g| g.ident == "Foo").unwrap();
+        let seq = [
+            g.index_by_name("foo_open").unwrap(),
+            g.index_by_name("foo_connect").unwrap(),
+            g.index_by_name("foo_close").unwrap(),
+        ];
+        let p = gen_seq(&seq, g.id, &t, &Default::default());
+        assert_eq!(compile(&p, &t, &conf, false), Ok(()));
+        // Without library of group, calls are left undefined.
+        assert!(compile(&p, &t, &conf, true)
+            .unwrap_err()
+            .starts_with("fail to link:"));
+
+        // No header defines structs of `Foo`.
+        let seq = [g.index_by_name("foo_write").unwrap()];
+        let p = gen_seq(&seq, g.id, &t, &Default::default());
+        let err = compile(&p, &t, &conf, false).unwrap_err();
+        assert!(err.starts_with("fail to compile:"), "{}", err);
+        assert!(err.contains("error"), "{}", err);
+    }
+
+    #[test]
+    fn check_call() {
+        let t = target();
+        let conf = conf();
+        let g = t.groups.values().find(|g| g.ident == "Foo").unwrap();
+        let mut f = g.fns[g.index_by_name("foo_sync").unwrap()].clone();
+        f.call_name = "getpid".to_string();
+        assert_eq!(check(&f, &t, &conf), Ok(()));
+
+        f.call_name = "healer_nonexistent".to_string();
+        let err = check(&f, &t, &conf).unwrap_err();
+        assert!(err.starts_with("fail to compile:"), "{}", err);
+    }
 }
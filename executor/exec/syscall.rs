@@ -1,16 +1,24 @@
 use crate::utils::Waiter;
+use crate::Config;
 use core::prog::Prog;
 use core::target::Target;
+use fots::types::FnInfo;
 use os_pipe::PipeWriter;
 
 #[cfg(feature = "kcov")]
-pub fn exec(_p: &Prog, _t: &Target, _out: &mut PipeWriter, _waiter: Waiter) {
+pub fn exec(_p: &Prog, _t: &Target, _out: &mut PipeWriter, _waiter: Waiter, _conf: &Config) {
     todo!()
 }
 
 #[cfg(not(feature = "kcov"))]
-pub fn exec(_p: &Prog, _t: &Target) {}
+pub fn exec(_p: &Prog, _t: &Target, _conf: &Config) {}
 
-pub fn bg_exec(_p: &Prog, _t: &Target) {
+pub fn bg_exec(_p: &Prog, _t: &Target, _conf: &Config) {
     todo!()
 }
+
+/// Calls are described by name only, there is no number to test with
+/// syscall(2), so every call is taken as available.
+pub fn check(_f: &FnInfo, _t: &Target, _conf: &Config) -> Result<(), String> {
+    Ok(())
+}
@@ -1,19 +1,23 @@
 use core::target::Target;
+use executor::probe::probe;
+use executor::transfer::{handshake, send, target_hash, Handshake};
+use executor::transport::{Conn, Transport, SERIAL_PORT_NAME};
 use executor::{exec_loop, Config};
 use fots::types::Items;
 use std::fs::{read, write};
-use std::net::TcpStream;
+use std::path::PathBuf;
 use std::process::exit;
-use std::thread::sleep;
-use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "healer-executor")]
 pub struct Settings {
-    /// Address of healer-fuzzer
+    /// Address of healer-fuzzer, ip:port for tcp, path for unix and virtio-serial
     #[structopt(short = "a", long)]
-    addr: String,
+    addr: Option<String>,
+    /// Channel to healer-fuzzer: tcp, stdio, unix or virtio-serial
+    #[structopt(short = "T", long, default_value = "tcp")]
+    transport: Transport,
     /// Path of fots file
     #[structopt(short = "t", long)]
     target: String,
@@ -23,6 +27,22 @@ pub struct Settings {
 
     #[structopt(short = "m", long = "memleak-check")]
     memleak_check: bool,
+
+    /// Collect coverage from SanitizerCoverage instead of kcov
+    #[structopt(short = "s", long)]
+    sancov: bool,
+
+    /// Path to search libraries of user-space target
+    #[structopt(short = "L", long = "lib-path")]
+    lib_paths: Vec<PathBuf>,
+
+    /// Token given by healer-fuzzer for handshake
+    #[structopt(short = "k", long, default_value = "0")]
+    token: u64,
+
+    /// Compress large results before sending
+    #[structopt(short = "z", long)]
+    compress: bool,
 }
 
 fn main() {
@@ -32,6 +52,7 @@ fn main() {
         eprintln!("Fail to read target:{}", e);
         exit(exitcode::NOINPUT);
     });
+    let hash = target_hash(&items);
     let items: Items = bincode::deserialize(&items).unwrap_or_else(|e| {
         eprintln!("Fail to deserialize given target {}:{}", settings.target, e);
         exit(exitcode::DATAERR);
@@ -42,26 +63,41 @@ fn main() {
         write("/sys/kernel/debug/kmemleak", "clear").unwrap();
     }
 
-    let mut retry = 1;
-    let conn = loop {
-        match TcpStream::connect(&settings.addr) {
-            Ok(c) => break c,
-            Err(e) => {
-                if retry == 5 {
-                    eprintln!("Fail to connect to healer-fuzzer:{}", e);
-                    exit(exitcode::NOHOST);
-                }
-                retry += 1;
-                sleep(Duration::from_millis(100));
-                continue;
-            }
+    let addr = match (settings.transport, settings.addr) {
+        (_, Some(addr)) => addr,
+        (Transport::VirtioSerial, None) => format!("/dev/virtio-ports/{}", SERIAL_PORT_NAME),
+        (Transport::Stdio, None) => String::new(),
+        (t, None) => {
+            eprintln!("Address of healer-fuzzer is required for {} transport", t);
+            exit(exitcode::USAGE);
         }
     };
+    let mut conn = Conn::connect(settings.transport, &addr).unwrap_or_else(|e| {
+        eprintln!("Fail to connect to healer-fuzzer:{}", e);
+        exit(exitcode::NOHOST);
+    });
+
+    let hs = Handshake::new(hash, settings.token);
+    let ack = handshake(&mut conn, &hs).unwrap_or_else(|e| {
+        eprintln!("Fail to handshake with healer-fuzzer:{}", e);
+        exit(exitcode::PROTOCOL);
+    });
 
     let conf = Config {
         memleak_check: settings.memleak_check,
         concurrency: settings.concurrency,
+        sancov: settings.sancov,
+        lib_paths: settings.lib_paths,
+        compress: settings.compress,
     };
 
+    if ack.probe {
+        let result = probe(&target, &conf);
+        if let Err(e) = send(&result, &mut conn, conf.compress) {
+            eprintln!("Fail to send probe result:{}", e);
+            exit(exitcode::PROTOCOL);
+        }
+    }
+
     exec_loop(target, conn, conf)
 }
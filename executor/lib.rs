@@ -7,6 +7,7 @@ extern crate maplit;
 
 use core::target::Target;
 use std::io::{Read, Write};
+use std::path::PathBuf;
 
 #[macro_use]
 #[allow(dead_code)]
@@ -14,13 +15,22 @@ mod utils;
 pub mod cover;
 #[allow(unused_imports, unused_mut, dead_code)]
 pub mod exec;
+pub mod probe;
+pub mod sancov;
 pub mod transfer;
+pub mod transport;
 
 pub use exec::{ExecResult, Reason};
 
 pub struct Config {
     pub memleak_check: bool,
     pub concurrency: bool,
+    /// Collect coverage from SanitizerCoverage callbacks instead of kcov.
+    pub sancov: bool,
+    /// Extra paths to search libraries declared with `#[lib(...)]`.
+    pub lib_paths: Vec<PathBuf>,
+    /// Compress large results before sending back.
+    pub compress: bool,
 }
 
 /// Read prog from conn, translate by target, run the translated test program.
@@ -31,7 +41,7 @@ pub fn exec_loop<T: Read + Write>(t: Target, mut conn: T, conf: Config) {
 
         let result = exec::fork_exec(p, &t, &conf);
 
-        transfer::send(&result, &mut conn)
+        transfer::send(&result, &mut conn, conf.compress)
             .unwrap_or_else(|e| exits!(exitcode::SOFTWARE, "Fail to Send {:?}:{}", result, e));
     }
 }
@@ -0,0 +1,32 @@
+//! Availability of calls in guest.
+//!
+//! Calls described in target may be missing in a given guest: disabled by
+//! kernel config, not wrapped by libc or not exported by user library.
+//! Executor probes each of them once when fuzzer asks in handshake, fuzzer
+//! disables the unavailable ones and their dependents.
+
+use crate::Config;
+use core::target::Target;
+use fots::types::FnId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Probe {
+    /// Unavailable calls with reason.
+    pub unavailable: Vec<(FnId, String)>,
+}
+
+/// Check every call of target, see `exec::jit::check`.
+pub fn probe(t: &Target, conf: &Config) -> Probe {
+    #[cfg(feature = "jit")]
+    use crate::exec::jit::check;
+    #[cfg(feature = "syscall")]
+    use crate::exec::syscall::check;
+
+    let unavailable = t
+        .iter_group()
+        .flat_map(|g| g.iter_fn())
+        .filter_map(|f| check(f, t, conf).err().map(|reason| (f.id, reason)))
+        .collect();
+    Probe { unavailable }
+}
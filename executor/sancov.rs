@@ -0,0 +1,127 @@
+//! Runtime of SanitizerCoverage
+//!
+//! User-space libraries built with `-fsanitize-coverage=trace-pc-guard`
+//! call back into executor for every edge they execute. Callbacks record
+//! pc of guard into `COVER`, whose layout is the same as kcov's: first slot
+//! is number of recorded entries, then the entries. Generated prog reads
+//! it through `healer_cover` symbol, see `exec::jit`.
+//!
+//! Loading address of library differs between executions, so pc of guard is
+//! hash of file name of its module in high 32 bits and index of guard in the
+//! module in low bits. It stays the same whatever order libraries are loaded
+//! in, as long as the library is not rebuilt.
+//!
+//! Executor must be linked with `--export-dynamic`, so that libraries
+//! loaded by tcc can resolve these callbacks.
+use nix::libc;
+use std::ffi::CStr;
+use std::os::raw::c_void;
+use std::path::Path;
+
+/// Max number of entries recorded for one call, the same as kcov.
+pub const COVER_SIZE: usize = 1024 * 1024;
+
+pub const COVER_SYMBOL: &str = "healer_cover";
+
+/// Guard is `slot << MODULE_SHIFT | index`, index starts from 1.
+const MODULE_SHIFT: u32 = 24;
+const MAX_MODULES: usize = 1 << (32 - MODULE_SHIFT);
+const MAX_GUARDS: usize = (1 << MODULE_SHIFT) - 1;
+
+static mut COVER: [usize; COVER_SIZE] = [0; COVER_SIZE];
+/// Base pc of each initialized module.
+static mut MODULES: [usize; MAX_MODULES] = [0; MAX_MODULES];
+static mut MODULE_N: usize = 0;
+
+/// Address of cover buffer, should be added to compiled prog as `COVER_SYMBOL`.
+pub fn cover_area() -> *const c_void {
+    std::ptr::addr_of!(COVER) as *const c_void
+}
+
+/// Base pc of module containing `addr`, slot is used if it's not found.
+unsafe fn module_base(addr: *const c_void, slot: usize) -> usize {
+    let mut info: libc::Dl_info = std::mem::zeroed();
+    let hash = if libc::dladdr(addr, &mut info) != 0 && !info.dli_fname.is_null() {
+        let path = CStr::from_ptr(info.dli_fname).to_string_lossy();
+        let name = Path::new(path.as_ref()).file_name().unwrap_or_default();
+        let digest = md5::compute(name.to_string_lossy().as_bytes()).0;
+        u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]])
+    } else {
+        slot as u32
+    };
+    (hash as usize) << 32
+}
+
+/// Number guards of loaded module, see `MODULE_SHIFT`.
+///
+/// Guards beyond `MAX_GUARDS` or of modules beyond `MAX_MODULES` stay 0 and
+/// are not traced.
+///
+/// # Safety
+/// Called by instrumented module with its own guard section.
+#[no_mangle]
+pub unsafe extern "C" fn __sanitizer_cov_trace_pc_guard_init(start: *mut u32, stop: *mut u32) {
+    if start == stop || *start != 0 || MODULE_N == MAX_MODULES {
+        return;
+    }
+    let slot = MODULE_N;
+    MODULE_N += 1;
+    MODULES[slot] = module_base(start as *const c_void, slot);
+    let n = usize::min(stop.offset_from(start) as usize, MAX_GUARDS);
+    for i in 0..n {
+        *start.add(i) = ((slot << MODULE_SHIFT) | (i + 1)) as u32;
+    }
+}
+
+/// # Safety
+/// Called by instrumented module with guard initialized by
+/// `__sanitizer_cov_trace_pc_guard_init`.
+#[no_mangle]
+pub unsafe extern "C" fn __sanitizer_cov_trace_pc_guard(guard: *mut u32) {
+    let g = *guard as usize;
+    if g == 0 {
+        return;
+    }
+    let len = COVER[0];
+    if len + 1 < COVER_SIZE {
+        COVER[len + 1] = MODULES[g >> MODULE_SHIFT] | (g & MAX_GUARDS);
+        COVER[0] = len + 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static mut GUARDS: [u32; 4] = [0; 4];
+
+    #[test]
+    fn trace_guards() {
+        unsafe {
+            let guards = std::ptr::addr_of_mut!(GUARDS) as *mut u32;
+            __sanitizer_cov_trace_pc_guard_init(guards, guards.add(4));
+            let slot = *guards as usize >> MODULE_SHIFT;
+            for i in 0..4 {
+                assert_eq!(*guards.add(i) as usize, (slot << MODULE_SHIFT) | (i + 1));
+            }
+            // Already initialized module keeps its guards.
+            __sanitizer_cov_trace_pc_guard_init(guards, guards.add(4));
+            assert_eq!(*guards as usize >> MODULE_SHIFT, slot);
+
+            // Base comes from file name of this test binary.
+            let exe = std::env::current_exe().unwrap();
+            let digest = md5::compute(exe.file_name().unwrap().to_string_lossy().as_bytes()).0;
+            let base =
+                (u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]]) as usize) << 32;
+            assert_eq!(MODULES[slot], base);
+
+            let mut off = 0;
+            COVER[0] = 0;
+            __sanitizer_cov_trace_pc_guard(guards);
+            __sanitizer_cov_trace_pc_guard(guards.add(2));
+            __sanitizer_cov_trace_pc_guard(&mut off);
+            let cover = &*std::ptr::addr_of!(COVER);
+            assert_eq!(cover[..3], [2, base | 1, base | 3]);
+        }
+    }
+}
@@ -1,55 +1,162 @@
 //! A implementation of very sample object transfer protocal.
+//!
+//! Every object is sent as a frame: bincode encoded `Header` followed by body
+//! of `Header::len` bytes. Body is bincode encoded object, deflated if
+//! `FLAG_COMPRESSED` is set.
+//!
+//! Before any frame, executor sends a `Handshake` carrying protocol version,
+//! hash of the loaded target and token given by fuzzer on command line.
+//! Fuzzer checks it and replies with a `HandshakeAck`, peers that fail the
+//! check are dropped. If the ack asks for probing, executor sends a
+//! `probe::Probe` before receiving any prog.
 
 use crate::ExecResult;
 use bytes::BytesMut;
 use core::prog::Prog;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::io;
 use std::io::{Read, Write};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+/// "HELR"
+pub const MAGIC: u32 = 0x4845_4c52;
+pub const VERSION: u16 = 2;
+/// Frames larger than this are treated as broken stream.
+pub const MAX_BODY_LEN: u32 = 64 * 1024 * 1024;
+/// Body smaller than this is not worth compressing.
+pub const COMPRESS_THRESHOLD: usize = 4 * 1024;
+
+pub const FLAG_COMPRESSED: u8 = 1;
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Header {
+    pub magic: u32,
+    pub flags: u8,
     pub len: u32,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Handshake {
+    pub magic: u32,
+    pub version: u16,
+    /// md5 of compiled fots file, see `target_hash`.
+    pub target_hash: [u8; 16],
+    pub token: u64,
+}
+
+impl Handshake {
+    pub fn new(target_hash: [u8; 16], token: u64) -> Self {
+        Self {
+            magic: MAGIC,
+            version: VERSION,
+            target_hash,
+            token,
+        }
+    }
+
+    /// Check handshake sent by peer.
+    pub fn verify(&self, target_hash: &[u8; 16], token: u64) -> Result<(), Error> {
+        if self.magic != MAGIC {
+            Err(Error::BadMagic(self.magic))
+        } else if self.version != VERSION {
+            Err(Error::Version(self.version))
+        } else if &self.target_hash != target_hash {
+            Err(Error::TargetMismatch)
+        } else if !ct_eq(&self.token.to_le_bytes(), &token.to_le_bytes()) {
+            Err(Error::BadToken)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HandshakeAck {
+    pub accepted: bool,
+    /// Executor should probe availability of calls, see `probe`.
+    pub probe: bool,
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Io:{0}")]
     Io(#[from] io::Error),
     #[error("Serialize: {0}")]
     Serialize(#[from] bincode::Error),
+    #[error("Bad magic: {0:#x}")]
+    BadMagic(u32),
+    #[error("Frame too large: {0} bytes")]
+    FrameTooLarge(u32),
+    #[error("Unsupported protocol version: {0}, expected {}", VERSION)]
+    Version(u16),
+    #[error("Target mismatch")]
+    TargetMismatch,
+    #[error("Bad token")]
+    BadToken,
+    #[error("Handshake rejected")]
+    Rejected,
 }
 
-pub fn recv_prog<S: Read>(src: &mut S) -> Result<Prog, Error> {
-    let header = Header::default();
-    let headler_len = bincode::serialized_size(&header)? as usize;
+/// Compare secrets in time independent of their content, only length leaks.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
 
-    let mut header_buf = BytesMut::with_capacity(headler_len);
-    assert!(header_buf.capacity() >= headler_len);
-    unsafe {
-        header_buf.set_len(headler_len);
-    }
-    src.read_exact(&mut header_buf)?;
-    let header: Header = bincode::deserialize(&header_buf)?;
+/// Hash of compiled fots file, both peers must load the same target.
+pub fn target_hash(target: &[u8]) -> [u8; 16] {
+    md5::compute(target).0
+}
 
-    let body_len = header.len as usize;
-    let mut body_buf = BytesMut::with_capacity(body_len);
-    unsafe {
-        body_buf.set_len(body_len);
+/// Executor side handshake.
+pub fn handshake<S: Read + Write>(conn: &mut S, hs: &Handshake) -> Result<HandshakeAck, Error> {
+    send(hs, conn, false)?;
+    let ack: HandshakeAck = recv(conn)?;
+    if ack.accepted {
+        Ok(ack)
+    } else {
+        Err(Error::Rejected)
     }
-    src.read_exact(&mut body_buf)?;
+}
+
+/// Fuzzer side handshake, `probe` asks accepted executor to probe calls.
+pub async fn async_accept_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut S,
+    target_hash: &[u8; 16],
+    token: u64,
+    probe: bool,
+) -> Result<(), Error> {
+    let hs: Handshake = async_recv(conn).await?;
+    let ret = hs.verify(target_hash, token);
+    let ack = HandshakeAck {
+        accepted: ret.is_ok(),
+        probe,
+    };
+    async_send(&ack, conn, false).await?;
+    ret
+}
 
-    bincode::deserialize(&body_buf).map_err(|e| e.into())
+pub fn recv_prog<S: Read>(src: &mut S) -> Result<Prog, Error> {
+    recv(src)
 }
 
-pub fn send<T: Serialize, S: Write>(v: &T, out: &mut S) -> Result<(), Error> {
-    let len = bincode::serialized_size(v)? as u32;
-    let header = Header { len };
+pub fn recv<T: DeserializeOwned, S: Read>(src: &mut S) -> Result<T, Error> {
+    let mut header_buf = alloc_buf(header_len()?);
+    src.read_exact(&mut header_buf)?;
+    let header = parse_header(&header_buf)?;
 
-    let header = bincode::serialize(&header)?;
-    let body = bincode::serialize(v)?;
+    let mut body_buf = alloc_buf(header.len as usize);
+    src.read_exact(&mut body_buf)?;
+    decode_body(&header, &body_buf)
+}
 
+/// Send `v` as a frame, body larger than `COMPRESS_THRESHOLD` is deflated
+/// if `compress` is set.
+pub fn send<T: Serialize, S: Write>(v: &T, out: &mut S, compress: bool) -> Result<(), Error> {
+    let (header, body) = encode(v, compress)?;
     out.write_all(&header)?;
     out.write_all(&body)?;
 
@@ -59,34 +166,205 @@ pub fn send<T: Serialize, S: Write>(v: &T, out: &mut S) -> Result<(), Error> {
 pub async fn async_send<T: Serialize, S: AsyncWrite + Unpin>(
     p: &T,
     out: &mut S,
+    compress: bool,
 ) -> Result<(), Error> {
-    let len = bincode::serialized_size(p)? as u32;
-    let header = Header { len };
-    let header = bincode::serialize(&header)?;
-    let body = bincode::serialize(p)?;
-
+    let (header, body) = encode(p, compress)?;
     out.write_all(&header).await?;
     out.write_all(&body).await?;
     Ok(())
 }
 
 pub async fn async_recv_result<T: AsyncRead + Unpin>(src: &mut T) -> Result<ExecResult, Error> {
+    async_recv(src).await
+}
+
+pub async fn async_recv<T: DeserializeOwned, S: AsyncRead + Unpin>(
+    src: &mut S,
+) -> Result<T, Error> {
+    let mut header_buf = alloc_buf(header_len()?);
+    src.read_exact(&mut header_buf).await?;
+    let header = parse_header(&header_buf)?;
+
+    let mut body_buf = alloc_buf(header.len as usize);
+    src.read_exact(&mut body_buf).await?;
+    decode_body(&header, &body_buf)
+}
+
+fn encode<T: Serialize>(v: &T, compress: bool) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let mut body = bincode::serialize(v)?;
+    let mut flags = 0;
+    if compress && body.len() >= COMPRESS_THRESHOLD {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&body)?;
+        body = encoder.finish()?;
+        flags |= FLAG_COMPRESSED;
+    }
+    if body.len() > MAX_BODY_LEN as usize {
+        return Err(Error::FrameTooLarge(body.len() as u32));
+    }
+
+    let header = Header {
+        magic: MAGIC,
+        flags,
+        len: body.len() as u32,
+    };
+    Ok((bincode::serialize(&header)?, body))
+}
+
+fn parse_header(buf: &[u8]) -> Result<Header, Error> {
+    let header: Header = bincode::deserialize(buf)?;
+    if header.magic != MAGIC {
+        Err(Error::BadMagic(header.magic))
+    } else if header.len > MAX_BODY_LEN {
+        Err(Error::FrameTooLarge(header.len))
+    } else {
+        Ok(header)
+    }
+}
+
+fn decode_body<T: DeserializeOwned>(header: &Header, body: &[u8]) -> Result<T, Error> {
+    if header.flags & FLAG_COMPRESSED != 0 {
+        // Inflated body is bounded as well, or a small frame could exhaust memory.
+        let mut decoder = DeflateDecoder::new(body).take(MAX_BODY_LEN as u64 + 1);
+        let mut buf = Vec::new();
+        decoder.read_to_end(&mut buf)?;
+        if buf.len() > MAX_BODY_LEN as usize {
+            return Err(Error::FrameTooLarge(buf.len() as u32));
+        }
+        bincode::deserialize(&buf).map_err(|e| e.into())
+    } else {
+        bincode::deserialize(body).map_err(|e| e.into())
+    }
+}
+
+fn header_len() -> Result<usize, Error> {
     let header = Header::default();
-    let headler_len = bincode::serialized_size(&header)? as usize;
-    let mut header_buf = BytesMut::with_capacity(headler_len);
-    assert!(header_buf.capacity() >= headler_len);
-    unsafe {
-        header_buf.set_len(headler_len);
+    Ok(bincode::serialized_size(&header)? as usize)
+}
+
+fn alloc_buf(len: usize) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(len);
+    buf.resize(len, 0);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::os::unix::net::UnixStream;
+
+    const HASH: [u8; 16] = [1; 16];
+    const TOKEN: u64 = 0x1234;
+
+    fn frame<T: Serialize>(v: &T, compress: bool) -> Vec<u8> {
+        let mut buf = Vec::new();
+        send(v, &mut buf, compress).unwrap();
+        buf
     }
-    src.read_exact(&mut header_buf).await?;
-    let header: Header = bincode::deserialize(&header_buf)?;
 
-    let body_len = header.len as usize;
-    let mut body_buf = BytesMut::with_capacity(body_len);
-    unsafe {
-        body_buf.set_len(body_len);
+    #[test]
+    fn round_trip() {
+        let small = vec![1u8, 2, 3];
+        let buf = frame(&small, true);
+        let header = parse_header(&buf).unwrap();
+        assert_eq!(header.flags, 0);
+        let v: Vec<u8> = recv(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(v, small);
+
+        let large = vec![7u32; COMPRESS_THRESHOLD];
+        let plain = frame(&large, false);
+        let buf = frame(&large, true);
+        let header = parse_header(&buf).unwrap();
+        assert_eq!(header.flags & FLAG_COMPRESSED, FLAG_COMPRESSED);
+        assert!(buf.len() < plain.len());
+        let v: Vec<u32> = recv(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(v, large);
+        let v: Vec<u32> = recv(&mut Cursor::new(plain)).unwrap();
+        assert_eq!(v, large);
+    }
+
+    #[test]
+    fn reject_bad_frame() {
+        let mut buf = frame(&0u64, false);
+        buf[0] ^= 0xff;
+        let ret = recv::<u64, _>(&mut Cursor::new(buf));
+        assert!(matches!(ret, Err(Error::BadMagic(_))));
+
+        let header = Header {
+            magic: MAGIC,
+            flags: 0,
+            len: MAX_BODY_LEN + 1,
+        };
+        let buf = bincode::serialize(&header).unwrap();
+        let ret = recv::<u64, _>(&mut Cursor::new(buf));
+        assert!(matches!(ret, Err(Error::FrameTooLarge(len)) if len == MAX_BODY_LEN + 1));
     }
-    src.read_exact(&mut body_buf).await?;
 
-    bincode::deserialize(&body_buf).map_err(|e| e.into())
+    #[test]
+    fn reject_inflated_frame() {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        let zeros = vec![0u8; 1024 * 1024];
+        for _ in 0..MAX_BODY_LEN as usize / zeros.len() {
+            encoder.write_all(&zeros).unwrap();
+        }
+        encoder.write_all(&[0]).unwrap();
+        let body = encoder.finish().unwrap();
+        let header = Header {
+            magic: MAGIC,
+            flags: FLAG_COMPRESSED,
+            len: body.len() as u32,
+        };
+        let mut buf = bincode::serialize(&header).unwrap();
+        buf.extend(body);
+        let ret = recv::<Vec<u8>, _>(&mut Cursor::new(buf));
+        assert!(matches!(ret, Err(Error::FrameTooLarge(len)) if len == MAX_BODY_LEN + 1));
+    }
+
+    #[test]
+    fn compare_secret() {
+        assert!(ct_eq(b"secret", b"secret"));
+        assert!(!ct_eq(b"secret", b"secreT"));
+        assert!(!ct_eq(b"secret", b"secret!"));
+        assert!(ct_eq(b"", b""));
+    }
+
+    #[test]
+    fn verify_handshake() {
+        assert!(Handshake::new(HASH, TOKEN).verify(&HASH, TOKEN).is_ok());
+
+        let mut hs = Handshake::new(HASH, TOKEN);
+        hs.magic = 0;
+        assert!(matches!(hs.verify(&HASH, TOKEN), Err(Error::BadMagic(0))));
+        let mut hs = Handshake::new(HASH, TOKEN);
+        hs.version = VERSION + 1;
+        assert!(matches!(hs.verify(&HASH, TOKEN), Err(Error::Version(_))));
+        let hs = Handshake::new([2; 16], TOKEN);
+        assert!(matches!(
+            hs.verify(&HASH, TOKEN),
+            Err(Error::TargetMismatch)
+        ));
+        let hs = Handshake::new(HASH, TOKEN + 1);
+        assert!(matches!(hs.verify(&HASH, TOKEN), Err(Error::BadToken)));
+    }
+
+    #[test]
+    fn executor_handshake() {
+        for &accepted in [true, false].iter() {
+            let (mut executor, mut fuzzer) = UnixStream::pair().unwrap();
+            let ack = HandshakeAck {
+                accepted,
+                probe: true,
+            };
+            send(&ack, &mut fuzzer, false).unwrap();
+            let ret = handshake(&mut executor, &Handshake::new(HASH, TOKEN));
+            let hs: Handshake = recv(&mut fuzzer).unwrap();
+            assert!(hs.verify(&HASH, TOKEN).is_ok());
+            if accepted {
+                assert!(ret.unwrap().probe);
+            } else {
+                assert!(matches!(ret, Err(Error::Rejected)));
+            }
+        }
+    }
 }
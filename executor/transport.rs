@@ -0,0 +1,141 @@
+//! Channels between executor and fuzzer.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Default name of virtio-serial port exposed to guest.
+pub const SERIAL_PORT_NAME: &str = "healer";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    /// Connect to fuzzer with tcp, addr is `ip:port`.
+    #[default]
+    Tcp,
+    /// Talk through stdin and stdout, for local or ssh-piped runs.
+    Stdio,
+    /// Connect to unix domain socket listened by fuzzer, addr is socket path.
+    Unix,
+    /// Open virtio-serial port backed by qemu chardev, addr is port device.
+    VirtioSerial,
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Transport::Tcp),
+            "stdio" => Ok(Transport::Stdio),
+            "unix" => Ok(Transport::Unix),
+            "virtio-serial" => Ok(Transport::VirtioSerial),
+            _ => Err(format!(
+                "Unknown transport `{}`, expect one of tcp, stdio, unix, virtio-serial",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Transport::Tcp => "tcp",
+            Transport::Stdio => "stdio",
+            Transport::Unix => "unix",
+            Transport::VirtioSerial => "virtio-serial",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub enum Conn {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+    Serial(File),
+    Stdio { input: File, output: File },
+}
+
+impl Conn {
+    /// Connect to fuzzer with `transport`, retry several times for tcp and unix.
+    pub fn connect(transport: Transport, addr: &str) -> io::Result<Self> {
+        match transport {
+            Transport::Tcp => retry(|| TcpStream::connect(addr).map(Conn::Tcp)),
+            Transport::Unix => retry(|| UnixStream::connect(addr).map(Conn::Unix)),
+            Transport::VirtioSerial => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(addr)
+                .map(Conn::Serial),
+            Transport::Stdio => Self::stdio(),
+        }
+    }
+
+    /// Take over stdin and stdout. Output of executed progs goes to stdout
+    /// too, so the original stdout is kept for conn and fd 1 is redirected
+    /// to stderr.
+    fn stdio() -> io::Result<Self> {
+        use nix::unistd::{dup, dup2};
+
+        let out = dup(1).map_err(|_| io::Error::last_os_error())?;
+        dup2(2, 1).map_err(|_| io::Error::last_os_error())?;
+        let (input, output) = unsafe { (File::from_raw_fd(0), File::from_raw_fd(out)) };
+        Ok(Conn::Stdio { input, output })
+    }
+}
+
+fn retry<F: FnMut() -> io::Result<Conn>>(mut f: F) -> io::Result<Conn> {
+    let mut retry = 1;
+    loop {
+        match f() {
+            Ok(c) => return Ok(c),
+            Err(e) => {
+                if retry == 5 {
+                    return Err(e);
+                }
+                retry += 1;
+                sleep(Duration::from_millis(100));
+            }
+        }
+    }
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Conn::Tcp(c) => c.read(buf),
+            Conn::Unix(c) => c.read(buf),
+            Conn::Serial(c) => c.read(buf),
+            Conn::Stdio { input, .. } => input.read(buf),
+        }
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Conn::Tcp(c) => c.write(buf),
+            Conn::Unix(c) => c.write(buf),
+            Conn::Serial(c) => c.write(buf),
+            Conn::Stdio { output, .. } => output.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Conn::Tcp(c) => c.flush(),
+            Conn::Unix(c) => c.flush(),
+            Conn::Serial(c) => c.flush(),
+            Conn::Stdio { output, .. } => output.flush(),
+        }
+    }
+}
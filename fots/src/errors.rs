@@ -9,6 +9,9 @@ pub enum Error {
     Parse(#[from] pest::error::Error<Rule>),
     #[error("Unresolved symbols:{0:?}")]
     Ident(Vec<String>),
+    /// Slice field without length can't be defined in C, see `#[defs]`.
+    #[error("Slice field without length in #[defs] group {group}: {field}")]
+    UnboundedField { group: String, field: String },
 }
 
 impl Error {
@@ -1,6 +1,6 @@
 //! Parser for items
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::ops::Range;
 use std::process::exit;
@@ -11,8 +11,8 @@ use pest::iterators::{Pair, Pairs};
 use crate::errors;
 use crate::grammar::Rule;
 use crate::types::{
-    Attr, Field, Flag, FnId, FnInfo, Group, GroupId, Items, NumInfo, NumLimit, Param, PtrDir,
-    StrType, Type, TypeId, TypeInfo, DEFAULT_GID,
+    Attr, Field, Flag, FnId, FnInfo, Group, GroupId, Items, LenUnit, NumInfo, NumLimit, Param,
+    PtrDir, StrType, Type, TypeId, TypeInfo, DEFAULT_GID,
 };
 use crate::{num, parse_grammar};
 
@@ -23,6 +23,64 @@ pub fn parse(text: &str) -> Result<Items, errors::Error> {
     Parser::parse(parse_tree)
 }
 
+/// Structs and unions reachable from groups declared with `#[defs]` are
+/// defined in C from their fields, each field must have fixed size.
+fn check_defs(items: &Items) -> Option<errors::Error> {
+    let types = items
+        .types
+        .iter()
+        .map(|t| (t.tid, &t.info))
+        .collect::<HashMap<_, _>>();
+    for g in items.groups.iter().filter(|g| g.get_attr("defs").is_some()) {
+        let mut visited = HashSet::new();
+        for f in g.fns.iter() {
+            let params = f.params.iter().flatten().map(|p| p.tid);
+            for tid in params.chain(f.r_tid) {
+                if let Some(field) = unbounded_field(tid, &types, &mut visited) {
+                    return Some(errors::Error::UnboundedField {
+                        group: g.ident.clone(),
+                        field,
+                    });
+                }
+            }
+        }
+    }
+    None
+}
+
+/// First field of slice without length in struct or union reachable from `tid`.
+fn unbounded_field(
+    tid: TypeId,
+    types: &HashMap<TypeId, &TypeInfo>,
+    visited: &mut HashSet<TypeId>,
+) -> Option<String> {
+    match types[&tid] {
+        TypeInfo::Struct { ident, fields, .. } | TypeInfo::Union { ident, fields, .. } => {
+            if !visited.insert(tid) {
+                return None;
+            }
+            for field in fields.iter() {
+                let mut ftid = field.tid;
+                while let TypeInfo::Alias { tid, .. } = types[&ftid] {
+                    ftid = *tid;
+                }
+                if let TypeInfo::Slice { l: -1, h: -1, .. } = types[&ftid] {
+                    return Some(format!("{}.{}", ident, field.ident));
+                }
+            }
+            fields
+                .iter()
+                .find_map(|field| unbounded_field(field.tid, types, visited))
+        }
+        TypeInfo::Ptr { tid, .. }
+        | TypeInfo::Slice { tid, .. }
+        | TypeInfo::Alias { tid, .. }
+        | TypeInfo::Res { tid }
+        | TypeInfo::Len { tid, .. } => unbounded_field(*tid, types, visited),
+        TypeInfo::Num(_) | TypeInfo::Str { .. } | TypeInfo::Flag { .. } => None,
+    }
+}
+
 struct Parser {
     type_table: TypeTable,
     group_table: GroupTable,
@@ -69,6 +127,9 @@ impl Parser {
         };
         items.types.sort_by_key(|a| a.tid);
         items.groups.sort_by_key(|i| i.id);
+        if let Some(e) = check_defs(&items) {
+            return Err(e);
+        }
 
         items.types.shrink_to_fit();
         items.groups.shrink_to_fit();
@@ -176,13 +237,49 @@ impl Parser {
 
     fn parse_struct(&mut self, p: Pair<Rule>) -> TypeId {
         let mut p: Pairs<Rule> = p.into_inner();
-        let ident_p: Pair<Rule> = p.next().unwrap();
+        let (attrs, ident_p) = self.parse_layout_attrs(&mut p);
         let fields_p: Pair<Rule> = p.next().unwrap();
         assert_eq!(fields_p.as_rule(), Rule::Fields);
-        let info = TypeInfo::struct_info(ident_p.as_str(), self.parse_fields(fields_p));
+        let info = TypeInfo::struct_info(ident_p.as_str(), self.parse_fields(fields_p), attrs);
         self.type_table.add(info)
     }
 
+    /// Optional `#[packed]` and `#[align(n)]` of struct or union, followed by ident.
+    fn parse_layout_attrs<'a>(
+        &mut self,
+        p: &mut Pairs<'a, Rule>,
+    ) -> (Option<Vec<Attr>>, Pair<'a, Rule>) {
+        let attr_or_ident_p = p.next().unwrap();
+        if attr_or_ident_p.as_rule() != Rule::AttrsDef {
+            return (None, attr_or_ident_p);
+        }
+        let attrs = self.parse_attrs(attr_or_ident_p);
+        let ident_p = p.next().unwrap();
+        for attr in attrs.iter() {
+            let valid = match attr.ident.as_str() {
+                "packed" => !attr.has_vals(),
+                "align" => {
+                    attr.has_vals()
+                        && attr.iter_val().count() == 1
+                        && num::parse::<u64>(attr.iter_val().next().unwrap())
+                            .is_ok_and(|n| n.is_power_of_two())
+                }
+                _ => false,
+            };
+            if !valid {
+                use colored::*;
+                eprintln!(
+                    "{}:Invalid Attr:{} of {}",
+                    "Error".red(),
+                    attr,
+                    ident_p.as_str()
+                );
+                exit(1);
+            }
+        }
+        (Some(attrs), ident_p)
+    }
+
     fn parse_fields(&mut self, p: Pair<Rule>) -> Vec<Field> {
         p.into_inner().map(|p| self.parse_field(p)).collect()
     }
@@ -270,9 +367,11 @@ impl Parser {
 
     fn parse_len(&mut self, p: Pair<Rule>) -> TypeId {
         let mut p = p.into_inner();
+        let unit_p = p.next().unwrap().into_inner().next().unwrap();
+        let unit = LenUnit::from_rule(unit_p.as_rule());
         let tid = self.parse_num_type(p.next().unwrap());
         let path = p.next().unwrap().as_str();
-        self.type_table.add(TypeInfo::len_info(tid, path))
+        self.type_table.add(TypeInfo::len_info(tid, path, unit))
     }
 
     fn parse_name_type(&mut self, p: Pair<Rule>) -> TypeId {
@@ -436,10 +535,10 @@ impl Parser {
 
     fn parse_union(&mut self, p: Pair<Rule>) -> TypeId {
         let mut p = p.into_inner();
-        let ident_p = p.next().unwrap();
+        let (attrs, ident_p) = self.parse_layout_attrs(&mut p);
         let fields_p = p.next().unwrap();
         assert_eq!(fields_p.as_rule(), Rule::Fields);
-        let t_info = TypeInfo::union_info(ident_p.as_str(), self.parse_fields(fields_p));
+        let t_info = TypeInfo::union_info(ident_p.as_str(), self.parse_fields(fields_p), attrs);
         self.type_table.add(t_info)
     }
 
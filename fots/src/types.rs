@@ -135,13 +135,16 @@ pub enum TypeInfo {
         str_type: StrType,
         vals: Option<Vec<String>>,
     },
+    // Layout of struct and union can be changed with #[packed] and #[align(n)].
     Struct {
         ident: String,
         fields: Vec<Field>,
+        attrs: Option<Vec<Attr>>,
     },
     Union {
         ident: String,
         fields: Vec<Field>,
+        attrs: Option<Vec<Attr>>,
     },
     Flag {
         ident: String,
@@ -158,9 +161,42 @@ pub enum TypeInfo {
         tid: TypeId,
         path: String,
         is_param: bool,
+        unit: LenUnit,
     },
 }
 
+/// What length of `Len` type counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LenUnit {
+    /// Elements of slice or chars of string, declared with `len`.
+    Count,
+    /// Declared with `bytesize`.
+    Bytes,
+    /// Declared with `bitsize`.
+    Bits,
+}
+
+impl Display for LenUnit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            LenUnit::Count => write!(f, "len"),
+            LenUnit::Bytes => write!(f, "bytesize"),
+            LenUnit::Bits => write!(f, "bitsize"),
+        }
+    }
+}
+
+impl LenUnit {
+    pub fn from_rule(unit: Rule) -> Self {
+        match unit {
+            Rule::Len => Self::Count,
+            Rule::ByteSize => Self::Bytes,
+            Rule::BitSize => Self::Bits,
+            _ => unreachable!(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialOrd, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum StrType {
     Str,
@@ -202,21 +238,29 @@ impl Display for TypeInfo {
                     write!(f, "{}", s)
                 }
             }
-            TypeInfo::Struct { ident, fields } => {
+            TypeInfo::Struct {
+                ident,
+                fields,
+                attrs,
+            } => {
                 let fields_str = fields
                     .iter()
                     .map(|f| f.to_string())
                     .collect::<Vec<_>>()
                     .join(",");
-                write!(f, "struct {}{{{}}}", ident, fields_str)
+                write!(f, "{}struct {}{{{}}}", attrs_str(attrs), ident, fields_str)
             }
-            TypeInfo::Union { ident, fields } => {
+            TypeInfo::Union {
+                ident,
+                fields,
+                attrs,
+            } => {
                 let fields_str = fields
                     .iter()
                     .map(|f| f.to_string())
                     .collect::<Vec<_>>()
                     .join(",");
-                write!(f, "union {}{{{}}}", ident, fields_str)
+                write!(f, "{}union {}{{{}}}", attrs_str(attrs), ident, fields_str)
             }
             TypeInfo::Flag { ident, flags } => {
                 let flags_str = flags
@@ -228,7 +272,25 @@ impl Display for TypeInfo {
             }
             TypeInfo::Alias { ident, tid } => write!(f, "Alias {}=>id({})", ident, tid),
             TypeInfo::Res { tid } => write!(f, "res<id({})>", tid),
-            TypeInfo::Len { tid, path, .. } => write!(f, "len<id({}),{}>", tid, path),
+            TypeInfo::Len {
+                tid, path, unit, ..
+            } => {
+                write!(f, "{}<id({}),{}>", unit, tid, path)
+            }
+        }
+    }
+}
+
+fn attrs_str(attrs: &Option<Vec<Attr>>) -> String {
+    match attrs {
+        None => "".into(),
+        Some(attrs) => {
+            let attrs = attrs
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            format!("#[{}]", attrs)
         }
     }
 }
@@ -294,12 +356,13 @@ impl TypeInfo {
         TypeInfo::Ptr { tid, dir, depth }
     }
 
-    pub fn len_info(tid: TypeId, path: &str) -> Self {
+    pub fn len_info(tid: TypeId, path: &str, unit: LenUnit) -> Self {
         // TODO path parse
         TypeInfo::Len {
             path: String::from(path),
             is_param: true,
             tid,
+            unit,
         }
     }
 
@@ -307,17 +370,29 @@ impl TypeInfo {
         TypeInfo::Res { tid }
     }
 
-    pub fn struct_info(ident: &str, fields: Vec<Field>) -> Self {
+    pub fn struct_info(ident: &str, fields: Vec<Field>, attrs: Option<Vec<Attr>>) -> Self {
         TypeInfo::Struct {
             ident: String::from(ident),
             fields,
+            attrs,
         }
     }
 
-    pub fn union_info(ident: &str, fields: Vec<Field>) -> Self {
+    pub fn union_info(ident: &str, fields: Vec<Field>, attrs: Option<Vec<Attr>>) -> Self {
         TypeInfo::Union {
             ident: String::from(ident),
             fields,
+            attrs,
+        }
+    }
+
+    /// Attribute of struct or union.
+    pub fn get_attr(&self, name: &str) -> Option<&Attr> {
+        match self {
+            Self::Struct { attrs, .. } | Self::Union { attrs, .. } => attrs
+                .as_ref()
+                .and_then(|attrs| attrs.iter().find(|&attr| attr.ident == name)),
+            _ => None,
         }
     }
 
@@ -598,6 +673,12 @@ impl Group {
     pub fn index_by_id(&self, fid: FnId) -> Option<usize> {
         self.iter_fn().position(|f| f.id == fid)
     }
+
+    pub fn get_attr(&self, name: &str) -> Option<&Attr> {
+        self.attrs
+            .as_ref()
+            .and_then(|attrs| attrs.iter().find(|&attr| attr.ident == name))
+    }
 }
 
 /// Information of different size numbe type
@@ -824,7 +905,7 @@ impl Flag {
     }
 }
 
-/// Attribute of group or function
+/// Attribute of group, function, struct or union
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Attr {
     pub ident: String,
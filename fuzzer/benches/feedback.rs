@@ -0,0 +1,182 @@
+//! Compare `FeedBack` with the mutex-guarded hash sets it replaced.
+//!
+//! Run with `cargo bench -p fuzzer --bench feedback`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fuzzer::feedback::{Block, Branch, FeedBack};
+use rand::prelude::*;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::Mutex;
+
+/// Known blocks of a long campaign.
+const KNOWN: usize = 1_000_000;
+/// Blocks covered by one call.
+const COVER: usize = 2000;
+/// Programs diffed by each vm in one iteration of concurrent bench.
+const PROGS: usize = 16;
+const KERNEL_TEXT: usize = 0xffff_ffff_8100_0000;
+
+/// Previous implementation, every diff takes a global lock.
+#[derive(Default)]
+struct MutexFeedBack {
+    branches: Mutex<HashSet<Branch>>,
+    blocks: Mutex<HashSet<Block>>,
+}
+
+impl MutexFeedBack {
+    async fn diff_branch(&self, branches: &[Branch]) -> HashSet<Branch> {
+        let inner = self.branches.lock().await;
+        let mut result = HashSet::new();
+        for b in branches {
+            if !inner.contains(b) {
+                result.insert(b.clone());
+            }
+        }
+        result.shrink_to_fit();
+        result
+    }
+
+    async fn diff_block(&self, blocks: &[Block]) -> HashSet<Block> {
+        let inner = self.blocks.lock().await;
+        let mut result = HashSet::new();
+        for b in blocks {
+            if !inner.contains(b) {
+                result.insert(b.clone());
+            }
+        }
+        result.shrink_to_fit();
+        result
+    }
+
+    async fn merge(&self, blocks: HashSet<Block>, branches: HashSet<Branch>) {
+        self.branches.lock().await.extend(branches);
+        self.blocks.lock().await.extend(blocks);
+    }
+}
+
+/// Kernel-like PCs, a few of them unknown.
+fn cover(rng: &mut StdRng) -> (Vec<Block>, Vec<Branch>) {
+    let blocks = (0..COVER)
+        .map(|_| Block::from(KERNEL_TEXT + rng.gen_range(0, KNOWN + KNOWN / 100) * 4))
+        .collect::<Vec<_>>();
+    let branches = blocks
+        .windows(2)
+        .map(|w| Branch::from((w[0].clone(), w[1].clone())))
+        .collect();
+    (blocks, branches)
+}
+
+fn known() -> (HashSet<Block>, HashSet<Branch>) {
+    let blocks = (0..KNOWN)
+        .map(|i| Block::from(KERNEL_TEXT + i * 4))
+        .collect::<HashSet<_>>();
+    let branches = (0..KNOWN)
+        .map(|i| {
+            Branch::from((
+                Block::from(KERNEL_TEXT + i * 4),
+                Block::from(KERNEL_TEXT + i * 8),
+            ))
+        })
+        .collect();
+    (blocks, branches)
+}
+
+fn runtime(threads: usize) -> Runtime {
+    Builder::new()
+        .threaded_scheduler()
+        .core_threads(threads)
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn bench_diff(c: &mut Criterion) {
+    let (blocks, branches) = known();
+    let mut rt = runtime(1);
+    let old = MutexFeedBack::default();
+    rt.block_on(old.merge(blocks.clone(), branches.clone()));
+    let new = FeedBack::default();
+    new.merge(blocks, branches);
+    let (blocks, branches) = cover(&mut StdRng::seed_from_u64(0));
+
+    let mut group = c.benchmark_group("diff");
+    group.bench_function("mutex", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                (
+                    old.diff_block(&blocks).await,
+                    old.diff_branch(&branches).await,
+                )
+            })
+        })
+    });
+    group.bench_function("signal_set", |b| {
+        b.iter(|| (new.diff_block(&blocks), new.diff_branch(&branches)))
+    });
+    group.finish();
+}
+
+/// Every vm diffs `PROGS` programs and merges new signal of each.
+fn bench_concurrent(c: &mut Criterion) {
+    let (blocks, branches) = known();
+    let mut group = c.benchmark_group("concurrent");
+    group.sample_size(10);
+    for &vms in [1, 4, 8].iter() {
+        let mut rt = runtime(vms);
+        let covers = Arc::new(
+            (0..vms * PROGS)
+                .map(|i| cover(&mut StdRng::seed_from_u64(i as u64)))
+                .collect::<Vec<_>>(),
+        );
+
+        let old = Arc::new(MutexFeedBack::default());
+        rt.block_on(old.merge(blocks.clone(), branches.clone()));
+        group.bench_with_input(BenchmarkId::new("mutex", vms), &vms, |b, &vms| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let tasks = (0..vms).map(|vm| {
+                        let (old, covers) = (old.clone(), covers.clone());
+                        tokio::spawn(async move {
+                            for (blocks, branches) in covers[vm * PROGS..(vm + 1) * PROGS].iter() {
+                                let new_blocks = old.diff_block(blocks).await;
+                                let new_branches = old.diff_branch(branches).await;
+                                old.merge(new_blocks, new_branches).await;
+                            }
+                        })
+                    });
+                    for t in tasks.collect::<Vec<_>>() {
+                        t.await.unwrap();
+                    }
+                })
+            })
+        });
+
+        let new = Arc::new(FeedBack::default());
+        new.merge(blocks.clone(), branches.clone());
+        group.bench_with_input(BenchmarkId::new("signal_set", vms), &vms, |b, &vms| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let tasks = (0..vms).map(|vm| {
+                        let (new, covers) = (new.clone(), covers.clone());
+                        tokio::spawn(async move {
+                            for (blocks, branches) in covers[vm * PROGS..(vm + 1) * PROGS].iter() {
+                                let new_blocks = new.diff_block(blocks);
+                                let new_branches = new.diff_branch(branches);
+                                new.merge(new_blocks, new_branches);
+                            }
+                        })
+                    });
+                    for t in tasks.collect::<Vec<_>>() {
+                        t.await.unwrap();
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_diff, bench_concurrent);
+criterion_main!(benches);
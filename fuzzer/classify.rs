@@ -0,0 +1,426 @@
+//! Classify failed cases into description bugs.
+//!
+//! Most failed progs don't reveal kernel behaviour, they fail to compile
+//! because of wrong types, arg counts or headers in descriptions. Reason of
+//! a failure is normalized by dropping locations, numbers and quoted names,
+//! so failures of the same bug fall into one class. The first quoted name is
+//! kept as subject to point at the FOTS function or type responsible.
+//!
+//! Calls whose progs never compile can be quarantined, see `Quarantine`.
+
+use crate::select::trim_with;
+use core::prog::Prog;
+use core::target::Target;
+use fots::types::FnId;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Write};
+use std::sync::{Mutex, RwLock};
+
+/// Normalized error longer than this is truncated.
+const MAX_ERROR_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Kind {
+    /// Generated prog is rejected by compiler.
+    Compile,
+    /// Symbol or library is missing.
+    Link,
+    Timeout,
+    Runtime,
+}
+
+impl Kind {
+    /// Prog never got to run, description is to blame.
+    pub fn is_build(self) -> bool {
+        self == Kind::Compile || self == Kind::Link
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Kind::Compile => "compile",
+            Kind::Link => "link",
+            Kind::Timeout => "timeout",
+            Kind::Runtime => "runtime",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub kind: Kind,
+    /// Error with locations, numbers and quoted names dropped.
+    pub error: String,
+    /// First name quoted in error, if any.
+    pub subject: Option<String>,
+}
+
+/// Classify reason of a failed case, reported by tcc or executor.
+pub fn classify(reason: &str) -> Failure {
+    if let Some(i) = reason.find("error:") {
+        let line = reason[i + "error:".len()..]
+            .lines()
+            .next()
+            .unwrap_or_default();
+        let (error, subject) = normalize(line);
+        let kind = if error.starts_with("undefined symbol")
+            || error.starts_with("library")
+            || error.starts_with("cannot find library")
+        {
+            Kind::Link
+        } else {
+            Kind::Compile
+        };
+        Failure {
+            kind,
+            error,
+            subject,
+        }
+    } else if reason.contains("Fail to relocate") {
+        Failure {
+            kind: Kind::Link,
+            error: "fail to relocate".to_string(),
+            subject: None,
+        }
+    } else if reason.contains("Time out") {
+        Failure {
+            kind: Kind::Timeout,
+            error: "time out".to_string(),
+            subject: None,
+        }
+    } else {
+        let line = reason
+            .lines()
+            .find(|l| !l.trim().is_empty())
+            .unwrap_or_default();
+        let (mut error, subject) = normalize(line);
+        if error.is_empty() {
+            error = "unknown".to_string();
+        }
+        Failure {
+            kind: Kind::Runtime,
+            error,
+            subject,
+        }
+    }
+}
+
+/// Replace quoted names with `'?'` and numbers with `N`, return first name.
+fn normalize(line: &str) -> (String, Option<String>) {
+    let mut error = String::new();
+    let mut subject = None;
+    let mut chars = line.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\'' || c == '"' {
+            let name = chars.by_ref().take_while(|&q| q != c).collect::<String>();
+            if subject.is_none() && !name.is_empty() {
+                subject = Some(name);
+            }
+            error.push_str("'?'");
+        } else if c.is_ascii_digit() {
+            while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                chars.next();
+            }
+            error.push('N');
+        } else {
+            error.push(c);
+        }
+    }
+    if error.len() > MAX_ERROR_LEN {
+        let mut end = MAX_ERROR_LEN;
+        while !error.is_char_boundary(end) {
+            end -= 1;
+        }
+        error.truncate(end);
+    }
+    (error, subject)
+}
+
+/// FOTS items responsible for failure of a prog calling `calls`.
+///
+/// Subject of failure naming one of calls or a type of target is blamed,
+/// otherwise every call is involved.
+pub fn blame(failure: &Failure, calls: &[String], t: Option<&Target>) -> Vec<String> {
+    if let Some(subject) = failure.subject.as_ref() {
+        let call = calls.iter().find(|&c| {
+            c == subject
+                || t.is_some_and(|t| {
+                    t.fns.values().any(|f| {
+                        let f = unsafe { f.as_ref() };
+                        &f.dec_name == c && &f.call_name == subject
+                    })
+                })
+        });
+        if let Some(call) = call {
+            return vec![format!("fn {}", call)];
+        }
+        if t.is_some_and(|t| t.types.values().any(|ty| ty.ident() == Some(subject))) {
+            return vec![format!("type {}", subject)];
+        }
+    }
+    let mut calls = calls
+        .iter()
+        .map(|c| format!("fn {}", c))
+        .collect::<Vec<_>>();
+    calls.sort_unstable();
+    calls.dedup();
+    calls
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ErrorStat {
+    pub kind: Option<Kind>,
+    pub error: String,
+    pub count: usize,
+    /// Times each FOTS item is blamed, see `blame`.
+    pub blamed: HashMap<String, usize>,
+    /// Reason of the first case.
+    pub example: String,
+}
+
+/// Failed cases grouped by error and by function.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Analysis {
+    pub total: usize,
+    /// Keyed by kind and normalized error.
+    pub errors: HashMap<String, ErrorStat>,
+    /// Failed cases calling each function, by declared name.
+    pub fns: HashMap<String, usize>,
+}
+
+impl Analysis {
+    pub fn add(&mut self, calls: &[String], reason: &str, t: Option<&Target>) {
+        let failure = classify(reason);
+        let blamed = blame(&failure, calls, t);
+        let key = format!("[{}] {}", failure.kind, failure.error);
+        let stat = self.errors.entry(key).or_insert_with(|| ErrorStat {
+            kind: Some(failure.kind),
+            error: failure.error,
+            example: reason.to_string(),
+            ..ErrorStat::default()
+        });
+        stat.count += 1;
+        for b in blamed {
+            *stat.blamed.entry(b).or_insert(0) += 1;
+        }
+        let calls = calls.iter().collect::<HashSet<_>>();
+        for c in calls {
+            *self.fns.entry(c.clone()).or_insert(0) += 1;
+        }
+        self.total += 1;
+    }
+
+    /// Plain text report, most frequent first.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        writeln!(report, "Failed cases: {}", self.total).unwrap();
+        writeln!(report, "By error:").unwrap();
+        for (key, stat) in sorted(&self.errors, |s| s.count) {
+            writeln!(report, "{:>8}  {}", stat.count, key).unwrap();
+            for (item, n) in sorted(&stat.blamed, |&n| n).into_iter().take(8) {
+                writeln!(report, "{:>18}  {}", n, item).unwrap();
+            }
+        }
+        writeln!(report, "By function:").unwrap();
+        for (f, n) in sorted(&self.fns, |&n| n) {
+            writeln!(report, "{:>8}  {}", n, f).unwrap();
+        }
+        report
+    }
+}
+
+fn sorted<V, F: Fn(&V) -> usize>(m: &HashMap<String, V>, count: F) -> Vec<(&String, &V)> {
+    let mut entries = m.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| count(b.1).cmp(&count(a.1)).then(a.0.cmp(b.0)));
+    entries
+}
+
+/// Calls whose progs failed to build `threshold` times and never built.
+pub struct Quarantine {
+    threshold: usize,
+    /// Built and failed to build progs calling each function.
+    counts: Mutex<HashMap<FnId, (usize, usize)>>,
+    quarantined: RwLock<HashSet<FnId>>,
+}
+
+impl Quarantine {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            counts: Mutex::new(HashMap::new()),
+            quarantined: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Record whether `p` is built, `failure` is its failure if any, return
+    /// calls quarantined because of it.
+    ///
+    /// Build failure is counted against calls blamed for it, see `blame`,
+    /// so calls merely appearing with a broken one are not quarantined.
+    pub fn record(&self, p: &Prog, failure: Option<&Failure>, t: &Target) -> Vec<FnId> {
+        let built = failure.is_none_or(|f| !f.kind.is_build());
+        let fids = match failure {
+            Some(failure) if !built => blamed_fns(failure, p, t),
+            _ => p.calls.iter().map(|c| c.fid).collect(),
+        };
+        let mut newly = Vec::new();
+        {
+            let mut counts = self.counts.lock().unwrap();
+            for fid in fids {
+                let (ok, failed) = counts.entry(fid).or_insert((0, 0));
+                if built {
+                    *ok += 1;
+                } else {
+                    *failed += 1;
+                    if *ok == 0 && *failed == self.threshold {
+                        newly.push(fid);
+                    }
+                }
+            }
+        }
+        if !newly.is_empty() {
+            self.quarantined
+                .write()
+                .unwrap()
+                .extend(newly.iter().copied());
+        }
+        newly
+    }
+
+    pub fn len(&self) -> usize {
+        self.quarantined.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove quarantined calls of `p`, see `select::trim_with`. `p` is
+    /// given back if its last call is quarantined.
+    pub fn trim(&self, p: Prog) -> Result<Prog, Prog> {
+        let quarantined = self.quarantined.read().unwrap();
+        if !p.calls.iter().any(|c| quarantined.contains(&c.fid)) {
+            return Ok(p);
+        }
+        trim_with(p.clone(), |fid| !quarantined.contains(&fid)).ok_or(p)
+    }
+}
+
+/// Calls of `p` blamed for `failure`, every call if none is named.
+fn blamed_fns(failure: &Failure, p: &Prog, t: &Target) -> HashSet<FnId> {
+    let names = p
+        .calls
+        .iter()
+        .map(|c| t.fn_of(c.fid).dec_name.clone())
+        .collect::<Vec<_>>();
+    let blamed = blame(failure, &names, Some(t));
+    let fids = p
+        .calls
+        .iter()
+        .map(|c| c.fid)
+        .filter(|&fid| blamed.contains(&format!("fn {}", t.fn_of(fid).dec_name)))
+        .collect::<HashSet<_>>();
+    if fids.is_empty() {
+        p.calls.iter().map(|c| c.fid).collect()
+    } else {
+        fids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::prog::Call;
+    use core::test_util::target;
+
+    #[test]
+    fn classify_reason() {
+        let f = classify(
+            "In file included from <string>:4:\n/usr/include/stdio.h:366: error: invalid type",
+        );
+        assert_eq!(f.kind, Kind::Compile);
+        assert_eq!(f.error, "invalid type");
+        assert_eq!(f.subject, None);
+
+        let f = classify("<string>:12: error: too many arguments to function 'foo_open'");
+        assert_eq!(f.kind, Kind::Compile);
+        assert_eq!(f.error, "too many arguments to function '?'");
+        assert_eq!(f.subject.as_deref(), Some("foo_open"));
+
+        let f = classify("tcc: error: undefined symbol 'foo_open'\nFail to relocate compiled prog");
+        assert_eq!(f.kind, Kind::Link);
+        assert_eq!(classify("Time out").kind, Kind::Timeout);
+        let f = classify("Fail to execute: KcovOpenErr at 0x1234\n");
+        assert_eq!(f.kind, Kind::Runtime);
+        assert_eq!(f.error, "Fail to execute: KcovOpenErr at NxN");
+        assert_eq!(classify("").error, "unknown");
+    }
+
+    #[test]
+    fn analysis() {
+        let t = target();
+        let calls = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let mut a = Analysis::default();
+        a.add(
+            &calls(&["foo_open", "foo_ioctl"]),
+            "<string>:3: error: unknown type size 'foo_arg'",
+            Some(&t),
+        );
+        a.add(
+            &calls(&["foo_ioctl"]),
+            "<string>:9: error: unknown type size 'foo_arg'",
+            Some(&t),
+        );
+        a.add(
+            &calls(&["foo_open", "foo_open"]),
+            "<string>:5: error: too few arguments to function 'foo_open'",
+            Some(&t),
+        );
+        a.add(&calls(&["foo_open"]), "Time out", None);
+
+        assert_eq!(a.total, 4);
+        let size = &a.errors["[compile] unknown type size '?'"];
+        assert_eq!(size.count, 2);
+        assert_eq!(size.blamed["type foo_arg"], 2);
+        let args = &a.errors["[compile] too few arguments to function '?'"];
+        assert_eq!(args.blamed["fn foo_open"], 1);
+        assert_eq!(a.errors["[timeout] time out"].blamed["fn foo_open"], 1);
+        assert_eq!(a.fns["foo_open"], 3);
+        assert_eq!(a.fns["foo_ioctl"], 2);
+        assert!(a.report().contains("By function:"));
+    }
+
+    #[test]
+    fn quarantine() {
+        let t = target();
+        let g = t.iter_group().find(|g| g.ident == "Foo").unwrap();
+        let fid = |name: &str| g.fns[g.index_by_name(name).unwrap()].id;
+        let prog = |names: &[&str]| {
+            let mut p = Prog::new(g.id);
+            for &n in names {
+                p.add_call(Call::new(fid(n)));
+            }
+            p
+        };
+        let q = Quarantine::new(2);
+        let both = prog(&["foo_ioctl", "foo_open"]);
+        let ioctl = classify("<string>:7: error: too many arguments to function 'foo_ioctl'");
+        assert!(q.record(&both, Some(&ioctl), &t).is_empty());
+        assert_eq!(q.record(&both, Some(&ioctl), &t), vec![fid("foo_ioctl")]);
+        assert!(q.record(&both, Some(&ioctl), &t).is_empty());
+        // foo_open is never built but not to blame
+        assert_eq!(q.len(), 1);
+
+        let unknown = classify("<string>:3: error: invalid type");
+        let q2 = Quarantine::new(2);
+        assert!(q2.record(&prog(&["foo_open"]), None, &t).is_empty());
+        assert!(q2.record(&both, Some(&unknown), &t).is_empty());
+        assert_eq!(q2.record(&both, Some(&unknown), &t), vec![fid("foo_ioctl")]);
+
+        assert_eq!(q.trim(both).unwrap().calls.len(), 1);
+        assert!(q.trim(prog(&["foo_open", "foo_ioctl"])).is_err());
+        assert!(q.trim(prog(&["foo_open"])).is_ok());
+    }
+}
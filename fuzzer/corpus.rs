@@ -1,17 +1,81 @@
+//! Corpus of fuzzer and its on-disk store.
+//!
+//! `CorpusDB` is an append-only file: a file header (`DB_MAGIC`, `DB_VERSION`)
+//! followed by records. Each record is little-endian u32 length and u32 crc32
+//! of length and body, then body, which is bincode encoded `Entry`. Entries
+//! are appended as programs are admitted, so nothing is lost if fuzzer is
+//! killed. A record failing checksum is skipped by scanning for the next valid
+//! one, as its length can't be trusted. Only a torn record at tail, with no
+//! valid record after it, is truncated on open.
+
+use crate::feedback::{Block, Branch};
 use core::prog::Prog;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashSet;
-use std::iter::FromIterator;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::Mutex;
 
+/// Scheduling metadata of a prog in corpus, see `crate::schedule`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SeedMeta {
+    /// New blocks and branches found by prog when admitted
+    pub signal: usize,
+    /// Exec count of fuzzer when admitted
+    pub found_at: usize,
+    /// Times chosen as seed of mutation
+    pub mutated: usize,
+    /// Exec time of prog in microseconds, 0 if unknown
+    pub exec_us: u64,
+    /// Distance to target of directed fuzzing, see `Directed::distance`
+    pub distance: Option<usize>,
+}
+
+/// Progs of corpus with their metadata, `meta[i]` belongs to `progs[i]`.
+#[derive(Debug, Default)]
+pub struct Seeds {
+    pub progs: Vec<Prog>,
+    pub meta: Vec<SeedMeta>,
+    /// Hash of each prog, to drop duplicates without keeping a second copy.
+    hashes: HashSet<u64>,
+}
+
+impl Seeds {
+    /// Insert `p`, return its index if it's new.
+    pub fn insert(&mut self, p: Prog, meta: SeedMeta) -> Option<usize> {
+        let mut hasher = DefaultHasher::new();
+        p.hash(&mut hasher);
+        if !self.hashes.insert(hasher.finish()) {
+            return None;
+        }
+        self.progs.push(p);
+        self.meta.push(meta);
+        Some(self.progs.len() - 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.progs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.progs.is_empty()
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Corpus {
-    pub inner: Mutex<HashSet<Prog>>,
+    pub inner: Mutex<Seeds>,
 }
 
 impl Corpus {
-    pub async fn insert(&self, p: Prog) -> bool {
+    /// Insert `p`, return its index if it's new, progs are never removed.
+    pub async fn insert(&self, p: Prog, meta: SeedMeta) -> Option<usize> {
         let mut inner = self.inner.lock().await;
-        inner.insert(p)
+        inner.insert(p, meta)
     }
 
     pub async fn len(&self) -> usize {
@@ -27,6 +91,7 @@ impl Corpus {
     pub async fn dump(&self) -> bincode::Result<Vec<u8>> {
         let inner = self.inner.lock().await;
         let mut progs = inner
+            .progs
             .iter()
             .map(|p| {
                 let mut p = p.clone();
@@ -39,10 +104,255 @@ impl Corpus {
     }
 
     pub fn load(c: &[u8]) -> bincode::Result<Self> {
-        let mut progs: Vec<Prog> = bincode::deserialize(c)?;
-        progs.shrink_to_fit();
+        let progs: Vec<Prog> = bincode::deserialize(c)?;
+        let mut seeds = Seeds::default();
+        for p in progs {
+            seeds.insert(p, SeedMeta::default());
+        }
         Ok(Self {
-            inner: Mutex::new(HashSet::from_iter(progs)),
+            inner: Mutex::new(seeds),
         })
     }
 }
+
+/// "HCDB"
+pub const DB_MAGIC: u32 = 0x4843_4442;
+pub const DB_VERSION: u32 = 2;
+const DB_HEADER_LEN: usize = 8;
+const RECORD_HEADER_LEN: usize = 8;
+/// Records larger than this are treated as garbage.
+const MAX_RECORD_LEN: usize = 64 * 1024 * 1024;
+
+/// Admitted prog with its signal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub prog: Prog,
+    pub blocks: Vec<Block>,
+    pub branches: Vec<Branch>,
+}
+
+pub struct CorpusDB {
+    file: Mutex<File>,
+}
+
+impl CorpusDB {
+    /// Open or create db at `path`, return it with all valid entries.
+    pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<(Self, Vec<Entry>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .await?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).await?;
+
+        let entries = if buf.is_empty() {
+            file.write_all(&db_header()).await?;
+            file.sync_all().await?;
+            Vec::new()
+        } else {
+            let (entries, valid_len, skipped) = parse(&buf)?;
+            if skipped != 0 {
+                warn!("Corpus db: {} corrupted records skipped", skipped);
+            }
+            if valid_len != buf.len() {
+                warn!(
+                    "Corpus db: truncating {} bytes of torn record",
+                    buf.len() - valid_len
+                );
+                file.set_len(valid_len as u64).await?;
+            }
+            file.seek(SeekFrom::Start(valid_len as u64)).await?;
+            entries
+        };
+
+        Ok((
+            Self {
+                file: Mutex::new(file),
+            },
+            entries,
+        ))
+    }
+
+    /// Append `entry` and flush it to disk.
+    pub async fn append(&self, entry: &Entry) -> io::Result<()> {
+        let record = encode_record(entry)?;
+        let mut file = self.file.lock().await;
+        file.write_all(&record).await?;
+        file.sync_data().await
+    }
+}
+
+/// Read entries of db at `path` without modifying it, for tools.
+pub fn read_db<P: AsRef<Path>>(path: P) -> io::Result<Vec<Entry>> {
+    let buf = std::fs::read(path)?;
+    let (entries, _, _) = parse(&buf)?;
+    Ok(entries)
+}
+
+fn db_header() -> Vec<u8> {
+    let mut header = Vec::with_capacity(DB_HEADER_LEN);
+    header.extend_from_slice(&DB_MAGIC.to_le_bytes());
+    header.extend_from_slice(&DB_VERSION.to_le_bytes());
+    header
+}
+
+fn encode_record(entry: &Entry) -> io::Result<Vec<u8>> {
+    let body =
+        bincode::serialize(entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + body.len());
+    let len = (body.len() as u32).to_le_bytes();
+    record.extend_from_slice(&len);
+    record.extend_from_slice(&record_sum(&len, &body).to_le_bytes());
+    record.extend_from_slice(&body);
+    Ok(record)
+}
+
+fn record_sum(len: &[u8], body: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(len);
+    hasher.update(body);
+    hasher.finalize()
+}
+
+/// Body of record at `off` if it's complete and passes checksum.
+fn record_at(buf: &[u8], off: usize) -> Option<&[u8]> {
+    if buf.len() - off < RECORD_HEADER_LEN {
+        return None;
+    }
+    let len = read_u32(&buf[off..]) as usize;
+    let sum = read_u32(&buf[off + 4..]);
+    let start = off + RECORD_HEADER_LEN;
+    if len > MAX_RECORD_LEN || buf.len() - start < len {
+        return None;
+    }
+    let body = &buf[start..start + len];
+    if record_sum(&buf[off..off + 4], body) == sum {
+        Some(body)
+    } else {
+        None
+    }
+}
+
+/// Parse content of db, return entries, length without torn tail and number
+/// of skipped records.
+fn parse(buf: &[u8]) -> io::Result<(Vec<Entry>, usize, usize)> {
+    if buf.len() < DB_HEADER_LEN || buf[..DB_HEADER_LEN] != db_header()[..] {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad magic or unsupported version",
+        ));
+    }
+
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    let mut off = DB_HEADER_LEN;
+    while buf.len() - off >= RECORD_HEADER_LEN {
+        if let Some(body) = record_at(buf, off) {
+            match bincode::deserialize(body) {
+                Ok(entry) => entries.push(entry),
+                Err(_) => skipped += 1,
+            }
+            off += RECORD_HEADER_LEN + body.len();
+            continue;
+        }
+        // Length of a bad record is unreliable, resync at the next valid
+        // record. Without one, the bad record is a torn tail.
+        match (off + 1..buf.len()).find(|&o| record_at(buf, o).is_some()) {
+            Some(next) => {
+                skipped += 1;
+                off = next;
+            }
+            None => break,
+        }
+    }
+    Ok((entries, off, skipped))
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    let mut b = [0; 4];
+    b.copy_from_slice(&buf[..4]);
+    u32::from_le_bytes(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(gid: usize) -> Entry {
+        Entry {
+            prog: Prog::new(gid),
+            blocks: vec![Block::from(gid), Block::from(gid + 1)],
+            branches: vec![Branch::from((Block::from(gid), Block::from(gid + 1)))],
+        }
+    }
+
+    fn db(entries: &[Entry]) -> Vec<u8> {
+        let mut buf = db_header();
+        for e in entries {
+            buf.extend(encode_record(e).unwrap());
+        }
+        buf
+    }
+
+    #[test]
+    fn parse_all() {
+        let entries = vec![entry(0), entry(1), entry(2)];
+        let buf = db(&entries);
+        let (parsed, valid_len, skipped) = parse(&buf).unwrap();
+        assert_eq!(parsed, entries);
+        assert_eq!(valid_len, buf.len());
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn parse_torn_tail() {
+        let entries = vec![entry(0), entry(1)];
+        let buf = db(&entries);
+        let (_, full_len, _) = parse(&buf).unwrap();
+        let one_len = db(&entries[..1]).len();
+
+        let (parsed, valid_len, _) = parse(&buf[..full_len - 3]).unwrap();
+        assert_eq!(parsed, entries[..1]);
+        assert_eq!(valid_len, one_len);
+
+        let (parsed, valid_len, _) = parse(&buf[..one_len + 5]).unwrap();
+        assert_eq!(parsed, entries[..1]);
+        assert_eq!(valid_len, one_len);
+    }
+
+    #[test]
+    fn parse_bad_checksum() {
+        let entries = vec![entry(0), entry(1), entry(2)];
+        let mut buf = db(&entries);
+        let one_len = db(&entries[..1]).len();
+        buf[one_len + RECORD_HEADER_LEN] ^= 0xff;
+
+        let (parsed, valid_len, skipped) = parse(&buf).unwrap();
+        assert_eq!(parsed, vec![entry(0), entry(2)]);
+        assert_eq!(valid_len, buf.len());
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn parse_bad_length() {
+        let entries = vec![entry(0), entry(1), entry(2)];
+        let one_len = db(&entries[..1]).len();
+        for len in [u32::MAX, 3, 1024].iter() {
+            let mut buf = db(&entries);
+            buf[one_len..one_len + 4].copy_from_slice(&len.to_le_bytes());
+
+            let (parsed, valid_len, skipped) = parse(&buf).unwrap();
+            assert_eq!(parsed, vec![entry(0), entry(2)]);
+            assert_eq!(valid_len, buf.len());
+            assert_eq!(skipped, 1);
+        }
+    }
+
+    #[test]
+    fn parse_bad_magic() {
+        assert!(parse(b"HEALER\0\0").is_err());
+    }
+}
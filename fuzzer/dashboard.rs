@@ -0,0 +1,561 @@
+//! Embedded http dashboard for running campaign.
+//!
+//! `/` serves a single page polling the json api below:
+//! - `/api/stats`: exec rate, coverage, corpus size, case counts, success
+//!   rate of each strategy and progress of each group.
+//! - `/api/vms`: state of each vm.
+//! - `/api/crashes`, `/api/crashes/<id>`: crash list, console log and repro of one crash.
+//! - `/api/failed?n=<n>`: recent failed cases.
+//! - `/api/relations`: relation table of each group.
+//!
+//! `/metrics` exports the same numbers for prometheus, see `metrics`.
+//!
+//! Only plain `GET` over http/1.1 is handled, connection is closed after
+//! each response, or without one if request doesn't arrive in `IO_TIMEOUT`.
+
+use crate::metrics::Metrics;
+use crate::report::{CrashedCase, FailedCase};
+use crate::repro::ReproCase;
+use crate::stats::{StatSource, Stats};
+use chrono::prelude::*;
+use chrono::DateTime;
+use circular_queue::CircularQueue;
+use core::analyze::{RTable, Relation};
+use core::target::Target;
+use fots::types::GroupId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::process::exit;
+use std::sync::Arc;
+use tokio::fs::read;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::{delay_for, timeout, Duration, Instant};
+
+const INDEX: &str = include_str!("dashboard.html");
+/// Requests with larger header are rejected.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+/// Connections idle for longer are closed.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// Exec rate is averaged over last `SAMPLE_NUM` samples.
+const SAMPLE_NUM: usize = 12;
+const DEFAULT_FAILED_NUM: usize = 50;
+const MAX_FAILED_NUM: usize = 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DashboardConf {
+    /// Address to listen, e.g. `127.0.0.1:8000`
+    pub addr: String,
+}
+
+impl DashboardConf {
+    pub fn check(&self) {
+        if let Err(e) = self.addr.parse::<SocketAddr>() {
+            eprintln!(
+                "Config Error: invalid dashboard addr `{}`: {}",
+                self.addr, e
+            );
+            exit(exitcode::CONFIG)
+        }
+    }
+}
+
+pub struct Dashboard {
+    source: StatSource,
+    target: Arc<Target>,
+    rt: Arc<Mutex<HashMap<GroupId, RTable>>>,
+    start: Instant,
+    /// (time, exec) sampled every `SAMPLE_INTERVAL`.
+    samples: Mutex<CircularQueue<(Instant, usize)>>,
+    /// `IO_TIMEOUT` except in tests.
+    io_timeout: Duration,
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    /// Seconds since dashboard started
+    uptime: u64,
+    /// Execs per second
+    exec_rate: f64,
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+#[derive(Debug, Serialize)]
+struct CrashSummary {
+    id: usize,
+    title: String,
+    test_time: DateTime<Local>,
+    repo: bool,
+    file_name: String,
+}
+
+#[derive(Serialize)]
+struct CrashDetail {
+    #[serde(flatten)]
+    case: CrashedCase,
+    repro: Option<ReproCase>,
+}
+
+#[derive(Debug, Serialize)]
+struct GroupRelations {
+    group: String,
+    fns: Vec<String>,
+    /// (i, j): calling `fns[i]` may influence following `fns[j]`
+    relations: Vec<(usize, usize)>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json<T: Serialize>(v: &T) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body: serde_json::to_vec(v).unwrap(),
+        }
+    }
+
+    fn error(status: u16, msg: &str) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(&serde_json::json!({ "error": msg })).unwrap(),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Error",
+        };
+        let mut buf = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason,
+            self.content_type,
+            self.body.len()
+        )
+        .into_bytes();
+        buf.extend_from_slice(&self.body);
+        buf
+    }
+}
+
+impl Dashboard {
+    pub fn new(
+        source: StatSource,
+        target: Arc<Target>,
+        rt: Arc<Mutex<HashMap<GroupId, RTable>>>,
+    ) -> Self {
+        Self {
+            source,
+            target,
+            rt,
+            start: Instant::now(),
+            samples: Mutex::new(CircularQueue::with_capacity(SAMPLE_NUM)),
+            io_timeout: IO_TIMEOUT,
+        }
+    }
+
+    /// Serve requests from `listener` until shutdown.
+    pub async fn serve(
+        self: Arc<Self>,
+        listener: TcpListener,
+        mut shutdown: broadcast::Receiver<()>,
+    ) {
+        tokio::select! {
+            _ = shutdown.recv() => (),
+            _ = self.sample() => (),
+            _ = self.accept(listener) => (),
+        }
+    }
+
+    async fn sample(&self) {
+        loop {
+            let exec = self.source.stats().await.exec;
+            {
+                let mut samples = self.samples.lock().await;
+                samples.push((Instant::now(), exec));
+            }
+            delay_for(SAMPLE_INTERVAL).await;
+        }
+    }
+
+    async fn accept(self: &Arc<Self>, mut listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((conn, _)) => {
+                    let dashboard = self.clone();
+                    tokio::spawn(async move { dashboard.handle(conn).await });
+                }
+                Err(e) => warn!("Dashboard: fail to accept: {}", e),
+            }
+        }
+    }
+
+    async fn handle(&self, mut conn: TcpStream) {
+        let req = match timeout(self.io_timeout, read_request(&mut conn)).await {
+            Ok(req) => req,
+            Err(_) => return,
+        };
+        let resp = match req {
+            Some((method, target)) => self.route(&method, &target).await,
+            None => Response::error(400, "bad request"),
+        };
+        match timeout(self.io_timeout, conn.write_all(&resp.to_bytes())).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => warn!("Dashboard: fail to send response: {}", e),
+            Err(_) => warn!("Dashboard: time out sending response"),
+        }
+    }
+
+    async fn route(&self, method: &str, target: &str) -> Response {
+        if method != "GET" {
+            return Response::error(405, "only GET is supported");
+        }
+        let (path, query) = match target.find('?') {
+            Some(i) => (&target[..i], &target[i + 1..]),
+            None => (target, ""),
+        };
+
+        match path {
+            "/" | "/index.html" => Response {
+                status: 200,
+                content_type: "text/html; charset=utf-8",
+                body: INDEX.as_bytes().to_vec(),
+            },
+            "/api/stats" => Response::json(&self.status().await),
+            "/api/vms" => Response::json(&self.source.vms()),
+            "/api/crashes" => Response::json(&self.crashes().await),
+            "/api/failed" => {
+                let n = query_param(query, "n")
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .unwrap_or(DEFAULT_FAILED_NUM);
+                Response::json(&self.failed(n).await)
+            }
+            "/api/relations" => Response::json(&self.relations().await),
+            "/metrics" => Response {
+                status: 200,
+                content_type: "text/plain; version=0.0.4",
+                body: self.metrics().await.render().into_bytes(),
+            },
+            _ => match path.strip_prefix("/api/crashes/") {
+                Some(id) => match id.parse::<usize>() {
+                    Ok(id) => match self.crash(id).await {
+                        Some(detail) => Response::json(&detail),
+                        None => Response::error(404, "no such crash"),
+                    },
+                    Err(_) => Response::error(400, "invalid crash id"),
+                },
+                None => Response::error(404, "not found"),
+            },
+        }
+    }
+
+    async fn status(&self) -> Status {
+        let stats = self.source.stats().await;
+        let exec_rate = {
+            let samples = self.samples.lock().await;
+            let (since, exec) = samples
+                .asc_iter()
+                .next()
+                .cloned()
+                .unwrap_or((self.start, 0));
+            let elapsed = since.elapsed().as_secs_f64();
+            if elapsed > 0.0 {
+                stats.exec.saturating_sub(exec) as f64 / elapsed
+            } else {
+                0.0
+            }
+        };
+        Status {
+            uptime: self.start.elapsed().as_secs(),
+            exec_rate,
+            stats,
+        }
+    }
+
+    async fn metrics(&self) -> Metrics {
+        let status = self.status().await;
+        Metrics {
+            uptime: status.uptime,
+            exec_rate: status.exec_rate,
+            stats: status.stats,
+            vms: self.source.vms(),
+            crashes: self.source.record.crash_counts().await,
+        }
+    }
+
+    async fn crashes(&self) -> Vec<CrashSummary> {
+        self.source
+            .record
+            .crashes()
+            .await
+            .into_iter()
+            .map(|c| CrashSummary {
+                file_name: c.file_name(),
+                id: c.meta.id,
+                title: c.meta.title,
+                test_time: c.meta.test_time,
+                repo: c.repo,
+            })
+            .collect()
+    }
+
+    async fn crash(&self, id: usize) -> Option<CrashDetail> {
+        let case = self
+            .source
+            .record
+            .crashes()
+            .await
+            .into_iter()
+            .find(|c| c.meta.id == id)?;
+        let repro = read(format!("./crashes/{}.repro", case.file_name()))
+            .await
+            .ok()
+            .and_then(|r| serde_json::from_slice(&r).ok());
+        Some(CrashDetail { case, repro })
+    }
+
+    async fn failed(&self, n: usize) -> Vec<FailedCase> {
+        self.source
+            .record
+            .recent_failed(usize::min(n, MAX_FAILED_NUM))
+            .await
+    }
+
+    async fn relations(&self) -> Vec<GroupRelations> {
+        let rt = self.rt.lock().await;
+        let mut groups = rt
+            .iter()
+            .map(|(gid, r)| {
+                let g = &self.target.groups[gid];
+                GroupRelations {
+                    group: g.ident.clone(),
+                    fns: g.iter_fn().map(|f| f.dec_name.clone()).collect(),
+                    relations: r
+                        .indexed_iter()
+                        .filter(|(_, r)| **r == Relation::Some)
+                        .map(|((i, j), _)| (j, i))
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        groups.sort_by(|a, b| a.group.cmp(&b.group));
+        groups
+    }
+}
+
+/// Read request head, return method and request target.
+async fn read_request(conn: &mut TcpStream) -> Option<(String, String)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = conn.read(&mut chunk).await.ok()?;
+        if n == 0 || buf.len() + n > MAX_REQUEST_LEN {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&buf);
+    let mut request_line = head.lines().next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?.to_string();
+    if !request_line.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    Some((method, target))
+}
+
+fn query_param<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::Corpus;
+    use crate::feedback::FeedBack;
+    use crate::report::TestCaseRecord;
+    use crate::schedule::Schedule;
+    use crate::stats::VmStat;
+    use core::analyze::static_analyze;
+    use core::gen::gen_seq;
+    use core::test_util::target;
+    use executor::Reason;
+    use serde_json::Value;
+    use std::future::Future;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::runtime::Runtime;
+
+    async fn start() -> (
+        SocketAddr,
+        Arc<TestCaseRecord>,
+        Arc<Target>,
+        broadcast::Sender<()>,
+    ) {
+        let target = Arc::new(target());
+        let record = Arc::new(TestCaseRecord::new(target.clone()));
+        let source = StatSource {
+            corpus: Arc::new(Corpus::default()),
+            feedback: Arc::new(FeedBack::default()),
+            candidates: Arc::new(Default::default()),
+            record: record.clone(),
+            exec: Arc::new(AtomicUsize::new(0)),
+            vms: Arc::new(vec![
+                Arc::new(VmStat::default()),
+                Arc::new(VmStat::default()),
+            ]),
+            schedule: Arc::new(Schedule::new(None, Vec::new())),
+        };
+        let rt = Arc::new(Mutex::new(static_analyze(&target)));
+        let mut dashboard = Dashboard::new(source, target.clone(), rt);
+        dashboard.io_timeout = Duration::from_millis(100);
+        let dashboard = Arc::new(dashboard);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = broadcast::channel(1);
+        tokio::spawn(dashboard.serve(listener, rx));
+        (addr, record, target, tx)
+    }
+
+    async fn request(addr: SocketAddr, req: &str) -> (u16, String) {
+        let mut conn = TcpStream::connect(addr).await.unwrap();
+        conn.write_all(req.as_bytes()).await.unwrap();
+        let mut resp = String::new();
+        conn.read_to_string(&mut resp).await.unwrap();
+        let status = resp[9..12].parse().unwrap();
+        let body = resp.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    async fn get(addr: SocketAddr, path: &str) -> (u16, Value) {
+        let (status, body) = request(
+            addr,
+            &format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path),
+        )
+        .await;
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    fn run<F: Future>(f: F) -> F::Output {
+        Runtime::new().unwrap().block_on(f)
+    }
+
+    #[test]
+    fn stats_and_vms() {
+        run(async {
+            let (addr, _, _, _shutdown) = start().await;
+
+            let (status, stats) = get(addr, "/api/stats").await;
+            assert_eq!(status, 200);
+            assert_eq!(stats["exec"], 0);
+            assert_eq!(stats["corpus"], 0);
+            assert!(stats["exec_rate"].is_number());
+
+            let (status, vms) = get(addr, "/api/vms").await;
+            assert_eq!(status, 200);
+            assert_eq!(vms.as_array().unwrap().len(), 2);
+            assert_eq!(vms[1]["id"], 1);
+            assert_eq!(vms[1]["state"], "booting");
+        })
+    }
+
+    #[test]
+    fn failed_and_crashes() {
+        run(async {
+            let (addr, record, target, _shutdown) = start().await;
+            let g = target.iter_group().find(|g| g.ident == "Foo").unwrap();
+            let seq = [
+                g.index_by_name("foo_open").unwrap(),
+                g.index_by_name("foo_read").unwrap(),
+            ];
+            for i in 0..3 {
+                let p = gen_seq(&seq, g.id, &target, &Default::default());
+                record
+                    .insert_failed(p, Reason(format!("reason {}", i)))
+                    .await;
+            }
+
+            let (status, failed) = get(addr, "/api/failed?n=2").await;
+            assert_eq!(status, 200);
+            assert_eq!(failed.as_array().unwrap().len(), 2);
+            assert_eq!(failed[0]["reason"], "reason 2");
+
+            let (status, crashes) = get(addr, "/api/crashes").await;
+            assert_eq!(status, 200);
+            assert!(crashes.as_array().unwrap().is_empty());
+            assert_eq!(get(addr, "/api/crashes/0").await.0, 404);
+            assert_eq!(get(addr, "/api/crashes/x").await.0, 400);
+        })
+    }
+
+    #[test]
+    fn relations() {
+        run(async {
+            let (addr, _, _, _shutdown) = start().await;
+            let (status, groups) = get(addr, "/api/relations").await;
+            assert_eq!(status, 200);
+            assert_eq!(groups[0]["group"], "Bar");
+            let foo = &groups[1];
+            assert_eq!(foo["group"], "Foo");
+            assert_eq!(foo["fns"][0], "foo_open");
+            assert_eq!(foo["fns"][3], "foo_read");
+            // foo_open produces fd consumed by foo_read.
+            let relations = foo["relations"].as_array().unwrap();
+            assert!(relations.contains(&serde_json::json!([0, 3])));
+        })
+    }
+
+    #[test]
+    fn page_and_errors() {
+        run(async {
+            let (addr, _, _, _shutdown) = start().await;
+            let (status, body) = request(addr, "GET / HTTP/1.1\r\n\r\n").await;
+            assert_eq!(status, 200);
+            assert!(body.contains("<html"));
+
+            let (status, body) = request(addr, "GET /metrics HTTP/1.1\r\n\r\n").await;
+            assert_eq!(status, 200);
+            assert!(body.contains("healer_exec_total 0\n"));
+            assert!(body.contains("healer_vm_restarts_total{vm=\"1\"} 0\n"));
+
+            assert_eq!(get(addr, "/api/nothing").await.0, 404);
+            assert_eq!(
+                request(addr, "POST /api/stats HTTP/1.1\r\n\r\n").await.0,
+                405
+            );
+            assert_eq!(request(addr, "garbage\r\n\r\n").await.0, 400);
+        })
+    }
+
+    #[test]
+    fn idle_connection() {
+        run(async {
+            let (addr, _, _, _shutdown) = start().await;
+            let mut conn = TcpStream::connect(addr).await.unwrap();
+            conn.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+            let mut resp = String::new();
+            let read = timeout(Duration::from_secs(5), conn.read_to_string(&mut resp));
+            assert!(read.await.unwrap().is_ok());
+            assert!(resp.is_empty());
+        })
+    }
+}
@@ -0,0 +1,126 @@
+//! Directed fuzzing toward one interface, e.g. syscall touched by a patch.
+//!
+//! Part of generated progs end with call of target, see `core::gen::gen_prog_for`,
+//! and progs of corpus whose calls are close to target in relation table get
+//! more energy as seed of mutation, see `schedule::energy`.
+
+use core::analyze::{distances, RTable};
+use core::prog::Prog;
+use core::target::Target;
+use fots::types::{FnId, GroupId};
+use rand::prelude::*;
+use std::collections::HashMap;
+use std::process::exit;
+
+const DEFAULT_RATIO: f64 = 0.5;
+/// Calls farther than this, or unrelated to target, count as this far.
+const MAX_DISTANCE: usize = 8;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DirectedConf {
+    /// Name of target interface
+    pub target: String,
+    /// Ratio of generated progs ending with target, 0.5 by default
+    pub ratio: Option<f64>,
+}
+
+impl DirectedConf {
+    pub fn check(&self) {
+        if self.target.is_empty() {
+            eprintln!("Config Error: target of directed fuzzing is empty");
+            exit(exitcode::CONFIG)
+        }
+        if let Some(ratio) = self.ratio {
+            if !(0.0..=1.0).contains(&ratio) {
+                eprintln!("Config Error: directed ratio should be in [0, 1]");
+                exit(exitcode::CONFIG)
+            }
+        }
+    }
+}
+
+pub struct Directed {
+    pub fid: FnId,
+    ratio: f64,
+    /// Distance of each interface of target group to target.
+    dist: HashMap<FnId, usize>,
+}
+
+impl Directed {
+    /// Resolve target by name, distances come from static relations `rs`.
+    pub fn new(
+        conf: &DirectedConf,
+        t: &Target,
+        rs: &HashMap<GroupId, RTable>,
+    ) -> Result<Self, String> {
+        let mut found = t
+            .iter_group()
+            .filter_map(|g| g.index_by_name(&conf.target).map(|i| (g, i)));
+        let (g, i) = found
+            .next()
+            .ok_or_else(|| format!("no interface named `{}`", conf.target))?;
+        if found.next().is_some() {
+            return Err(format!("`{}` is declared by several groups", conf.target));
+        }
+
+        let dist = distances(&rs[&g.id], i)
+            .into_iter()
+            .zip(g.iter_fn())
+            .filter_map(|(d, f)| d.map(|d| (f.id, d)))
+            .collect();
+        Ok(Self {
+            fid: g.fns[i].id,
+            ratio: conf.ratio.unwrap_or(DEFAULT_RATIO),
+            dist,
+        })
+    }
+
+    /// Whether next generated prog should end with target.
+    pub fn should_direct(&self) -> bool {
+        thread_rng().gen::<f64>() < self.ratio
+    }
+
+    /// Distance of the call closest to target.
+    pub fn distance(&self, p: &Prog) -> usize {
+        p.calls
+            .iter()
+            .filter_map(|c| self.dist.get(&c.fid))
+            .min()
+            .map_or(MAX_DISTANCE, |&d| d.min(MAX_DISTANCE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::analyze::static_analyze;
+    use core::test_util::target;
+
+    #[test]
+    fn distance() {
+        let t = target();
+        let rs = static_analyze(&t);
+        let conf = |target: &str| DirectedConf {
+            target: target.to_string(),
+            ratio: None,
+        };
+        assert!(Directed::new(&conf("foo_nothing"), &t, &rs).is_err());
+        let d = Directed::new(&conf("foo_read"), &t, &rs).unwrap();
+
+        let g = t.iter_group().find(|g| g.ident == "Foo").unwrap();
+        let prog = |names: &[&str]| {
+            let mut p = Prog::new(g.id);
+            for n in names {
+                let f = &g.fns[g.index_by_name(n).unwrap()];
+                p.add_call(core::prog::Call::new(f.id));
+            }
+            p
+        };
+        let read = prog(&["foo_open", "foo_read"]);
+        let open = prog(&["foo_open"]);
+        let stat = prog(&["foo_stat"]);
+        assert_eq!(d.distance(&read), 0);
+        assert_eq!(d.distance(&open), 1);
+        assert_eq!(d.distance(&stat), MAX_DISTANCE);
+    }
+}
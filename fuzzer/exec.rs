@@ -1,20 +1,32 @@
 use crate::guest;
 use crate::guest::{Crash, Guest};
+use crate::stats::{VmStat, VmState};
 use crate::utils::cli::{App, Arg, OptVal};
 use crate::utils::free_ipv4_port;
 use crate::Config;
+use circular_queue::CircularQueue;
 use core::c::to_prog;
 use core::prog::Prog;
 use core::target::Target;
-use executor::transfer::{async_recv_result, async_send};
+use executor::probe::Probe;
+use executor::transfer::{
+    async_accept_handshake, async_recv, async_recv_result, async_send, target_hash,
+};
+use executor::transport::Transport;
 use executor::{ExecResult, Reason};
 use std::env::temp_dir;
+use std::io;
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::process::exit;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 use tokio::fs::write;
-use tokio::io::AsyncReadExt;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
 use tokio::process::Child;
+use tokio::stream::{Stream, StreamExt};
 use tokio::sync::oneshot;
 use tokio::time::{delay_for, timeout, Duration};
 
@@ -26,6 +38,20 @@ pub struct ExecutorConf {
     pub concurrency: bool,
     pub memleak_check: bool,
     pub script_mode: bool,
+    /// Fuzz user-space library, coverage comes from SanitizerCoverage
+    #[serde(default)]
+    pub sancov: bool,
+    /// Path to search libraries in guest
+    pub lib_path: Option<Vec<String>>,
+    /// Let executor compress large coverage results
+    #[serde(default)]
+    pub compress: bool,
+    /// Channel to executor: tcp, stdio, unix or virtio-serial
+    #[serde(default)]
+    pub transport: Transport,
+    /// Don't probe availability of calls at startup
+    #[serde(default)]
+    pub no_probe: bool,
 }
 
 impl ExecutorConf {
@@ -53,8 +79,14 @@ impl ExecutorConf {
     }
 }
 
+/// Time executor may take to probe all calls.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(300);
+
 pub struct Executor {
     inner: ExecutorImpl,
+    /// Last executed progs, used to reproduce crash.
+    history: CircularQueue<Prog>,
+    stat: Arc<VmStat>,
 }
 
 enum ExecutorImpl {
@@ -63,46 +95,84 @@ enum ExecutorImpl {
 }
 
 impl Executor {
-    pub fn new(cfg: &Config) -> Self {
+    pub fn new(cfg: &Config, stat: Arc<VmStat>) -> Self {
         let inner = if cfg.executor.script_mode {
-            ExecutorImpl::Scripy(ScriptExecutor::new(cfg))
+            ExecutorImpl::Scripy(ScriptExecutor::new(cfg, stat.clone()))
         } else {
-            ExecutorImpl::Linux(LinuxExecutor::new(cfg))
+            ExecutorImpl::Linux(LinuxExecutor::new(cfg, stat.clone()))
         };
-        Self { inner }
+        let history = cfg.repro.clone().unwrap_or_default().history;
+        Self {
+            inner,
+            history: CircularQueue::with_capacity(history),
+            stat,
+        }
     }
 
     pub async fn start(&mut self) {
+        self.stat.set_state(VmState::Booting);
         match self.inner {
             ExecutorImpl::Linux(ref mut e) => e.start().await,
             ExecutorImpl::Scripy(ref mut e) => e.start().await,
         }
+        self.stat.set_state(VmState::Fuzzing);
     }
 
     pub async fn exec(&mut self, p: &Prog, t: &Target) -> Result<ExecResult, Option<Crash>> {
+        self.history.push(p.clone());
+        self.stat.exec.fetch_add(1, Ordering::SeqCst);
         match self.inner {
             ExecutorImpl::Linux(ref mut e) => e.exec(p).await,
             ExecutorImpl::Scripy(ref mut e) => e.exec(p, t).await,
         }
     }
+
+    /// Start with executor probing availability of calls, `None` if not
+    /// supported by script mode.
+    pub async fn start_probe(&mut self) -> Option<Probe> {
+        if let ExecutorImpl::Linux(ref mut e) = self.inner {
+            e.probe = true;
+        }
+        self.start().await;
+        match self.inner {
+            ExecutorImpl::Linux(ref mut e) => e.probed.take(),
+            ExecutorImpl::Scripy(_) => None,
+        }
+    }
+
+    /// Executed progs, oldest first.
+    pub fn history(&self) -> Vec<Prog> {
+        self.history.asc_iter().cloned().collect()
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear()
+    }
+
+    pub fn stat(&self) -> &VmStat {
+        &self.stat
+    }
 }
 
 struct ScriptExecutor {
     path_on_host: PathBuf,
     guest: Guest,
+    stat: Arc<VmStat>,
 }
 
 impl ScriptExecutor {
-    pub fn new(cfg: &Config) -> Self {
+    pub fn new(cfg: &Config, stat: Arc<VmStat>) -> Self {
         let guest = Guest::new(cfg);
 
         Self {
             path_on_host: cfg.executor.path.clone(),
             guest,
+            stat,
         }
     }
 
     pub async fn start(&mut self) {
+        self.stat.boots.fetch_add(1, Ordering::SeqCst);
         self.guest.boot().await;
     }
 
@@ -167,16 +237,30 @@ struct LinuxExecutor {
     guest: Guest,
     port: u16,
     exec_handle: Option<Child>,
-    conn: Option<TcpStream>,
+    conn: Option<Box<dyn Conn>>,
+    transport: Transport,
+    /// Socket listened for executor with unix transport.
+    unix_path: PathBuf,
     concurrency: bool,
     memleak_check: bool,
+    sancov: bool,
+    lib_path: Vec<String>,
+    compress: bool,
     executor_bin_path: PathBuf,
     target_path: PathBuf,
+    target_hash: [u8; 16],
+    /// Regenerated every boot, executor must present it in handshake.
+    token: u64,
+    /// Ask next started executor to probe calls.
+    probe: bool,
+    /// Result of last probe, see `executor::probe`.
+    probed: Option<Probe>,
     host_ip: String,
+    stat: Arc<VmStat>,
 }
 
 impl LinuxExecutor {
-    pub fn new(cfg: &Config) -> Self {
+    pub fn new(cfg: &Config, stat: Arc<VmStat>) -> Self {
         let guest = Guest::new(cfg);
         let port = free_ipv4_port()
             .unwrap_or_else(|| exits!(exitcode::TEMPFAIL, "No Free port for executor driver"));
@@ -186,36 +270,115 @@ impl LinuxExecutor {
             .as_ref()
             .map(String::from)
             .unwrap_or_else(|| String::from(guest::LINUX_QEMU_HOST_IP_ADDR));
+        let target = std::fs::read(&cfg.fots_bin).unwrap_or_else(|e| {
+            exits!(
+                exitcode::IOERR,
+                "Fail to read {}: {}",
+                cfg.fots_bin.display(),
+                e
+            )
+        });
 
         Self {
             guest,
             port,
             exec_handle: None,
             conn: None,
+            transport: cfg.executor.transport,
+            unix_path: temp_dir().join(format!(
+                "healer-executor-{}-{}.sock",
+                std::process::id(),
+                port
+            )),
 
             concurrency: cfg.executor.concurrency,
             memleak_check: cfg.executor.memleak_check,
+            sancov: cfg.executor.sancov,
+            lib_path: cfg.executor.lib_path.clone().unwrap_or_default(),
+            compress: cfg.executor.compress,
             executor_bin_path: cfg.executor.path.clone(),
             target_path: PathBuf::from(&cfg.fots_bin),
+            target_hash: target_hash(&target),
+            token: 0,
+            probe: false,
+            probed: None,
             host_ip,
+            stat,
         }
     }
 
     pub async fn start(&mut self) {
         // handle should be set to kill on drop
         self.exec_handle = None;
+        self.stat.boots.fetch_add(1, Ordering::SeqCst);
+        self.token = rand::random();
         self.guest.boot().await;
 
         self.start_executer().await
     }
 
     pub async fn start_executer(&mut self) {
-        use tokio::io::ErrorKind::*;
-
         self.exec_handle = None;
+        self.conn = None;
         let target = self.guest.copy(&self.target_path).await;
 
-        let (tx, rx) = oneshot::channel();
+        let mut executor = App::new(self.executor_bin_path.to_str().unwrap());
+        executor
+            .arg(Arg::new_opt("-t", OptVal::normal(target.to_str().unwrap())))
+            .arg(Arg::new_opt(
+                "-T",
+                OptVal::normal(&self.transport.to_string()),
+            ));
+        if self.memleak_check {
+            executor.arg(Arg::new_flag("-m"));
+        }
+        if self.concurrency {
+            executor.arg(Arg::new_flag("-c"));
+        }
+        if self.sancov {
+            executor.arg(Arg::new_flag("-s"));
+        }
+        if self.compress {
+            executor.arg(Arg::new_flag("-z"));
+        }
+        executor.arg(Arg::new_opt("-k", OptVal::normal(&self.token.to_string())));
+        for path in self.lib_path.iter() {
+            executor.arg(Arg::new_opt("-L", OptVal::normal(path)));
+        }
+
+        let conn = match self.transport {
+            Transport::Tcp => self.connect_tcp(executor).await,
+            Transport::Unix => self.connect_unix(executor).await,
+            Transport::Stdio => self.connect_stdio(executor).await,
+            Transport::VirtioSerial => self.connect_serial(executor).await,
+        };
+        self.conn = Some(conn);
+        if self.probe {
+            self.probe = false;
+            self.recv_probe().await;
+        }
+    }
+
+    async fn recv_probe(&mut self) {
+        let conn = self.conn.as_mut().unwrap();
+        match timeout(PROBE_TIMEOUT, async_recv(conn)).await {
+            Err(_) => {
+                self.exec_handle = None;
+                eprintln!("Time out: wait executor probe");
+                exit(1)
+            }
+            Ok(Err(e)) => {
+                self.exec_handle = None;
+                eprintln!("Fail to recv probe result: {}", e);
+                exit(exitcode::PROTOCOL)
+            }
+            Ok(Ok(probe)) => self.probed = Some(probe),
+        }
+    }
+
+    async fn connect_tcp(&mut self, mut executor: App) -> Box<dyn Conn> {
+        use tokio::io::ErrorKind::*;
+
         let mut retry = 0;
         let mut listener;
         loop {
@@ -235,47 +398,110 @@ impl LinuxExecutor {
             };
             break;
         }
-        let host_addr = listener.local_addr().unwrap();
+        let host_addr = listener.local_addr().unwrap().to_string();
 
+        let (tx, rx) = oneshot::channel();
+        let hash = self.target_hash;
+        let token = self.token;
+        let probe = self.probe;
         tokio::spawn(async move {
-            match listener.accept().await {
-                Ok((conn, _addr)) => {
-                    tx.send(conn).unwrap();
-                }
-                Err(e) => {
-                    eprintln!("Executor driver: fail to get client: {}", e);
-                    exit(exitcode::OSERR);
-                }
-            }
+            let conn = accept_executor(listener.incoming(), hash, token, probe).await;
+            tx.send(conn).unwrap();
         });
 
-        let mut executor = App::new(self.executor_bin_path.to_str().unwrap());
-        executor
-            .arg(Arg::new_opt("-t", OptVal::normal(target.to_str().unwrap())))
-            .arg(Arg::new_opt(
-                "-a",
-                OptVal::normal(&format!(
-                    "{}:{}",
-                    guest::LINUX_QEMU_USER_NET_HOST_IP_ADDR,
-                    self.port
-                )),
-            ));
-        if self.memleak_check {
-            executor.arg(Arg::new_flag("-m"));
-        }
-        if self.concurrency {
-            executor.arg(Arg::new_flag("-c"));
-        }
+        executor.arg(Arg::new_opt(
+            "-a",
+            OptVal::normal(&format!("{}:{}", self.guest.host_ip(), self.port)),
+        ));
+        self.exec_handle = Some(self.guest.run_cmd(&executor).await);
+        Box::new(self.wait_conn(rx, &host_addr).await)
+    }
 
+    async fn connect_unix(&mut self, mut executor: App) -> Box<dyn Conn> {
+        let _ = std::fs::remove_file(&self.unix_path);
+        let mut listener = UnixListener::bind(&self.unix_path).unwrap_or_else(|e| {
+            exits!(
+                exitcode::OSERR,
+                "Fail to listen on {}: {}",
+                self.unix_path.display(),
+                e
+            )
+        });
+
+        let (tx, rx) = oneshot::channel();
+        let hash = self.target_hash;
+        let token = self.token;
+        let probe = self.probe;
+        tokio::spawn(async move {
+            let conn = accept_executor(listener.incoming(), hash, token, probe).await;
+            tx.send(conn).unwrap();
+        });
+
+        executor.arg(Arg::new_opt(
+            "-a",
+            OptVal::normal(self.unix_path.to_str().unwrap()),
+        ));
         self.exec_handle = Some(self.guest.run_cmd(&executor).await);
-        self.conn = match timeout(Duration::new(32, 0), rx).await {
+        let addr = self.unix_path.display().to_string();
+        Box::new(self.wait_conn(rx, &addr).await)
+    }
+
+    async fn connect_stdio(&mut self, executor: App) -> Box<dyn Conn> {
+        let mut handle = self.guest.run_cmd(&executor).await;
+        let mut conn = Duplex {
+            r: handle.stdout.take().unwrap(),
+            w: handle.stdin.take().unwrap(),
+        };
+        self.exec_handle = Some(handle);
+        self.handshake(&mut conn, "stdio").await;
+        Box::new(conn)
+    }
+
+    async fn connect_serial(&mut self, executor: App) -> Box<dyn Conn> {
+        let path = self
+            .guest
+            .serial_path()
+            .unwrap_or_else(|| exits!(exitcode::CONFIG, "Guest has no virtio-serial port"));
+        let mut conn = UnixStream::connect(&path).await.unwrap_or_else(|e| {
+            exits!(
+                exitcode::OSERR,
+                "Fail to connect to virtio-serial {}: {}",
+                path.display(),
+                e
+            )
+        });
+        self.exec_handle = Some(self.guest.run_cmd(&executor).await);
+        self.handshake(&mut conn, &path.display().to_string()).await;
+        Box::new(conn)
+    }
+
+    async fn wait_conn<C>(&mut self, rx: oneshot::Receiver<C>, addr: &str) -> C {
+        match timeout(Duration::new(32, 0), rx).await {
             Err(_) => {
                 self.exec_handle = None;
-                eprintln!("Time out: wait executor connection {}", host_addr);
+                eprintln!("Time out: wait executor connection {}", addr);
                 exit(1)
             }
-            Ok(conn) => Some(conn.unwrap()),
-        };
+            Ok(conn) => conn.unwrap(),
+        }
+    }
+
+    /// Handshake on channel that can't be reached by others, so failure is fatal.
+    async fn handshake<C: Conn>(&mut self, conn: &mut C, addr: &str) {
+        let hs = async_accept_handshake(conn, &self.target_hash, self.token, self.probe);
+        match timeout(Duration::new(32, 0), hs).await {
+            Err(_) => {
+                self.exec_handle = None;
+                eprintln!("Time out: wait executor handshake {}", addr);
+                exit(1)
+            }
+            Ok(Err(e)) => {
+                self.exec_handle = None;
+                eprintln!("Fail to handshake with executor {}: {}", addr, e);
+                exit(exitcode::PROTOCOL)
+            }
+            Ok(Ok(())) => (),
+        }
     }
 
     pub async fn exec(&mut self, p: &Prog) -> Result<ExecResult, Option<Crash>> {
@@ -283,7 +509,7 @@ impl LinuxExecutor {
         assert!(self.conn.is_some());
         if let Err(e) = timeout(
             Duration::new(15, 0),
-            async_send(p, self.conn.as_mut().unwrap()),
+            async_send(p, self.conn.as_mut().unwrap(), false),
         )
         .await
         {
@@ -335,8 +561,10 @@ impl LinuxExecutor {
                     return Err(self.guest.try_collect_crash().await);
                 } else {
                     let mut handle = self.exec_handle.take().unwrap();
-                    let mut stdout = handle.stdout.take().unwrap();
+                    // stdout is already taken as conn with stdio transport
+                    let stdout = handle.stdout.take();
                     let mut stderr = handle.stderr.take().unwrap();
+                    self.conn = None;
                     handle.await.unwrap_or_else(|e| {
                         exits!(exitcode::OSERR, "Fail to wait executor handle:{}", e)
                     });
@@ -344,13 +572,16 @@ impl LinuxExecutor {
                     let mut err = Vec::new();
                     stderr.read_to_end(&mut err).await.unwrap();
                     let mut out = Vec::new();
-                    stdout.read_to_end(&mut out).await.unwrap();
+                    if let Some(mut stdout) = stdout {
+                        stdout.read_to_end(&mut out).await.unwrap();
+                    }
 
                     warn!(
                         "Executor: Connection lost. STDOUT:{}. STDERR: {}",
                         String::from_utf8(out).unwrap(),
                         String::from_utf8(err).unwrap()
                     );
+                    self.stat.reconnects.fetch_add(1, Ordering::SeqCst);
                     self.start_executer().await;
                 }
             }
@@ -359,3 +590,142 @@ impl LinuxExecutor {
         Ok(ExecResult::Ok(Vec::new()))
     }
 }
+
+/// Connection to executor, see `executor::transport`.
+trait Conn: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Conn for T {}
+
+/// Accept executor that passes handshake, others are dropped.
+async fn accept_executor<C, S>(mut incoming: S, hash: [u8; 16], token: u64, probe: bool) -> C
+where
+    C: Conn,
+    S: Stream<Item = io::Result<C>> + Unpin,
+{
+    while let Some(conn) = incoming.next().await {
+        match conn {
+            Ok(mut conn) => {
+                let hs = async_accept_handshake(&mut conn, &hash, token, probe);
+                match timeout(Duration::new(5, 0), hs).await {
+                    Ok(Ok(())) => return conn,
+                    Ok(Err(e)) => warn!("Executor driver: reject client: {}", e),
+                    Err(_) => warn!("Executor driver: client handshake timeout"),
+                }
+            }
+            Err(e) => {
+                eprintln!("Executor driver: fail to get client: {}", e);
+                exit(exitcode::OSERR);
+            }
+        }
+    }
+    unreachable!()
+}
+
+/// Join stdout and stdin of executor as one conn.
+struct Duplex<R, W> {
+    r: R,
+    w: W,
+}
+
+impl<R: AsyncRead + Unpin, W: Unpin> AsyncRead for Duplex<R, W> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.r).poll_read(cx, buf)
+    }
+}
+
+impl<R: Unpin, W: AsyncWrite + Unpin> AsyncWrite for Duplex<R, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.w).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.w).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.w).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use executor::transfer::{handshake, send, Handshake, HandshakeAck};
+    use executor::transport::Conn as ExecutorConn;
+    use std::future::Future;
+    use std::thread;
+    use tokio::io::split;
+    use tokio::runtime::Runtime;
+
+    const HASH: [u8; 16] = [1; 16];
+    const TOKEN: u64 = 0x1234;
+
+    fn run<F: Future>(f: F) -> F::Output {
+        Runtime::new().unwrap().block_on(f)
+    }
+
+    #[test]
+    fn unix_loopback() {
+        let path = temp_dir().join(format!("healer-exec-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        run(async {
+            let mut listener = UnixListener::bind(&path).unwrap();
+            let addr = path.to_str().unwrap().to_string();
+            let executor = thread::spawn(move || {
+                let mut conn = ExecutorConn::connect(Transport::Unix, &addr).unwrap();
+                let ack = handshake(&mut conn, &Handshake::new(HASH, TOKEN)).unwrap();
+                assert!(ack.probe);
+                send(&"ping".to_string(), &mut conn, false).unwrap();
+            });
+
+            let mut conn: UnixStream =
+                accept_executor(listener.incoming(), HASH, TOKEN, true).await;
+            let msg: String = async_recv(&mut conn).await.unwrap();
+            assert_eq!(msg, "ping");
+            executor.join().unwrap();
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stdio_duplex() {
+        run(async {
+            let mut conns = Vec::new();
+            let mut executors = Vec::new();
+            for &token in [TOKEN + 1, TOKEN].iter() {
+                let (fuzzer, mut executor) = UnixStream::pair().unwrap();
+                let (r, w) = split(fuzzer);
+                conns.push(Ok(Duplex { r, w }));
+                executors.push(tokio::spawn(async move {
+                    async_send(&Handshake::new(HASH, token), &mut executor, false)
+                        .await
+                        .unwrap();
+                    let ack: HandshakeAck = async_recv(&mut executor).await.unwrap();
+                    if ack.accepted {
+                        async_send(&token, &mut executor, false).await.unwrap();
+                    }
+                    ack.accepted
+                }));
+            }
+
+            // executor with wrong token is dropped
+            let incoming = tokio::stream::iter(conns);
+            let mut conn = accept_executor(incoming, HASH, TOKEN, false).await;
+            let token: u64 = async_recv(&mut conn).await.unwrap();
+            assert_eq!(token, TOKEN);
+            let mut accepted = Vec::new();
+            for e in executors {
+                accepted.push(e.await.unwrap());
+            }
+            assert_eq!(accepted, [false, true]);
+        });
+    }
+}
@@ -1,8 +1,13 @@
-use std::collections::HashSet;
-use std::iter::Extend;
-use tokio::sync::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
 
-#[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Ord, Eq)]
+/// Times a block or branch may be seen inconsistently by triage before it is
+/// taken as noise, e.g. coverage of interrupts, RCU or timers.
+pub const FLAKY_THRESHOLD: usize = 2;
+
+#[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Ord, Eq, Serialize, Deserialize)]
 pub struct Block(usize);
 
 impl From<usize> for Block {
@@ -11,7 +16,13 @@ impl From<usize> for Block {
     }
 }
 
-#[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Ord, Eq)]
+impl From<Block> for usize {
+    fn from(b: Block) -> Self {
+        b.0
+    }
+}
+
+#[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Ord, Eq, Serialize, Deserialize)]
 pub struct Branch(usize);
 
 impl From<(Block, Block)> for Branch {
@@ -19,83 +30,421 @@ impl From<(Block, Block)> for Branch {
         let mut a = b1.0 as u32;
         // hash algorithm from syzkaller
         a = (a ^ 61) ^ (a >> 16);
-        a = a + (a << 3);
+        a = a.wrapping_add(a << 3);
         a = a ^ (a >> 4);
-        a *= 0x27d4_eb2d;
+        a = a.wrapping_mul(0x27d4_eb2d);
         a = a ^ (a >> 15);
 
         Self(a as usize ^ b2.0)
     }
 }
 
-#[derive(Default)]
-pub struct FeedBack {
-    branches: Mutex<HashSet<Branch>>,
-    blocks: Mutex<HashSet<Block>>,
+/// Bits of bitmap of known signal, 2^24 bits take 2 MiB.
+const KNOWN_BITS: usize = 1 << 24;
+/// Noise is rare, a smaller bitmap is enough.
+const NOISE_BITS: usize = 1 << 16;
+/// Flaky signal not seen again among this many distinct ones is forgotten.
+const FLAKY_WINDOW: usize = 1 << 16;
+const SHARDS: usize = 64;
+
+/// Concurrent set of blocks or branches.
+///
+/// Hash of each value indexes a bit of an atomic bitmap, values whose bit is
+/// clear are unknown for sure, which is the case for most new signal, so it's
+/// answered without any lock. Otherwise exact values are looked up in one of
+/// `SHARDS` shards behind read-write locks, a batch takes each lock once, so
+/// lookups never block each other and inserts only block lookups of the same
+/// shard. Exact values are grouped by high 32 bits, which all kernel PCs
+/// share, so each one takes 4 bytes.
+pub struct SignalSet {
+    bits: Box<[AtomicU64]>,
+    shards: Box<[RwLock<Shard>]>,
+    len: AtomicUsize,
 }
 
-impl FeedBack {
-    pub async fn diff_branch(&self, branches: &[Branch]) -> HashSet<Branch> {
-        let inner = self.branches.lock().await;
+impl Default for SignalSet {
+    fn default() -> Self {
+        Self::with_bits(KNOWN_BITS)
+    }
+}
+
+impl SignalSet {
+    /// Set with bitmap of `bits` bits, `bits` must be power of 2.
+    pub fn with_bits(bits: usize) -> Self {
+        assert!(bits.is_power_of_two() && bits >= 64);
+        Self {
+            bits: (0..bits / 64).map(|_| AtomicU64::new(0)).collect(),
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+            len: AtomicUsize::new(0),
+        }
+    }
 
-        let mut result = HashSet::new();
-        for b in branches {
-            if !inner.contains(b) {
-                result.insert(b.clone());
+    pub fn contains(&self, v: usize) -> bool {
+        let h = hash(v);
+        self.test_bit(h) && self.shards[shard(h)].read().unwrap().contains(v)
+    }
+
+    /// Insert `v`, return whether it's new.
+    pub fn insert(&self, v: usize) -> bool {
+        let h = hash(v);
+        let new = self.shards[shard(h)].write().unwrap().insert(v);
+        if new {
+            self.set_bit(h);
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+        new
+    }
+
+    /// Values of `vals` not in the set.
+    pub fn diff<I: IntoIterator<Item = usize>>(&self, vals: I) -> Vec<usize> {
+        let mut diff = Vec::new();
+        let mut maybe = Vec::new();
+        for v in vals {
+            let h = hash(v);
+            if self.test_bit(h) {
+                maybe.push((shard(h), v));
+            } else {
+                diff.push(v);
             }
         }
-        result.shrink_to_fit();
-        result
+        for run in by_shard(maybe).chunk_by(|a, b| a.0 == b.0) {
+            let shard = self.shards[run[0].0].read().unwrap();
+            diff.extend(run.iter().map(|&(_, v)| v).filter(|&v| !shard.contains(v)));
+        }
+        diff
+    }
+
+    /// Insert all of `vals`, return number of new values.
+    pub fn extend<I: IntoIterator<Item = usize>>(&self, vals: I) -> usize {
+        let vals = vals.into_iter().map(|v| (shard(hash(v)), v)).collect();
+        let mut n = 0;
+        for run in by_shard(vals).chunk_by(|a, b| a.0 == b.0) {
+            let mut shard = self.shards[run[0].0].write().unwrap();
+            for &(_, v) in run.iter() {
+                if shard.insert(v) {
+                    self.set_bit(hash(v));
+                    n += 1;
+                }
+            }
+        }
+        self.len.fetch_add(n, Ordering::Relaxed);
+        n
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn bit(&self, h: u64) -> (usize, u64) {
+        let i = h as usize & (self.bits.len() * 64 - 1);
+        (i / 64, 1 << (i % 64))
+    }
+
+    fn test_bit(&self, h: u64) -> bool {
+        let (word, mask) = self.bit(h);
+        self.bits[word].load(Ordering::Acquire) & mask != 0
+    }
+
+    /// Set after exact value is inserted, so a set bit is always backed by it.
+    fn set_bit(&self, h: u64) {
+        let (word, mask) = self.bit(h);
+        self.bits[word].fetch_or(mask, Ordering::Release);
     }
+}
+
+/// Sort `(shard, value)` pairs by shard with a counting sort, so a batch
+/// takes each lock once.
+fn by_shard(vals: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let mut pos = [0; SHARDS + 1];
+    for &(s, _) in vals.iter() {
+        pos[s + 1] += 1;
+    }
+    for s in 0..SHARDS {
+        pos[s + 1] += pos[s];
+    }
+    let mut sorted = vec![(0, 0); vals.len()];
+    for (s, v) in vals {
+        sorted[pos[s]] = (s, v);
+        pos[s] += 1;
+    }
+    sorted
+}
 
-    pub async fn diff_block(&self, blocks: &[Block]) -> HashSet<Block> {
-        let inner = self.blocks.lock().await;
+type LowSet = HashSet<u32, BuildHasherDefault<MixHasher>>;
 
-        let mut result = HashSet::new();
-        for b in blocks {
-            if !inner.contains(b) {
-                result.insert(b.clone());
+/// Exact values of one shard, low 32 bits grouped by high 32 bits.
+#[derive(Default)]
+struct Shard {
+    groups: Vec<(u32, LowSet)>,
+}
+
+impl Shard {
+    fn contains(&self, v: usize) -> bool {
+        let (high, low) = split(v);
+        self.groups
+            .iter()
+            .any(|(h, lows)| *h == high && lows.contains(&low))
+    }
+
+    fn insert(&mut self, v: usize) -> bool {
+        let (high, low) = split(v);
+        match self.groups.iter_mut().find(|(h, _)| *h == high) {
+            Some((_, lows)) => lows.insert(low),
+            None => {
+                let mut lows = LowSet::default();
+                lows.insert(low);
+                self.groups.push((high, lows));
+                true
             }
         }
-        result.shrink_to_fit();
-        result
+    }
+}
+
+fn split(v: usize) -> (u32, u32) {
+    ((v as u64 >> 32) as u32, v as u32)
+}
+
+/// Finalizer of splitmix64, PCs differ in low bits only.
+fn hash(v: usize) -> u64 {
+    let mut h = v as u64;
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+/// Shard from high bits of hash, low bits index bitmap.
+fn shard(h: u64) -> usize {
+    (h >> 58) as usize % SHARDS
+}
+
+/// Hasher of low 32 bits of PCs, cheaper than default SipHash, which guards
+/// against crafted keys that PCs are not.
+#[derive(Default)]
+struct MixHasher(u64);
+
+impl Hasher for MixHasher {
+    fn finish(&self) -> u64 {
+        self.0
     }
 
-    pub async fn merge(&self, blocks: HashSet<Block>, branches: HashSet<Branch>) {
-        {
-            let mut inner = self.branches.lock().await;
-            inner.extend(branches);
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = hash((self.0 as usize) << 8 | b as usize);
         }
-        {
-            let mut inner = self.blocks.lock().await;
-            inner.extend(blocks);
+    }
+
+    fn write_u32(&mut self, v: u32) {
+        self.0 = hash(v as usize);
+    }
+}
+
+/// Tracker of signal seen inconsistently across repeated runs of triage.
+///
+/// Counts are kept in two generations of `window` values each, when recent
+/// one is full it becomes old and the old one is dropped, so memory is
+/// bounded however many signal is flaky only once.
+struct Flaky {
+    counts: Mutex<Counts>,
+    window: usize,
+    noise: SignalSet,
+}
+
+#[derive(Default)]
+struct Counts {
+    recent: HashMap<usize, usize>,
+    old: HashMap<usize, usize>,
+}
+
+impl Default for Flaky {
+    fn default() -> Self {
+        Self {
+            counts: Mutex::default(),
+            window: FLAKY_WINDOW,
+            noise: SignalSet::with_bits(NOISE_BITS),
         }
     }
+}
 
-    pub async fn is_empty(&self) -> bool {
-        let (block_empty, branch_empty) = tokio::join!(
-            async {
-                let inner = self.blocks.lock().await;
-                inner.is_empty()
-            },
-            async {
-                let inner = self.branches.lock().await;
-                inner.is_empty()
-            }
-        );
-        block_empty || branch_empty
-    }
-
-    pub async fn len(&self) -> (usize, usize) {
-        tokio::join!(
-            async {
-                let inner = self.blocks.lock().await;
-                inner.len()
-            },
-            async {
-                let inner = self.branches.lock().await;
-                inner.len()
+impl Flaky {
+    fn record<I: IntoIterator<Item = usize>>(&self, vals: I) {
+        let mut counts = self.counts.lock().unwrap();
+        for v in vals {
+            let old = counts.old.remove(&v).unwrap_or(0);
+            let cnt = counts.recent.entry(v).or_insert(old);
+            *cnt += 1;
+            if *cnt >= FLAKY_THRESHOLD {
+                counts.recent.remove(&v);
+                self.noise.insert(v);
+            } else if counts.recent.len() >= self.window {
+                counts.old = std::mem::take(&mut counts.recent);
             }
+        }
+    }
+}
+
+/// Blocks and branches covered so far, shared by all vms.
+#[derive(Default)]
+pub struct FeedBack {
+    branches: SignalSet,
+    blocks: SignalSet,
+    flaky_blocks: Flaky,
+    flaky_branches: Flaky,
+}
+
+impl FeedBack {
+    /// Branches neither known nor noise.
+    pub fn diff_branch(&self, branches: &[Branch]) -> HashSet<Branch> {
+        let diff = self.branches.diff(branches.iter().map(|b| b.0));
+        self.flaky_branches
+            .noise
+            .diff(diff)
+            .into_iter()
+            .map(Branch)
+            .collect()
+    }
+
+    /// Blocks neither known nor noise.
+    pub fn diff_block(&self, blocks: &[Block]) -> HashSet<Block> {
+        let diff = self.blocks.diff(blocks.iter().map(|b| b.0));
+        self.flaky_blocks
+            .noise
+            .diff(diff)
+            .into_iter()
+            .map(Block)
+            .collect()
+    }
+
+    /// Record new signal seen in some but not all runs of a triage, it is
+    /// noise after `FLAKY_THRESHOLD` times.
+    pub fn record_flaky(&self, blocks: HashSet<Block>, branches: HashSet<Branch>) {
+        self.flaky_blocks.record(blocks.into_iter().map(|b| b.0));
+        self.flaky_branches
+            .record(branches.into_iter().map(|b| b.0));
+    }
+
+    /// Number of blocks and branches taken as noise.
+    pub fn noise_len(&self) -> (usize, usize) {
+        (
+            self.flaky_blocks.noise.len(),
+            self.flaky_branches.noise.len(),
         )
     }
+
+    pub fn merge(&self, blocks: HashSet<Block>, branches: HashSet<Branch>) {
+        self.branches.extend(branches.into_iter().map(|b| b.0));
+        self.blocks.extend(blocks.into_iter().map(|b| b.0));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() || self.branches.is_empty()
+    }
+
+    pub fn len(&self) -> (usize, usize) {
+        (self.blocks.len(), self.branches.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter::FromIterator;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn signal_set() {
+        let s = SignalSet::with_bits(64);
+        assert!(s.is_empty());
+        let pcs = (0..1000)
+            .map(|i| 0xffff_ffff_8100_0000 + i * 5)
+            .chain(vec![0, 1, usize::MAX])
+            .collect::<Vec<_>>();
+        for &pc in pcs.iter() {
+            assert!(!s.contains(pc));
+            assert!(s.insert(pc));
+            assert!(!s.insert(pc));
+        }
+        // Small bitmap is saturated, lookups fall to exact sets.
+        assert!(pcs.iter().all(|&pc| s.contains(pc)));
+        assert!(!s.contains(0xffff_ffff_8100_0001));
+        assert!(!s.contains(0x8100_0000));
+        assert_eq!(s.len(), pcs.len());
+        let mut diff = s.diff(vec![0xffff_ffff_8100_0001, 1, 2, 0xffff_ffff_8100_0005]);
+        diff.sort_unstable();
+        assert_eq!(diff, vec![2, 0xffff_ffff_8100_0001]);
+        assert_eq!(s.extend(vec![1, 2, 3, 3]), 2);
+        assert!(s.diff(vec![1, 2, 3]).is_empty());
+        assert_eq!(s.len(), pcs.len() + 2);
+
+        let s = Arc::new(SignalSet::default());
+        let handles = (0..4)
+            .map(|t| {
+                let s = s.clone();
+                thread::spawn(move || (0..10000).filter(|i| s.insert(i * 4 + t % 2)).count())
+            })
+            .collect::<Vec<_>>();
+        let inserted = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .sum::<usize>();
+        assert_eq!(inserted, 20000);
+        assert_eq!(s.len(), 20000);
+        assert!(s.contains(4 * 9999 + 1));
+        assert!(!s.contains(2));
+    }
+
+    #[test]
+    fn flaky_window() {
+        let f = Flaky {
+            window: 4,
+            ..Flaky::default()
+        };
+        f.record(vec![1, 2]);
+        f.record(vec![3, 4, 5]);
+        // 1 moved to old generation, still counted.
+        f.record(vec![1]);
+        assert!(f.noise.contains(1));
+
+        f.record(vec![2]);
+        assert!(f.noise.contains(2));
+        f.record(vec![6]);
+        f.record(10..20);
+        let counts = f.counts.lock().unwrap();
+        assert!(counts.recent.len() < 4 && counts.old.len() <= 4);
+        drop(counts);
+        // forgotten after two windows
+        f.record(vec![6]);
+        assert!(!f.noise.contains(6));
+    }
+
+    #[test]
+    fn flaky() {
+        let f = FeedBack::default();
+        let blocks = [Block::from(1), Block::from(2)];
+        let branch = Branch::from((Block::from(1), Block::from(2)));
+
+        f.record_flaky(HashSet::from_iter(vec![Block::from(1)]), HashSet::new());
+        assert_eq!(f.diff_block(&blocks).len(), 2);
+        f.record_flaky(
+            HashSet::from_iter(vec![Block::from(1)]),
+            HashSet::from_iter(vec![branch.clone()]),
+        );
+        let diff = f.diff_block(&blocks);
+        assert_eq!(diff, HashSet::from_iter(vec![Block::from(2)]));
+        assert_eq!(f.diff_branch(std::slice::from_ref(&branch)).len(), 1);
+        assert_eq!(f.noise_len(), (1, 0));
+
+        f.record_flaky(HashSet::new(), HashSet::from_iter(vec![branch.clone()]));
+        assert!(f.diff_branch(std::slice::from_ref(&branch)).is_empty());
+        assert_eq!(f.noise_len(), (1, 1));
+
+        f.merge(HashSet::from_iter(vec![Block::from(2)]), HashSet::new());
+        assert!(f.diff_block(&blocks).is_empty());
+        assert_eq!(f.len(), (1, 0));
+        assert!(f.is_empty());
+    }
 }
@@ -0,0 +1,270 @@
+//! Coverage filter, focus fuzzing on some functions, files or address ranges
+//! of kernel.
+//!
+//! Functions and files are resolved to address ranges with `nm` from symbol
+//! table of vmlinux, files need debug info. Without `weight`, only PCs inside
+//! the filter count as new signal, so corpus only admits progs reaching them.
+//! With `weight`, all PCs count and progs covering filtered PCs are chosen as
+//! seed of mutation `weight` times as often as others.
+
+use crate::feedback::Block;
+use crate::utils::glob;
+use std::path::PathBuf;
+use std::process::{exit, Command};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CoverFilterConf {
+    /// Kernel image, required by `functions` and `files`
+    pub vmlinux: Option<PathBuf>,
+    /// Globs of function names, e.g. `tcp_*`
+    #[serde(default)]
+    pub functions: Vec<String>,
+    /// Globs of source files relative to kernel source, e.g. `net/ipv4/*`
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// Address ranges, `start-end` in hex, end excluded
+    #[serde(default)]
+    pub ranges: Vec<String>,
+    /// Weight of progs covering filtered PCs when choosing seed of mutation
+    pub weight: Option<usize>,
+}
+
+impl CoverFilterConf {
+    pub fn check(&self) {
+        if self.functions.is_empty() && self.files.is_empty() && self.ranges.is_empty() {
+            eprintln!("Config Error: coverage filter is empty");
+            exit(exitcode::CONFIG)
+        }
+        if !self.functions.is_empty() || !self.files.is_empty() {
+            match self.vmlinux.as_ref() {
+                Some(vmlinux) if vmlinux.is_file() => (),
+                Some(vmlinux) => {
+                    eprintln!(
+                        "Config Error: vmlinux {} of coverage filter is not a file",
+                        vmlinux.display()
+                    );
+                    exit(exitcode::CONFIG)
+                }
+                None => {
+                    eprintln!("Config Error: vmlinux is required to filter functions or files");
+                    exit(exitcode::CONFIG)
+                }
+            }
+        }
+        for r in self.ranges.iter() {
+            if parse_range(r).is_none() {
+                eprintln!("Config Error: bad coverage filter range `{}`", r);
+                exit(exitcode::CONFIG)
+            }
+        }
+        if self.weight == Some(0) {
+            eprintln!("Config Error: weight of coverage filter should be at least 1");
+            exit(exitcode::CONFIG)
+        }
+    }
+}
+
+/// Sorted, disjoint address ranges.
+#[derive(Debug, Default)]
+pub struct CoverFilter {
+    ranges: Vec<(usize, usize)>,
+    weight: Option<usize>,
+}
+
+impl CoverFilter {
+    /// Resolve config to address ranges, run `nm` on vmlinux if needed.
+    pub fn new(conf: &CoverFilterConf) -> Result<Self, String> {
+        let mut ranges = conf
+            .ranges
+            .iter()
+            .filter_map(|r| parse_range(r))
+            .collect::<Vec<_>>();
+        if let Some(vmlinux) = conf.vmlinux.as_ref() {
+            if !conf.functions.is_empty() || !conf.files.is_empty() {
+                let syms = symbols(vmlinux, !conf.files.is_empty())?;
+                ranges.extend(
+                    syms.iter()
+                        .filter(|s| s.matches(&conf.functions, &conf.files))
+                        .map(|s| (s.addr, s.addr + s.size)),
+                );
+            }
+        }
+        Ok(Self::from_ranges(ranges, conf.weight))
+    }
+
+    pub fn from_ranges(mut ranges: Vec<(usize, usize)>, weight: Option<usize>) -> Self {
+        ranges.retain(|(start, end)| start < end);
+        ranges.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Self {
+            ranges: merged,
+            weight,
+        }
+    }
+
+    pub fn contains(&self, pc: usize) -> bool {
+        let i = self.ranges.partition_point(|&(start, _)| start <= pc);
+        i != 0 && pc < self.ranges[i - 1].1
+    }
+
+    pub fn ranges(&self) -> &[(usize, usize)] {
+        &self.ranges
+    }
+
+    /// Weight of focused progs in mutation, signal outside filter is dropped
+    /// if not set.
+    pub fn weight(&self) -> Option<usize> {
+        self.weight
+    }
+
+    /// Whether any block is inside the filter.
+    pub fn hits(&self, blocks: &[Block]) -> bool {
+        blocks.iter().any(|b| self.contains(usize::from(b.clone())))
+    }
+}
+
+fn parse_range(r: &str) -> Option<(usize, usize)> {
+    let (start, end) = r.split_once('-')?;
+    let parse = |s: &str| usize::from_str_radix(s.trim().trim_start_matches("0x"), 16).ok();
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start < end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Symbol {
+    name: String,
+    addr: usize,
+    size: usize,
+    file: Option<String>,
+}
+
+impl Symbol {
+    fn matches(&self, functions: &[String], files: &[String]) -> bool {
+        // Local functions may get suffix from compiler, e.g. `foo.isra.0`.
+        let name = self.name.split('.').next().unwrap();
+        if functions
+            .iter()
+            .any(|f| glob::matches(f, &self.name) || glob::matches(f, name))
+        {
+            return true;
+        }
+        match self.file.as_ref() {
+            Some(file) => files.iter().any(|f| file_matches(f, file)),
+            None => false,
+        }
+    }
+}
+
+/// Pattern relative to kernel source matches absolute path of debug info if
+/// it matches the path or any suffix of it starting after `/`.
+fn file_matches(pattern: &str, path: &str) -> bool {
+    glob::matches(pattern, path)
+        || path
+            .match_indices('/')
+            .any(|(i, _)| glob::matches(pattern, &path[i + 1..]))
+}
+
+/// Text symbols with size, with file if `lines`.
+fn symbols(vmlinux: &PathBuf, lines: bool) -> Result<Vec<Symbol>, String> {
+    let mut nm = Command::new("nm");
+    nm.arg("--defined-only").arg("-S");
+    if lines {
+        nm.arg("-l");
+    }
+    let out = nm
+        .arg(vmlinux)
+        .output()
+        .map_err(|e| format!("fail to run nm: {}", e))?;
+    if !out.status.success() {
+        return Err(format!(
+            "nm: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    let out = String::from_utf8_lossy(&out.stdout);
+    Ok(out.lines().filter_map(parse_symbol).collect())
+}
+
+/// Parse line of `nm -S [-l]` like `ffffffff81000000 0000000000000030 T foo\t/src/foo.c:12`.
+fn parse_symbol(line: &str) -> Option<Symbol> {
+    let (sym, loc) = match line.split_once('\t') {
+        Some((sym, loc)) => (sym, Some(loc)),
+        None => (line, None),
+    };
+    let cols = sym.split_whitespace().collect::<Vec<_>>();
+    if cols.len() != 4 || !matches!(cols[2], "t" | "T" | "w" | "W") {
+        return None;
+    }
+    let file = loc
+        .and_then(|l| l.rsplit_once(':'))
+        .map(|(f, _)| f.to_string());
+    Some(Symbol {
+        name: cols[3].to_string(),
+        addr: usize::from_str_radix(cols[0], 16).ok()?,
+        size: usize::from_str_radix(cols[1], 16).ok()?,
+        file,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("0x1000-0x2000"), Some((0x1000, 0x2000)));
+        assert_eq!(
+            parse_range("ffffffff81000000 - ffffffff81000100"),
+            Some((0xffff_ffff_8100_0000, 0xffff_ffff_8100_0100))
+        );
+        assert_eq!(parse_range("0x2000-0x1000"), None);
+        assert_eq!(parse_range("0x1000"), None);
+
+        let f = CoverFilter::from_ranges(vec![(0x30, 0x40), (0x10, 0x20), (0x18, 0x28)], None);
+        assert_eq!(f.ranges(), &[(0x10, 0x28), (0x30, 0x40)]);
+        assert!(!f.contains(0xf));
+        assert!(f.contains(0x10));
+        assert!(f.contains(0x27));
+        assert!(!f.contains(0x28));
+        assert!(f.contains(0x3f));
+        assert!(!f.contains(0x40));
+        assert!(f.hits(&[Block::from(0x1), Block::from(0x31)]));
+        assert!(!f.hits(&[Block::from(0x1), Block::from(0x29)]));
+    }
+
+    #[test]
+    fn symbols() {
+        let tcp = parse_symbol(
+            "ffffffff81000000 0000000000000030 T tcp_sendmsg\t/build/linux/net/ipv4/tcp.c:1234",
+        )
+        .unwrap();
+        assert_eq!(tcp.addr, 0xffff_ffff_8100_0000);
+        assert_eq!(tcp.size, 0x30);
+        assert_eq!(tcp.file.as_deref(), Some("/build/linux/net/ipv4/tcp.c"));
+        let udp = parse_symbol("ffffffff81000100 0000000000000010 t udp_rcv.isra.0").unwrap();
+        assert_eq!(udp.file, None);
+        assert_eq!(parse_symbol("ffffffff81000000 T _stext"), None);
+        assert_eq!(
+            parse_symbol("ffffffff82000000 0000000000000008 D jiffies"),
+            None
+        );
+
+        let f = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(tcp.matches(&f(&["tcp_*"]), &[]));
+        assert!(tcp.matches(&[], &f(&["net/ipv4/*"])));
+        assert!(tcp.matches(&[], &f(&["net/*/tcp.c"])));
+        assert!(!tcp.matches(&[], &f(&["ipv6/*"])));
+        assert!(udp.matches(&f(&["udp_rcv"]), &[]));
+        assert!(!udp.matches(&f(&["tcp_*"]), &f(&["net/*"])));
+    }
+}
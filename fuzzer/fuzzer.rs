@@ -1,61 +1,145 @@
-use crate::corpus::Corpus;
+use crate::classify::{classify, Quarantine};
+use crate::corpus::{Corpus, CorpusDB, Entry, SeedMeta};
+use crate::directed::Directed;
 use crate::exec::Executor;
 use crate::feedback::{Block, Branch, FeedBack};
+use crate::filter::CoverFilter;
 use crate::guest::Crash;
+use crate::hub::{HubCrash, Pending};
 use crate::report::TestCaseRecord;
-use crate::stats::StatSource;
+use crate::repro::repro;
+use crate::schedule::{Found, Schedule, Source, DEFAULT_SMASH, MAX_SMASH_PENDING};
+use crate::select::trim;
+use crate::stats::{StatSource, VmStat};
 use crate::utils::queue::CQueue;
 use crate::Config;
 use core::analyze::prog_analyze;
 use core::analyze::static_analyze;
 use core::analyze::RTable;
 use core::c::to_prog;
-use core::gen::gen;
+use core::gen::{gen, gen_prog, gen_prog_for};
 use core::minimize::remove;
-use core::mutate::mutate;
+use core::mutate::mutate_by;
 use core::prog::Prog;
 use core::target::Target;
 use executor::{ExecResult, Reason};
 use fots::types::GroupId;
 use itertools::Itertools;
+use rand::prelude::*;
 use regex::Regex;
 use std::collections::{HashMap, HashSet};
+use std::iter::FromIterator;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::fs::write;
 use tokio::sync::broadcast;
 use tokio::sync::Mutex;
 
+/// Re-execute once, the same as before triage runs became configurable.
+pub const DEFAULT_TRIAGE_RUNS: usize = 1;
+
+/// Progs left with quarantined calls only are dropped this many times in a
+/// row at most, the next one is executed as is.
+const MAX_QUARANTINE_TRIES: usize = 16;
+
 #[derive(Clone)]
 pub struct Fuzzer {
     pub target: Arc<Target>,
     pub rt: Arc<Mutex<HashMap<GroupId, RTable>>>,
     pub conf: core::gen::Config,
     pub corpus: Arc<Corpus>,
+    /// Admitted progs are appended here as soon as possible.
+    pub db: Arc<CorpusDB>,
     pub feedback: Arc<FeedBack>,
     pub candidates: Arc<CQueue<Prog>>,
     pub record: Arc<TestCaseRecord>,
     pub exec_cnt: Arc<AtomicUsize>,
-    pub crash_digests: Arc<Mutex<HashSet<md5::Digest>>>,
+    /// Live state of each vm, indexed by vm id.
+    pub vms: Arc<Vec<Arc<VmStat>>>,
+    /// Titles of reported crashes, see `oops::parse`.
+    pub crash_titles: Arc<Mutex<HashSet<String>>>,
+    /// Admitted progs and crashes waiting to be pushed to hub, if any.
+    pub hub: Option<Arc<Pending>>,
+    /// Coverage filter, see `filter::CoverFilter`.
+    pub filter: Option<Arc<CoverFilter>>,
+    /// Progs of corpus covering filtered PCs, preferred by mutation.
+    pub focus: Arc<Mutex<Vec<Prog>>>,
+    /// Directed fuzzing toward one interface, see `directed::Directed`.
+    pub directed: Option<Arc<Directed>>,
+    /// Times a sub prog with new signal is re-executed by triage.
+    pub triage_runs: usize,
+    /// Seed choice and gen/mutate ratio, see `schedule::Schedule`.
+    pub schedule: Arc<Schedule>,
+    /// Freshly admitted progs being smashed, index in corpus and mutations left,
+    /// at most `MAX_SMASH_PENDING` of them.
+    pub smash: Arc<CQueue<(usize, usize)>>,
+    /// Mutations of each freshly admitted prog.
+    pub smash_len: usize,
+    /// Calls whose progs never build, disabled if not configured.
+    pub quarantine: Option<Arc<Quarantine>>,
 
     pub suppressions: Vec<Regex>,
     pub ignores: Vec<Regex>,
 }
 
 impl Fuzzer {
-    pub fn new(target: Target, candidates: Vec<Prog>, cfg: &Config) -> Self {
+    pub fn new(
+        target: Target,
+        candidates: Vec<Prog>,
+        db: CorpusDB,
+        vms: Vec<Arc<VmStat>>,
+        cfg: &Config,
+    ) -> Self {
         let target = Arc::new(target);
         let record = Arc::new(TestCaseRecord::new(target.clone()));
         let rt = static_analyze(&target);
+        let directed = cfg.directed.as_ref().map(|conf| {
+            let directed = Directed::new(conf, &target, &rt)
+                .unwrap_or_else(|e| exits!(exitcode::CONFIG, "Fail to direct fuzzing: {}", e));
+            Arc::new(directed)
+        });
+        let groups = target.iter_group().map(|g| (g.id, g.ident.clone()));
+        let schedule = Arc::new(Schedule::new(cfg.schedule.as_ref(), groups));
         Self {
             target,
             record,
-            crash_digests: Arc::new(Mutex::new(HashSet::new())),
+            vms: Arc::new(vms),
+            crash_titles: Arc::new(Mutex::new(HashSet::new())),
+            hub: cfg.hub.as_ref().map(|_| Arc::new(Pending::default())),
+            filter: cfg.coverage_filter.as_ref().map(|conf| {
+                let filter = CoverFilter::new(conf).unwrap_or_else(|e| {
+                    exits!(exitcode::DATAERR, "Fail to resolve coverage filter: {}", e)
+                });
+                if filter.ranges().is_empty() {
+                    exits!(exitcode::CONFIG, "Coverage filter matches nothing");
+                }
+                Arc::new(filter)
+            }),
+            focus: Arc::new(Mutex::new(Vec::new())),
+            directed,
+            triage_runs: cfg.triage_runs.unwrap_or(DEFAULT_TRIAGE_RUNS),
+            schedule,
+            smash: Arc::new(CQueue::default()),
+            smash_len: cfg
+                .schedule
+                .as_ref()
+                .and_then(|s| s.smash)
+                .unwrap_or(DEFAULT_SMASH),
+            quarantine: cfg.quarantine.map(|n| Arc::new(Quarantine::new(n))),
             exec_cnt: Arc::new(AtomicUsize::new(0)),
             rt: Arc::new(Mutex::new(rt)),
-            conf: Default::default(),
+            conf: core::gen::Config {
+                arch: cfg
+                    .guest
+                    .arch
+                    .parse()
+                    .unwrap_or_else(|e| exits!(exitcode::CONFIG, "Config Error: {}", e)),
+                ..Default::default()
+            },
             candidates: Arc::new(CQueue::from(candidates)),
             corpus: Arc::new(Corpus::default()),
+            db: Arc::new(db),
             feedback: Arc::new(FeedBack::default()),
 
             suppressions: cfg
@@ -75,6 +159,27 @@ impl Fuzzer {
         }
     }
 
+    /// Restore corpus, feedback and relations from entries of db.
+    pub async fn restore(&self, entries: Vec<Entry>) {
+        for e in entries {
+            {
+                let g = &self.target.groups[&e.prog.gid];
+                let mut r = self.rt.lock().await;
+                prog_analyze(g, r.get_mut(&e.prog.gid).unwrap(), &e.prog);
+            }
+            self.focus_if_hit(&e.prog, std::slice::from_ref(&e.blocks))
+                .await;
+            let meta = SeedMeta {
+                signal: e.blocks.len() + e.branches.len(),
+                distance: self.directed.as_ref().map(|d| d.distance(&e.prog)),
+                ..SeedMeta::default()
+            };
+            self.corpus.insert(e.prog, meta).await;
+            self.feedback
+                .merge(HashSet::from_iter(e.blocks), HashSet::from_iter(e.branches));
+        }
+    }
+
     pub fn stats(&self) -> StatSource {
         StatSource {
             exec: self.exec_cnt.clone(),
@@ -82,6 +187,8 @@ impl Fuzzer {
             feedback: self.feedback.clone(),
             candidates: self.candidates.clone(),
             record: self.record.clone(),
+            vms: self.vms.clone(),
+            schedule: self.schedule.clone(),
         }
     }
     pub async fn fuzz(self, executor: Executor, mut shutdown: broadcast::Receiver<()>) {
@@ -92,21 +199,27 @@ impl Fuzzer {
     }
 
     async fn do_fuzz(&self, mut executor: Executor) {
-        let mut gen_cnt = 0;
         loop {
-            let p = self.get_prog(&mut gen_cnt).await;
-            match executor.exec(&p, &self.target).await {
+            let (p, src) = self.get_prog().await;
+            let gid = p.gid;
+            let found = match executor.exec(&p, &self.target).await {
                 Ok(exec_result) => match exec_result {
                     ExecResult::Ok(raw_branches) => {
+                        self.quarantine_analyze(&p, None);
                         self.feedback_analyze(p, raw_branches, &mut executor).await
                     }
-                    ExecResult::Failed(reason) => self.failed_analyze(p, reason).await,
+                    ExecResult::Failed(reason) => {
+                        self.failed_analyze(p, reason).await;
+                        Found::default()
+                    }
                 },
                 Err(crash) => {
                     self.crash_analyze(p, crash.unwrap_or_default(), &mut executor)
-                        .await
+                        .await;
+                    Found::default()
                 }
             };
+            self.schedule.record(src, gid, found);
             self.exec_cnt.fetch_add(1, Ordering::SeqCst);
         }
     }
@@ -130,19 +243,46 @@ impl Fuzzer {
     }
 
     async fn failed_analyze(&self, p: Prog, reason: Reason) {
+        self.quarantine_analyze(&p, Some(&reason));
         self.record.insert_failed(p, reason).await
     }
 
+    /// Count prog as built unless it failed with build error, quarantine
+    /// calls never built.
+    fn quarantine_analyze(&self, p: &Prog, failed: Option<&Reason>) {
+        let quarantine = match self.quarantine.as_ref() {
+            Some(q) => q,
+            None => return,
+        };
+        let failure = failed.map(|reason| classify(&reason.0));
+        let quarantined = quarantine.record(p, failure.as_ref(), &self.target);
+        if let Some(failure) = failure.as_ref() {
+            for fid in quarantined {
+                let f = self.target.fn_of(fid);
+                warn!(
+                    "Quarantined {}::{}: [{}] {}",
+                    self.target.group_name_of(f.gid),
+                    f.dec_name,
+                    failure.kind,
+                    failure.error
+                );
+            }
+        }
+    }
+
     async fn crash_analyze(&self, p: Prog, crash: Crash, executor: &mut Executor) {
+        executor.stat().crashes.fetch_add(1, Ordering::SeqCst);
         if self.should_ignore(&crash.inner) {
             warn!("Crashed, match ignores, restarting ...");
+            executor.clear_history();
             executor.start().await;
             return;
         }
 
-        if self.should_suppress(&crash.inner).await {
+        if self.should_suppress(&crash).await {
             self.record.insert_crash(p, crash, false).await;
             warn!("Crashed, match suppressions, restarting ...");
+            executor.clear_history();
             executor.start().await;
             return;
         }
@@ -151,25 +291,31 @@ impl Fuzzer {
         let p_str = to_prog(&p, &self.target);
         warn!("Caused by:\n{}", p_str);
         warn!("Restarting to repro ...");
-        executor.start().await;
 
-        self.exec_cnt.fetch_add(1, Ordering::SeqCst);
-        match executor.exec(&p, &self.target).await {
-            Ok(exec_result) => {
-                match exec_result {
-                    ExecResult::Ok(_) => warn!("Repo failed, executed successfully"),
-                    ExecResult::Failed(reason) => warn!("Repo failed, executed failed: {}", reason),
-                };
-                self.record.insert_crash(p, crash, false).await
-            }
-            Err(repo_crash) => {
-                self.record
-                    .insert_crash(p, repo_crash.unwrap_or(crash), true)
-                    .await;
-                warn!("Repo successfully, restarting guest ...");
-                executor.start().await;
+        let history = executor.history();
+        let title = crash.title();
+        let repro = repro(executor, &self.target, history, &title, &self.exec_cnt).await;
+        executor.clear_history();
+        if let Some(hub) = self.hub.as_ref() {
+            let c = HubCrash {
+                title,
+                report: crash.inner.clone(),
+                prog: p_str,
+            };
+            hub.push_crash(c).await;
+        }
+        let name = self.record.insert_crash(p, crash, repro.is_some()).await;
+        match repro {
+            Some(repro) => {
+                warn!(
+                    "Repo successfully with {} progs, restarting guest ...",
+                    repro.progs.len()
+                );
+                repro.persist(&name, &self.target).await;
             }
+            None => warn!("Repo failed, restarting guest ..."),
         }
+        executor.start().await;
     }
 
     fn should_ignore(&self, reason: &str) -> bool {
@@ -182,18 +328,24 @@ impl Fuzzer {
         }
     }
 
-    async fn should_suppress(&self, reason: &str) -> bool {
-        if reason.contains("CRASH-MEMLEAK") {
+    /// Suppress crash whose report or title matches suppressions, or reported
+    /// before with the same title, see `Crash::title`.
+    async fn should_suppress(&self, crash: &Crash) -> bool {
+        if crash.inner.contains("CRASH-MEMLEAK") {
             return true;
         }
 
-        if self.suppressions.iter().any(|s| s.is_match(reason)) {
+        let title = crash.title();
+        if self
+            .suppressions
+            .iter()
+            .any(|s| s.is_match(&crash.inner) || s.is_match(&title))
+        {
             return true;
         }
 
-        let digest = md5::compute(reason);
-        let mut g = self.crash_digests.lock().await;
-        !g.insert(digest)
+        let mut g = self.crash_titles.lock().await;
+        !g.insert(title)
     }
 
     async fn feedback_analyze(
@@ -201,62 +353,140 @@ impl Fuzzer {
         p: Prog,
         raw_blocks: Vec<Vec<usize>>,
         executor: &mut Executor,
-    ) {
+    ) -> Found {
+        let mut found = Found::default();
         for (call_index, raw_blocks) in raw_blocks.iter().enumerate() {
-            let (new_blocks_1, new_branches_1) = self.check_new_feedback(raw_blocks).await;
+            let (new_blocks_1, new_branches_1) = self.check_new_feedback(raw_blocks);
 
             if !new_blocks_1.is_empty() || !new_branches_1.is_empty() {
                 let p = p.sub_prog(call_index);
-                let exec_result = self.exec_no_crash(executor, &p).await;
-
-                if let ExecResult::Ok(raw_blocks) = exec_result {
-                    if raw_blocks.len() == call_index + 1 {
-                        let (new_block_2, new_branches_2) =
-                            self.check_new_feedback(&raw_blocks[call_index]).await;
-
-                        let new_block: HashSet<_> =
-                            new_blocks_1.intersection(&new_block_2).cloned().collect();
-                        let new_branches: HashSet<_> = new_branches_1
-                            .intersection(&new_branches_2)
-                            .cloned()
-                            .collect();
-
-                        if !new_block.is_empty() || !new_branches.is_empty() {
-                            let minimized_p = self.minimize(&p, &new_block, executor).await;
-                            let raw_branches = self.exec_no_fail(executor, &minimized_p).await;
-                            {
-                                let g = &self.target.groups[&p.gid];
-                                let mut r = self.rt.lock().await;
-                                prog_analyze(g, r.get_mut(&p.gid).unwrap(), &p);
-                            }
+                let stable = self
+                    .triage(&p, new_blocks_1, new_branches_1, executor)
+                    .await;
 
-                            let mut blocks = Vec::new();
-                            let mut branches = Vec::new();
-                            for raw_branches in raw_branches.iter() {
-                                let (block, branch) = self.cook_raw_block(raw_branches);
-                                blocks.push(block);
-                                branches.push(branch);
-                            }
+                if let Some((new_block, new_branches)) = stable {
+                    if !new_block.is_empty() || !new_branches.is_empty() {
+                        found.blocks += new_block.len();
+                        found.branches += new_branches.len();
+                        let minimized_p = self.minimize(&p, &new_block, executor).await;
+                        let start = Instant::now();
+                        let raw_branches = self.exec_no_fail(executor, &minimized_p).await;
+                        let exec_us = start.elapsed().as_micros() as u64;
+                        {
+                            let g = &self.target.groups[&p.gid];
+                            let mut r = self.rt.lock().await;
+                            prog_analyze(g, r.get_mut(&p.gid).unwrap(), &p);
+                        }
 
-                            blocks.shrink_to_fit();
-                            branches.shrink_to_fit();
-
-                            self.record
-                                .insert_executed(
-                                    &minimized_p,
-                                    &blocks[..],
-                                    &branches[..],
-                                    &new_block,
-                                    &new_branches,
-                                )
-                                .await;
-                            self.corpus.insert(minimized_p).await;
-                            self.feedback.merge(new_block, new_branches).await;
+                        let mut blocks = Vec::new();
+                        let mut branches = Vec::new();
+                        for raw_branches in raw_branches.iter() {
+                            let (block, branch) = self.cook_raw_block(raw_branches);
+                            blocks.push(block);
+                            branches.push(branch);
+                        }
+
+                        blocks.shrink_to_fit();
+                        branches.shrink_to_fit();
+
+                        self.record
+                            .insert_executed(
+                                &minimized_p,
+                                &blocks[..],
+                                &branches[..],
+                                &new_block,
+                                &new_branches,
+                            )
+                            .await;
+                        let meta = SeedMeta {
+                            signal: new_block.len() + new_branches.len(),
+                            found_at: self.exec_cnt.load(Ordering::Relaxed),
+                            mutated: 0,
+                            exec_us,
+                            distance: self.directed.as_ref().map(|d| d.distance(&minimized_p)),
+                        };
+                        if let Some(i) = self.corpus.insert(minimized_p.clone(), meta).await {
+                            if self.smash_len != 0 && self.smash.len().await < MAX_SMASH_PENDING {
+                                self.smash.push((i, self.smash_len)).await;
+                            }
+                            self.focus_if_hit(&minimized_p, &blocks).await;
+                            self.persist_entry(minimized_p, blocks, branches).await;
                         }
+                        self.feedback.merge(new_block, new_branches);
                     }
                 }
             }
         }
+        found
+    }
+
+    /// Re-execute sub prog `p` `triage_runs` times, return new signal seen by
+    /// every run. Signal seen by some runs only is recorded as flaky, `None`
+    /// if any run fails.
+    async fn triage(
+        &self,
+        p: &Prog,
+        mut new_blocks: HashSet<Block>,
+        mut new_branches: HashSet<Branch>,
+        executor: &mut Executor,
+    ) -> Option<(HashSet<Block>, HashSet<Branch>)> {
+        let call_index = p.len() - 1;
+        let mut seen_blocks = new_blocks.clone();
+        let mut seen_branches = new_branches.clone();
+        for _ in 0..self.triage_runs {
+            let raw_blocks = match self.exec_no_crash(executor, p).await {
+                ExecResult::Ok(raw_blocks) if raw_blocks.len() == call_index + 1 => raw_blocks,
+                _ => return None,
+            };
+            let (blocks, branches) = self.check_new_feedback(&raw_blocks[call_index]);
+            new_blocks.retain(|b| blocks.contains(b));
+            new_branches.retain(|b| branches.contains(b));
+            seen_blocks.extend(blocks);
+            seen_branches.extend(branches);
+        }
+
+        let flaky_blocks = seen_blocks
+            .difference(&new_blocks)
+            .cloned()
+            .collect::<HashSet<_>>();
+        let flaky_branches = seen_branches
+            .difference(&new_branches)
+            .cloned()
+            .collect::<HashSet<_>>();
+        if !flaky_blocks.is_empty() || !flaky_branches.is_empty() {
+            self.feedback.record_flaky(flaky_blocks, flaky_branches);
+        }
+        Some((new_blocks, new_branches))
+    }
+
+    /// Remember `p` as seed to focus on if it covers PCs in weighted filter.
+    async fn focus_if_hit(&self, p: &Prog, blocks: &[Vec<Block>]) {
+        if let Some(filter) = self.filter.as_ref() {
+            if filter.weight().is_some() && blocks.iter().any(|b| filter.hits(b)) {
+                let mut focus = self.focus.lock().await;
+                focus.push(p.clone());
+            }
+        }
+    }
+
+    async fn persist_entry(&self, prog: Prog, blocks: Vec<Vec<Block>>, branches: Vec<Vec<Branch>>) {
+        let mut blocks = blocks.into_iter().flatten().collect::<Vec<_>>();
+        blocks.sort();
+        blocks.dedup();
+        let mut branches = branches.into_iter().flatten().collect::<Vec<_>>();
+        branches.sort();
+        branches.dedup();
+        let entry = Entry {
+            prog,
+            blocks,
+            branches,
+        };
+        if let Err(e) = self.db.append(&entry).await {
+            exits!(exitcode::IOERR, "Fail to append corpus db: {}", e)
+        }
+        if let Some(hub) = self.hub.as_ref() {
+            hub.push_prog(entry).await;
+        }
     }
 
     async fn minimize(
@@ -279,7 +509,7 @@ impl Fuzzer {
             if !remove(&mut p, i) {
                 i += 1;
             } else if let ExecResult::Ok(cover) = self.exec_no_crash(executor, &p).await {
-                let (new_blocks_1, _) = self.check_new_feedback(cover.last().unwrap()).await;
+                let (new_blocks_1, _) = self.check_new_feedback(cover.last().unwrap());
                 if new_blocks_1.is_empty() || new_blocks_1.intersection(new_block).count() == 0 {
                     i += 1;
                     p = p_orig;
@@ -292,10 +522,21 @@ impl Fuzzer {
         p
     }
 
-    async fn check_new_feedback(&self, raw_blocks: &[usize]) -> (HashSet<Block>, HashSet<Branch>) {
-        let (blocks, branches) = self.cook_raw_block(raw_blocks);
-        let new_blocks = self.feedback.diff_block(&blocks[..]).await;
-        let new_branches = self.feedback.diff_branch(&branches[..]).await;
+    fn check_new_feedback(&self, raw_blocks: &[usize]) -> (HashSet<Block>, HashSet<Branch>) {
+        let (blocks, branches) = match self.filter.as_ref() {
+            // Signal outside filter is dropped without weight.
+            Some(filter) if filter.weight().is_none() => {
+                let raw_blocks = raw_blocks
+                    .iter()
+                    .copied()
+                    .filter(|&pc| filter.contains(pc))
+                    .collect::<Vec<_>>();
+                self.cook_raw_block(&raw_blocks)
+            }
+            _ => self.cook_raw_block(raw_blocks),
+        };
+        let new_blocks = self.feedback.diff_block(&blocks[..]);
+        let new_branches = self.feedback.diff_branch(&branches[..]);
         (new_blocks, new_branches)
     }
 
@@ -345,20 +586,111 @@ impl Fuzzer {
         }
     }
 
-    async fn get_prog(&self, gen_cnt: &mut usize) -> Prog {
-        if let Some(p) = self.candidates.pop().await {
-            p
-        } else if self.corpus.is_empty().await || *gen_cnt % 100 != 0 {
-            *gen_cnt += 1;
+    /// Next prog to execute, quarantined calls are trimmed, see
+    /// `classify::Quarantine::trim`.
+    async fn get_prog(&self) -> (Prog, Source) {
+        let quarantine = match self.quarantine.as_ref() {
+            Some(q) if !q.is_empty() => q,
+            _ => return self.next_prog().await,
+        };
+        for _ in 0..MAX_QUARANTINE_TRIES {
+            let (p, src) = self.next_prog().await;
+            if let Ok(p) = quarantine.trim(p) {
+                return (p, src);
+            }
+        }
+        self.next_prog().await
+    }
+
+    async fn next_prog(&self) -> (Prog, Source) {
+        if let Some(p) = self.next_candidate().await {
+            (p, Source::Candidate)
+        } else if let Some(i) = self.next_smash().await {
+            self.mutate(Some(i)).await
+        } else if self.corpus.is_empty().await || !self.schedule.should_mutate() {
             let rt = self.rt.lock().await;
-            gen(&self.target, &rt, &self.conf)
-        } else {
-            let rt = {
-                let rt = self.rt.lock().await;
-                rt.clone()
+            let p = match (self.directed.as_ref(), self.schedule.choose_group()) {
+                (Some(d), _) if d.should_direct() => {
+                    gen_prog_for(d.fid, &rt, &self.target, &self.conf)
+                }
+                (_, Some(gid)) => gen_prog(gid, &rt[&gid], &self.target, &self.conf),
+                _ => gen(&self.target, &rt, &self.conf),
             };
-            let corpus = self.corpus.inner.lock().await;
-            mutate(&corpus, &self.target, &rt, &self.conf)
+            (p, Source::Gen)
+        } else {
+            self.mutate(None).await
+        }
+    }
+
+    /// Next candidate, calls disabled in target are trimmed, see `select::trim`.
+    async fn next_candidate(&self) -> Option<Prog> {
+        while let Some(p) = self.candidates.pop().await {
+            if let Some(p) = trim(&self.target, p) {
+                return Some(p);
+            }
+        }
+        None
+    }
+
+    /// Index of corpus prog to smash next, if any burst is in progress and
+    /// schedule gives this prog to smash.
+    async fn next_smash(&self) -> Option<usize> {
+        if self.smash.is_empty().await || !self.schedule.should_smash() {
+            return None;
+        }
+        let (i, left) = self.smash.pop().await?;
+        if left > 1 {
+            self.smash.push((i, left - 1)).await;
+        }
+        Some(i)
+    }
+
+    /// Mutate corpus prog `seed`, or a prog chosen by coverage filter or
+    /// schedule.
+    async fn mutate(&self, seed: Option<usize>) -> (Prog, Source) {
+        let rt = {
+            let rt = self.rt.lock().await;
+            rt.clone()
+        };
+        let mut corpus = self.corpus.inner.lock().await;
+        let op = self.schedule.choose_operator();
+        if seed.is_none() {
+            let focus = self.focus.lock().await;
+            if let Some(p) = self.choose_focus(&focus, corpus.len()) {
+                let p = mutate_by(op, p, &corpus.progs, &self.target, &rt, &self.conf);
+                return (p, Source::Mutate(op));
+            }
+        }
+        let now = self.exec_cnt.load(Ordering::Relaxed);
+        let i = seed.unwrap_or_else(|| self.schedule.choose(&corpus, now).unwrap());
+        self.schedule.mutated(&mut corpus, i, now);
+        let p = mutate_by(
+            op,
+            &corpus.progs[i],
+            &corpus.progs,
+            &self.target,
+            &rt,
+            &self.conf,
+        );
+        match seed {
+            Some(_) => (p, Source::Smash(op)),
+            None => (p, Source::Mutate(op)),
+        }
+    }
+
+    /// Choose a focused prog with probability of `weight * focus / (weight * focus + others)`.
+    fn choose_focus<'a>(&self, focus: &'a [Prog], corpus_len: usize) -> Option<&'a Prog> {
+        let weight = self.filter.as_ref()?.weight()?;
+        if focus.is_empty() {
+            return None;
+        }
+        let focused = weight * focus.len();
+        let others = corpus_len.saturating_sub(focus.len());
+        let i = thread_rng().gen_range(0, focused + others);
+        if i < focused {
+            Some(&focus[i / weight])
+        } else {
+            None
         }
     }
 }
@@ -1,15 +1,20 @@
+use crate::oops;
 /// Driver for kernel to be tested
 use crate::utils::cli::{App, Arg, OptVal};
 use crate::utils::free_ipv4_port;
 use crate::Config;
+use executor::transport::{Transport, SERIAL_PORT_NAME};
 use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use os_pipe::{pipe, PipeReader, PipeWriter};
 use std::collections::HashMap;
+use std::env::temp_dir;
 use std::fmt;
 use std::io::{ErrorKind, Read};
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
+use std::process;
 use std::process::exit;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use tokio::process::Child;
 use tokio::time::{delay_for, timeout, Duration};
 
@@ -140,7 +145,7 @@ pub struct GuestConf {
     pub platform: String,
 }
 
-pub const PLATFORM: [&str; 1] = ["qemu"];
+pub const PLATFORM: [&str; 2] = ["qemu", "local"];
 pub const ARCH: [&str; 1] = ["amd64"];
 pub const OS: [&str; 1] = ["linux"];
 
@@ -216,14 +221,49 @@ impl SSHConf {
     }
 }
 
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct LocalConf {
+    /// Namespaces to run executor in, see `unshare(1)`.
+    pub namespaces: Option<Vec<String>>,
+}
+
+pub const NAMESPACES: [&str; 7] = ["user", "pid", "net", "mount", "ipc", "uts", "cgroup"];
+
+impl LocalConf {
+    pub fn check(&self) {
+        if let Some(namespaces) = self.namespaces.as_ref() {
+            for ns in namespaces {
+                if !NAMESPACES.contains(&ns.as_str()) {
+                    eprintln!(
+                        "Config Error: unknown namespace `{}`, expect one of {:?}",
+                        ns, NAMESPACES
+                    );
+                    exit(exitcode::CONFIG)
+                }
+            }
+        }
+    }
+
+    pub fn has_namespace(&self, ns: &str) -> bool {
+        self.namespaces
+            .as_ref()
+            .map(|n| n.iter().any(|n| n == ns))
+            .unwrap_or(false)
+    }
+}
+
 pub enum Guest {
-    LinuxQemu(LinuxQemu),
+    LinuxQemu(Box<LinuxQemu>),
+    LinuxLocal(LinuxLocal),
 }
 
 impl Guest {
     pub fn new(cfg: &Config) -> Self {
-        // only support linux/amd64 on qemu now.
-        Guest::LinuxQemu(LinuxQemu::new(cfg))
+        // only support linux/amd64 now.
+        match cfg.guest.platform.as_str() {
+            "local" => Guest::LinuxLocal(LinuxLocal::new(cfg)),
+            _ => Guest::LinuxQemu(Box::new(LinuxQemu::new(cfg))),
+        }
     }
 }
 
@@ -232,6 +272,7 @@ impl Guest {
     pub async fn boot(&mut self) {
         match self {
             Guest::LinuxQemu(ref mut guest) => guest.boot().await,
+            Guest::LinuxLocal(ref mut guest) => guest.boot().await,
         }
     }
 
@@ -239,6 +280,7 @@ impl Guest {
     pub async fn is_alive(&self) -> bool {
         match self {
             Guest::LinuxQemu(ref guest) => guest.is_alive().await,
+            Guest::LinuxLocal(ref guest) => guest.is_alive().await,
         }
     }
 
@@ -246,6 +288,7 @@ impl Guest {
     pub async fn run_cmd(&self, app: &App) -> Child {
         match self {
             Guest::LinuxQemu(ref guest) => guest.run_cmd(app).await,
+            Guest::LinuxLocal(ref guest) => guest.run_cmd(app).await,
         }
     }
 
@@ -253,12 +296,14 @@ impl Guest {
     pub async fn try_collect_crash(&mut self) -> Option<Crash> {
         match self {
             Guest::LinuxQemu(ref mut guest) => guest.try_collect_crash().await,
+            Guest::LinuxLocal(ref mut guest) => guest.try_collect_crash().await,
         }
     }
 
     pub async fn clear(&mut self) {
         match self {
             Guest::LinuxQemu(ref mut guest) => guest.clear().await,
+            Guest::LinuxLocal(_) => (),
         }
     }
 
@@ -266,6 +311,23 @@ impl Guest {
     pub async fn copy<T: AsRef<Path>>(&self, path: T) -> PathBuf {
         match self {
             Guest::LinuxQemu(ref guest) => guest.copy(path).await,
+            Guest::LinuxLocal(ref guest) => guest.copy(path),
+        }
+    }
+
+    /// Host side socket of virtio-serial port, if guest has one
+    pub fn serial_path(&self) -> Option<PathBuf> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.serial.clone(),
+            Guest::LinuxLocal(_) => None,
+        }
+    }
+
+    /// Address of host seen from guest
+    pub fn host_ip(&self) -> &'static str {
+        match self {
+            Guest::LinuxQemu(_) => LINUX_QEMU_USER_NET_HOST_IP_ADDR,
+            Guest::LinuxLocal(_) => LINUX_LOCAL_HOST_IP_ADDR,
         }
     }
 }
@@ -283,6 +345,16 @@ impl Default for Crash {
     }
 }
 
+impl Crash {
+    /// Title of parsed oops, digest of whole report if it can't be parsed.
+    pub fn title(&self) -> String {
+        match oops::parse(&self.inner) {
+            Some(oops) => oops.title,
+            None => format!("{:x}", md5::compute(&self.inner)),
+        }
+    }
+}
+
 impl fmt::Display for Crash {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.inner)
@@ -304,22 +376,36 @@ pub struct LinuxQemu {
     user: String,
     guest: GuestConf,
     qemu: QemuConf,
+    /// Unix socket of chardev backing virtio-serial port for executor.
+    serial: Option<PathBuf>,
 }
 
 impl LinuxQemu {
     pub fn new(cfg: &Config) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
         assert_eq!(cfg.guest.os, "linux");
+        // checked by `Config::check`
+        let qemu = cfg.qemu.as_ref().unwrap();
+        let ssh = cfg.ssh.as_ref().unwrap();
+
+        let serial = if cfg.executor.transport == Transport::VirtioSerial {
+            let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+            Some(temp_dir().join(format!("healer-serial-{}-{}.sock", process::id(), id)))
+        } else {
+            None
+        };
 
         Self {
             handle: Option::None,
             rp: Option::None,
-            wait_boot_time: cfg.qemu.wait_boot_time.unwrap_or(15),
+            wait_boot_time: qemu.wait_boot_time.unwrap_or(15),
             addr: LINUX_QEMU_HOST_IP_ADDR.to_string(),
             port: 0,
-            key: cfg.ssh.key_path.clone(),
+            key: ssh.key_path.clone(),
             user: LINUX_QEMU_HOST_USER.to_string(),
             guest: cfg.guest.clone(),
-            qemu: cfg.qemu.clone(),
+            qemu: qemu.clone(),
+            serial,
         }
     }
 }
@@ -335,8 +421,11 @@ impl LinuxQemu {
         const MAX_RETRY: u8 = 64;
         let mut retry = 0;
         loop {
-            let (qemu, port) = build_qemu_cli(&self.guest, &self.qemu);
+            let (qemu, port) = build_qemu_cli(&self.guest, &self.qemu, self.serial.as_deref());
             self.port = port;
+            if let Some(serial) = self.serial.as_ref() {
+                let _ = std::fs::remove_file(serial);
+            }
 
             let (mut handle, mut rp) = {
                 let mut cmd = qemu.clone().into_cmd();
@@ -501,7 +590,188 @@ impl LinuxQemu {
     }
 }
 
-fn build_qemu_cli(g: &GuestConf, q: &QemuConf) -> (App, u16) {
+pub const LINUX_LOCAL_HOST_IP_ADDR: &str = "127.0.0.1";
+
+/// Run executor as child process on host, no vm is needed.
+///
+/// Guest is considered dead, if last started command is killed by signal or
+/// kernel oops appears in dmesg. Dmesg is shared by all local guests, so one
+/// oops is reported by each of them. New lines of dmesg are told by their
+/// timestamp, so kernel must be booted with `printk.time=1`.
+pub struct LinuxLocal {
+    namespaces: Vec<String>,
+    /// Pid of last started command, 0 if none.
+    pid: AtomicU32,
+    /// Timestamp of last line of dmesg when booting, later lines belong to
+    /// this run. `None` if dmesg can't be read.
+    dmesg_mark: Option<(u64, u64)>,
+    /// Command printing kernel log.
+    dmesg: App,
+}
+
+impl LinuxLocal {
+    pub fn new(cfg: &Config) -> Self {
+        let namespaces = cfg
+            .local
+            .as_ref()
+            .and_then(|l| l.namespaces.clone())
+            .unwrap_or_default();
+
+        Self {
+            namespaces,
+            pid: AtomicU32::new(0),
+            dmesg_mark: None,
+            dmesg: App::new("dmesg"),
+        }
+    }
+}
+
+impl LinuxLocal {
+    async fn boot(&mut self) {
+        self.pid.store(0, Ordering::SeqCst);
+        self.dmesg_mark = dmesg(&self.dmesg)
+            .await
+            .map(|d| d.lines().rev().find_map(dmesg_stamp).unwrap_or_default());
+    }
+
+    async fn is_alive(&self) -> bool {
+        if self.exit_signal().is_some() {
+            return false;
+        }
+        match self.new_dmesg().await {
+            Some(d) => oops::parse(&d).is_none(),
+            None => true,
+        }
+    }
+
+    async fn run_cmd(&self, app: &App) -> Child {
+        let mut cmd = if self.namespaces.is_empty() {
+            app.clone()
+        } else {
+            let mut unshare = App::new("unshare");
+            for ns in self.namespaces.iter() {
+                unshare.arg(Arg::Flag(format!("--{}", ns)));
+                match ns.as_str() {
+                    "user" => {
+                        unshare.arg(Arg::new_flag("--map-root-user"));
+                    }
+                    "pid" => {
+                        unshare.arg(Arg::new_flag("--fork"));
+                        if self.namespaces.iter().any(|n| n == "mount") {
+                            unshare.arg(Arg::new_flag("--mount-proc"));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            unshare.arg(Arg::new_flag(&app.bin));
+            for app_arg in app.clone().iter_arg() {
+                unshare.arg(Arg::Flag(app_arg));
+            }
+            unshare
+        }
+        .into_cmd();
+
+        let child = cmd
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to spawn:{}", e));
+        self.pid.store(child.id(), Ordering::SeqCst);
+        child
+    }
+
+    async fn try_collect_crash(&mut self) -> Option<Crash> {
+        let mut crash = String::new();
+        if let Some(sig) = self.exit_signal() {
+            crash.push_str(&format!("Executor killed by signal {}\n", sig));
+        }
+        if let Some(d) = self.new_dmesg().await {
+            if oops::parse(&d).is_some() {
+                crash.push_str(&d);
+            }
+        }
+        self.pid.store(0, Ordering::SeqCst);
+
+        if crash.is_empty() {
+            None
+        } else {
+            Some(Crash { inner: crash })
+        }
+    }
+
+    fn copy<T: AsRef<Path>>(&self, path: T) -> PathBuf {
+        let path = path.as_ref();
+        std::fs::canonicalize(path)
+            .unwrap_or_else(|e| exits!(exitcode::IOERR, "Fail to find {}: {}", path.display(), e))
+    }
+
+    /// Signal killed last started command, the command is not reaped.
+    fn exit_signal(&self) -> Option<i32> {
+        use nix::libc;
+
+        let pid = self.pid.load(Ordering::SeqCst);
+        if pid == 0 {
+            return None;
+        }
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid,
+                &mut info,
+                libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+            )
+        };
+        if ret == 0
+            && unsafe { info.si_pid() } != 0
+            && (info.si_code == libc::CLD_KILLED || info.si_code == libc::CLD_DUMPED)
+        {
+            Some(unsafe { info.si_status() })
+        } else {
+            None
+        }
+    }
+
+    /// Dmesg after last boot, lines logged before it are dropped even if
+    /// ring buffer rotated.
+    async fn new_dmesg(&self) -> Option<String> {
+        let mark = self.dmesg_mark?;
+        let d = dmesg(&self.dmesg).await?;
+        let mut new = false;
+        let lines = d
+            .lines()
+            .filter(|l| {
+                // Lines without timestamp continue the last one.
+                if let Some(t) = dmesg_stamp(l) {
+                    new = t > mark;
+                }
+                new
+            })
+            .collect::<Vec<_>>();
+        Some(lines.join("\n"))
+    }
+}
+
+/// Seconds and microseconds of `[ sec.usec]` prefix of dmesg line.
+fn dmesg_stamp(line: &str) -> Option<(u64, u64)> {
+    let line = line.trim_start().strip_prefix('[')?;
+    let (sec, usec) = line[..line.find(']')?].trim().split_once('.')?;
+    Some((sec.parse().ok()?, usec.parse().ok()?))
+}
+
+async fn dmesg(app: &App) -> Option<String> {
+    let output = app.clone().into_cmd().output().await.ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        None
+    }
+}
+
+fn build_qemu_cli(g: &GuestConf, q: &QemuConf, serial: Option<&Path>) -> (App, u16) {
     let target = format!("{}/{}", g.os, g.arch);
 
     let mut qemu = QEMUS
@@ -532,6 +802,34 @@ fn build_qemu_cli(g: &GuestConf, q: &QemuConf) -> (App, u16) {
         ))
         .arg(Arg::new_opt("-hda", OptVal::Normal(cfg.image.clone())))
         .arg(Arg::new_opt("-kernel", OptVal::Normal(cfg.kernel.clone())));
+
+    if let Some(serial) = serial {
+        qemu.arg(Arg::new_opt("-device", OptVal::normal("virtio-serial")))
+            .arg(Arg::new_opt(
+                "-chardev",
+                OptVal::Multiple {
+                    vals: vec![
+                        String::from("socket"),
+                        String::from("id=healer"),
+                        format!("path={}", serial.display()),
+                        String::from("server"),
+                        String::from("nowait"),
+                    ],
+                    sp: Some(','),
+                },
+            ))
+            .arg(Arg::new_opt(
+                "-device",
+                OptVal::Multiple {
+                    vals: vec![
+                        String::from("virtserialport"),
+                        String::from("chardev=healer"),
+                        format!("name={}", SERIAL_PORT_NAME),
+                    ],
+                    sp: Some(','),
+                },
+            ));
+    }
     (qemu, port)
 }
 
@@ -578,3 +876,83 @@ fn read_all_nonblock(rp: &mut PipeReader) -> Vec<u8> {
     result.shrink_to_fit();
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use tokio::runtime::Runtime;
+
+    const OOPS: &str = include_str!("testdata/oops/bug_null_deref.log");
+
+    #[test]
+    fn stamp() {
+        assert_eq!(dmesg_stamp("[   73.410102] BUG: x"), Some((73, 410102)));
+        assert_eq!(dmesg_stamp("[12345.000001] foo"), Some((12345, 1)));
+        assert_eq!(dmesg_stamp("Call Trace:"), None);
+        assert_eq!(dmesg_stamp("[ abc] foo"), None);
+    }
+
+    #[test]
+    fn local_crash() {
+        let log = temp_dir().join(format!("healer-dmesg-{}", process::id()));
+        std::fs::write(
+            &log,
+            "[    0.000000] Linux version\n[    1.000000] old line\n",
+        )
+        .unwrap();
+        let mut cat = App::new("cat");
+        cat.arg(Arg::new_flag(log.to_str().unwrap()));
+        let mut guest = LinuxLocal {
+            namespaces: Vec::new(),
+            pid: AtomicU32::new(0),
+            dmesg_mark: None,
+            dmesg: cat,
+        };
+
+        Runtime::new().unwrap().block_on(async {
+            guest.boot().await;
+            assert_eq!(guest.dmesg_mark, Some((1, 0)));
+            assert!(guest.is_alive().await);
+
+            let mut sh = App::new("sh");
+            sh.arg(Arg::new_flag("-c"))
+                .arg(Arg::new_flag("kill -SEGV $$"));
+            let _child = guest.run_cmd(&sh).await;
+            let mut f = OpenOptions::new().append(true).open(&log).unwrap();
+            f.write_all(OOPS.as_bytes()).unwrap();
+            for _ in 0..100 {
+                if guest.exit_signal().is_some() {
+                    break;
+                }
+                delay_for(Duration::from_millis(10)).await;
+            }
+            assert!(!guest.is_alive().await);
+
+            let crash = guest.try_collect_crash().await.unwrap();
+            assert!(crash.inner.starts_with(&format!(
+                "Executor killed by signal {}\n",
+                nix::libc::SIGSEGV
+            )));
+            assert!(!crash.inner.contains("old line"));
+            assert_eq!(
+                crash.title(),
+                "BUG: kernel NULL pointer dereference in vcs_scr_readw"
+            );
+
+            // Oops before the mark of next boot belongs to last run.
+            guest.boot().await;
+            assert!(guest.is_alive().await);
+            assert!(guest.try_collect_crash().await.is_none());
+
+            // Line of mark rotated out of ring buffer, old oops is not new.
+            std::fs::write(&log, &OOPS[OOPS.find('\n').unwrap() + 1..]).unwrap();
+            assert!(guest.is_alive().await);
+            assert!(guest.try_collect_crash().await.is_none());
+            guest.dmesg_mark = None;
+            assert!(guest.new_dmesg().await.is_none());
+        });
+        std::fs::remove_file(log).unwrap();
+    }
+}
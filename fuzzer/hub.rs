@@ -0,0 +1,679 @@
+//! Corpus synchronization hub.
+//!
+//! Fuzzers connected to the same hub share programs admitted to their corpus
+//! and report crashes to it. Every `sync_interval` seconds, fuzzer connects to
+//! hub, sends `Connect` and then `SyncRequest` carrying programs admitted and
+//! crashes found since last sync. Hub replies with programs from other fuzzers
+//! after the cursor of this one, which are enqueued into candidates of fuzzer
+//! for triage. Cursor moves only when fuzzer acknowledges it in next request,
+//! so a response lost on the way is sent again. Messages are framed with `executor::transfer`. A
+//! request too large for one frame is split, a single program or crash too
+//! large is dropped.
+//!
+//! Hub keeps programs in `corpus.db`, acknowledged cursor of each fuzzer in
+//! `cursors.json` and one json file for each crash title in
+//! `crashes` of its working directory. Origin of programs is not kept, so
+//! after restart of hub a fuzzer may pull back its own programs pushed since
+//! its last sync, which are dropped by triage.
+
+use crate::corpus::{CorpusDB, Entry};
+use crate::report::sanitize_title;
+use crate::utils::queue::CQueue;
+use chrono::prelude::*;
+use chrono::DateTime;
+use core::prog::Prog;
+use executor::transfer::{self, async_recv, async_send};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::mem;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::Arc;
+use tokio::fs::{create_dir_all, read, read_dir, write};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::stream::StreamExt;
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::{delay_for, timeout, Duration};
+
+/// Max number of programs sent in one `SyncResponse`.
+pub const MAX_SYNC_PROGS: usize = 1024;
+const DEFAULT_SYNC_INTERVAL: u64 = 60;
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+const CURSORS_FILE: &str = "cursors.json";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HubConf {
+    /// Address of hub, `ip:port`
+    pub addr: String,
+    /// Name of this fuzzer, should be unique among fuzzers of the same hub
+    pub name: String,
+    /// Key shared with hub
+    #[serde(default)]
+    pub key: String,
+    /// Seconds between two syncs, 60 by default
+    pub sync_interval: Option<u64>,
+}
+
+impl HubConf {
+    pub fn check(&self) {
+        if let Err(e) = self.addr.to_socket_addrs() {
+            eprintln!("Config Error: invalid hub addr `{}`: {}", self.addr, e);
+            exit(exitcode::CONFIG)
+        }
+        if self.name.is_empty() {
+            eprintln!("Config Error: name of fuzzer is required by hub");
+            exit(exitcode::CONFIG)
+        }
+        if self.sync_interval == Some(0) {
+            eprintln!("Config Error: hub sync interval should be at least 1s");
+            exit(exitcode::CONFIG)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Connect {
+    pub name: String,
+    pub key: String,
+    /// See `executor::transfer::target_hash`.
+    pub target_hash: [u8; 16],
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum ConnectAck {
+    Accepted,
+    Rejected(String),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HubCrash {
+    pub title: String,
+    /// Console log of crash
+    pub report: String,
+    /// Prog caused the crash in script form
+    pub prog: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SyncRequest {
+    pub progs: Vec<Entry>,
+    pub crashes: Vec<HubCrash>,
+    /// `SyncResponse::cursor` last received, `None` after restart of fuzzer
+    pub ack: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SyncResponse {
+    pub progs: Vec<Entry>,
+    /// More programs are waiting, sync again.
+    pub more: bool,
+    /// Index of next program to send, to be acknowledged by next request
+    pub cursor: usize,
+}
+
+/// Crash reported to hub, persisted as `crashes/<title>.json`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CrashRecord {
+    pub title: String,
+    pub count: usize,
+    pub reporters: Vec<String>,
+    pub first_time: DateTime<Local>,
+    pub last_time: DateTime<Local>,
+    /// First report of this title
+    pub crash: HubCrash,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Transfer(transfer::Error),
+    Rejected(String),
+    Timeout,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transfer(e) => write!(f, "{}", e),
+            Error::Rejected(reason) => write!(f, "rejected by hub: {}", reason),
+            Error::Timeout => write!(f, "time out"),
+        }
+    }
+}
+
+impl From<transfer::Error> for Error {
+    fn from(e: transfer::Error) -> Self {
+        Error::Transfer(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Transfer(e.into())
+    }
+}
+
+pub struct Hub {
+    target_hash: [u8; 16],
+    key: String,
+    dir: PathBuf,
+    db: CorpusDB,
+    state: Mutex<HubState>,
+}
+
+#[derive(Default)]
+struct HubState {
+    /// Programs with name of fuzzer they come from, empty for loaded ones.
+    progs: Vec<(String, Entry)>,
+    known: HashSet<Prog>,
+    /// Index of next program to send for each fuzzer, as acknowledged by it,
+    /// see `CURSORS_FILE`.
+    cursors: HashMap<String, usize>,
+    crashes: HashMap<String, CrashRecord>,
+}
+
+impl Hub {
+    /// Create hub working in `dir`, load programs and crashes kept there.
+    pub async fn new(dir: PathBuf, target_hash: [u8; 16], key: String) -> io::Result<Self> {
+        create_dir_all(dir.join("crashes")).await?;
+        let (db, entries) = CorpusDB::open(dir.join("corpus.db")).await?;
+
+        let mut state = HubState::default();
+        for e in entries {
+            if state.known.insert(e.prog.clone()) {
+                state.progs.push((String::new(), e));
+            }
+        }
+        if let Ok(data) = read(dir.join(CURSORS_FILE)).await {
+            let cursors: HashMap<String, usize> = serde_json::from_slice(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            // Torn tail of db may have been truncated.
+            let n = state.progs.len();
+            state.cursors = cursors.into_iter().map(|(k, c)| (k, c.min(n))).collect();
+        }
+        let mut crashes = read_dir(dir.join("crashes")).await?;
+        while let Some(f) = crashes.next().await {
+            let data = read(f?.path()).await?;
+            if let Ok(r) = serde_json::from_slice::<CrashRecord>(&data) {
+                state.crashes.insert(r.title.clone(), r);
+            }
+        }
+
+        Ok(Self {
+            target_hash,
+            key,
+            dir,
+            db,
+            state: Mutex::new(state),
+        })
+    }
+
+    pub async fn len(&self) -> (usize, usize) {
+        let state = self.state.lock().await;
+        (state.progs.len(), state.crashes.len())
+    }
+
+    pub async fn serve(self: Arc<Self>, mut listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((conn, peer)) => {
+                    let hub = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = hub.handle(conn).await {
+                            warn!("Hub: fail to sync with {}: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Hub: fail to accept: {}", e),
+            }
+        }
+    }
+
+    async fn handle(&self, mut conn: TcpStream) -> Result<(), Error> {
+        let c: Connect = with_timeout(async_recv(&mut conn)).await?;
+        let ack = if !transfer::ct_eq(c.key.as_bytes(), self.key.as_bytes()) {
+            ConnectAck::Rejected("bad key".to_string())
+        } else if c.target_hash != self.target_hash {
+            ConnectAck::Rejected("target mismatch".to_string())
+        } else if c.name.is_empty() {
+            ConnectAck::Rejected("empty name".to_string())
+        } else {
+            ConnectAck::Accepted
+        };
+        with_timeout(async_send(&ack, &mut conn, false)).await?;
+        if let ConnectAck::Rejected(reason) = ack {
+            return Err(Error::Rejected(format!("{}: {}", c.name, reason)));
+        }
+
+        let req: SyncRequest = with_timeout(async_recv(&mut conn)).await?;
+        let resp = self.sync(&c.name, req).await;
+        with_timeout(async_send(&resp, &mut conn, true)).await
+    }
+
+    async fn sync(&self, name: &str, req: SyncRequest) -> SyncResponse {
+        let mut state = self.state.lock().await;
+        let mut new_progs = 0;
+        for e in req.progs {
+            if state.known.insert(e.prog.clone()) {
+                if let Err(e) = self.db.append(&e).await {
+                    exits!(exitcode::IOERR, "Fail to append hub corpus db: {}", e)
+                }
+                state.progs.push((name.to_string(), e));
+                new_progs += 1;
+            }
+        }
+        for c in req.crashes {
+            self.record_crash(&mut state, name, c).await;
+        }
+
+        let HubState { progs, cursors, .. } = &mut *state;
+        let cursor = cursors.entry(name.to_string()).or_insert(0);
+        if let Some(ack) = req.ack {
+            // Db may have lost a torn tail since fuzzer received it.
+            let ack = ack.min(progs.len());
+            if ack != *cursor {
+                *cursor = ack;
+                self.persist_cursors(cursors).await;
+            }
+        }
+        let mut next = cursors[name];
+        let mut resp = SyncResponse::default();
+        while next < progs.len() && resp.progs.len() < MAX_SYNC_PROGS {
+            let (origin, e) = &progs[next];
+            if origin != name {
+                resp.progs.push(e.clone());
+            }
+            next += 1;
+        }
+        resp.cursor = next;
+        resp.more = next < progs.len();
+        info!(
+            "Hub: {} pushed {} new progs, pulled {}",
+            name,
+            new_progs,
+            resp.progs.len()
+        );
+        resp
+    }
+
+    async fn persist_cursors(&self, cursors: &HashMap<String, usize>) {
+        let path = self.dir.join(CURSORS_FILE);
+        let data = serde_json::to_string(cursors).unwrap();
+        if let Err(e) = write(&path, data).await {
+            exits!(
+                exitcode::IOERR,
+                "Fail to persist cursors to {}: {}",
+                path.display(),
+                e
+            )
+        }
+    }
+
+    async fn record_crash(&self, state: &mut HubState, name: &str, crash: HubCrash) {
+        let now = Local::now();
+        let r = state.crashes.entry(crash.title.clone()).or_insert_with(|| {
+            warn!("Hub: new crash from {}: {}", name, crash.title);
+            CrashRecord {
+                title: crash.title.clone(),
+                count: 0,
+                reporters: Vec::new(),
+                first_time: now,
+                last_time: now,
+                crash,
+            }
+        });
+        r.count += 1;
+        r.last_time = now;
+        if !r.reporters.iter().any(|n| n == name) {
+            r.reporters.push(name.to_string());
+        }
+
+        let path = self
+            .dir
+            .join("crashes")
+            .join(format!("{}.json", sanitize_title(&r.title)));
+        let data = serde_json::to_string_pretty(r).unwrap();
+        if let Err(e) = write(&path, data).await {
+            exits!(
+                exitcode::IOERR,
+                "Fail to persist crash to {}: {}",
+                path.display(),
+                e
+            )
+        }
+    }
+}
+
+impl SyncRequest {
+    /// Split into two halves, `None` if there is at most one item.
+    fn split(mut self) -> Option<(SyncRequest, SyncRequest)> {
+        let progs = self.progs.len();
+        let crashes = self.crashes.len();
+        if progs + crashes <= 1 {
+            return None;
+        }
+        let rest = if progs == 1 && crashes == 1 {
+            SyncRequest {
+                progs: Vec::new(),
+                crashes: mem::take(&mut self.crashes),
+                ack: None,
+            }
+        } else {
+            SyncRequest {
+                progs: self.progs.split_off(progs / 2),
+                crashes: self.crashes.split_off(crashes / 2),
+                ack: None,
+            }
+        };
+        Some((self, rest))
+    }
+}
+
+/// Programs and crashes waiting to be pushed to hub.
+#[derive(Default)]
+pub struct Pending {
+    progs: Mutex<Vec<Entry>>,
+    crashes: Mutex<Vec<HubCrash>>,
+}
+
+impl Pending {
+    pub async fn push_prog(&self, e: Entry) {
+        let mut progs = self.progs.lock().await;
+        progs.push(e);
+    }
+
+    pub async fn push_crash(&self, c: HubCrash) {
+        let mut crashes = self.crashes.lock().await;
+        crashes.push(c);
+    }
+
+    async fn take(&self) -> SyncRequest {
+        let mut progs = self.progs.lock().await;
+        let mut crashes = self.crashes.lock().await;
+        SyncRequest {
+            progs: mem::take(&mut *progs),
+            crashes: mem::take(&mut *crashes),
+            ack: None,
+        }
+    }
+
+    /// Put back request failed to send.
+    async fn restore(&self, req: SyncRequest) {
+        let mut progs = self.progs.lock().await;
+        let mut crashes = self.crashes.lock().await;
+        progs.extend(req.progs);
+        crashes.extend(req.crashes);
+    }
+}
+
+/// One round trip with hub.
+pub async fn sync(
+    conf: &HubConf,
+    target_hash: [u8; 16],
+    req: &SyncRequest,
+) -> Result<SyncResponse, Error> {
+    let mut conn = with_timeout(TcpStream::connect(&conf.addr)).await?;
+    let c = Connect {
+        name: conf.name.clone(),
+        key: conf.key.clone(),
+        target_hash,
+    };
+    with_timeout(async_send(&c, &mut conn, false)).await?;
+    match with_timeout(async_recv(&mut conn)).await? {
+        ConnectAck::Accepted => (),
+        ConnectAck::Rejected(reason) => return Err(Error::Rejected(reason)),
+    }
+    with_timeout(async_send(req, &mut conn, true)).await?;
+    with_timeout(async_recv(&mut conn)).await
+}
+
+/// Sync with hub every `sync_interval` until shutdown, programs pulled are
+/// pushed into `candidates`.
+pub async fn sync_loop(
+    conf: HubConf,
+    target_hash: [u8; 16],
+    pending: Arc<Pending>,
+    candidates: Arc<CQueue<Prog>>,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    let interval = Duration::from_secs(conf.sync_interval.unwrap_or(DEFAULT_SYNC_INTERVAL));
+    let do_sync = async {
+        let mut cursor = None;
+        loop {
+            delay_for(interval).await;
+            let mut req = pending.take().await;
+            loop {
+                req.ack = cursor;
+                match sync(&conf, target_hash, &req).await {
+                    Ok(resp) => {
+                        cursor = Some(resp.cursor);
+                        for e in resp.progs {
+                            candidates.push(e.prog).await;
+                        }
+                        if !resp.more {
+                            break;
+                        }
+                        req = SyncRequest::default();
+                    }
+                    Err(Error::Transfer(transfer::Error::FrameTooLarge(len))) => {
+                        // Retrying the same request would fail forever.
+                        match req.split() {
+                            Some((first, rest)) => {
+                                pending.restore(rest).await;
+                                req = first;
+                            }
+                            None => {
+                                warn!("Hub: drop request of {} bytes, too large to sync", len);
+                                break;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Hub: fail to sync with {}: {}", conf.addr, e);
+                        pending.restore(req).await;
+                        break;
+                    }
+                }
+            }
+        }
+    };
+    tokio::select! {
+        _ = shutdown.recv() => (),
+        _ = do_sync => (),
+    }
+}
+
+async fn with_timeout<T, E, F>(f: F) -> Result<T, Error>
+where
+    F: std::future::Future<Output = Result<T, E>>,
+    Error: From<E>,
+{
+    match timeout(IO_TIMEOUT, f).await {
+        Ok(ret) => ret.map_err(Error::from),
+        Err(_) => Err(Error::Timeout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feedback::Block;
+    use std::env::temp_dir;
+    use std::future::Future;
+    use tokio::runtime::Runtime;
+
+    const HASH: [u8; 16] = [1; 16];
+
+    fn run<F: Future>(f: F) -> F::Output {
+        Runtime::new().unwrap().block_on(f)
+    }
+
+    fn entry(gid: usize) -> Entry {
+        Entry {
+            prog: Prog::new(gid),
+            blocks: vec![Block::from(gid)],
+            branches: Vec::new(),
+        }
+    }
+
+    fn push(progs: Vec<Entry>, ack: Option<usize>) -> SyncRequest {
+        SyncRequest {
+            progs,
+            crashes: Vec::new(),
+            ack,
+        }
+    }
+
+    fn conf(addr: &str, name: &str) -> HubConf {
+        HubConf {
+            addr: addr.to_string(),
+            name: name.to_string(),
+            key: "key".to_string(),
+            sync_interval: None,
+        }
+    }
+
+    async fn start(dir: &str) -> (String, Arc<Hub>, PathBuf) {
+        let dir = temp_dir().join(format!("healer-hub-{}-{}", std::process::id(), dir));
+        let _ = std::fs::remove_dir_all(&dir);
+        let hub = Arc::new(
+            Hub::new(dir.clone(), HASH, "key".to_string())
+                .await
+                .unwrap(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(hub.clone().serve(listener));
+        (addr, hub, dir)
+    }
+
+    #[test]
+    fn share_progs() {
+        run(async {
+            let (addr, hub, dir) = start("share").await;
+            let (a, b) = (conf(&addr, "a"), conf(&addr, "b"));
+
+            let resp = sync(&a, HASH, &push(vec![entry(0), entry(1)], None))
+                .await
+                .unwrap();
+            assert!(resp.progs.is_empty());
+            assert_eq!(resp.cursor, 2);
+
+            // b gets progs of a and pushes one known and one new.
+            let resp = sync(&b, HASH, &push(vec![entry(1), entry(2)], None))
+                .await
+                .unwrap();
+            assert_eq!(resp.progs, vec![entry(0), entry(1)]);
+            assert!(!resp.more);
+            sync(&b, HASH, &push(Vec::new(), Some(resp.cursor)))
+                .await
+                .unwrap();
+
+            let resp = sync(&a, HASH, &push(Vec::new(), Some(2))).await.unwrap();
+            assert_eq!(resp.progs, vec![entry(2)]);
+            // Response not acknowledged is sent again.
+            let resp = sync(&a, HASH, &push(Vec::new(), Some(2))).await.unwrap();
+            assert_eq!(resp.progs, vec![entry(2)]);
+            let resp = sync(&a, HASH, &push(Vec::new(), Some(resp.cursor)))
+                .await
+                .unwrap();
+            assert!(resp.progs.is_empty());
+            assert_eq!(hub.len().await, (3, 0));
+
+            // Restarted hub keeps progs and what has been sent.
+            let hub = Arc::new(
+                Hub::new(dir.clone(), HASH, "key".to_string())
+                    .await
+                    .unwrap(),
+            );
+            assert_eq!(hub.len().await, (3, 0));
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            tokio::spawn(hub.serve(listener));
+            let resp = sync(&conf(&addr, "b"), HASH, &SyncRequest::default())
+                .await
+                .unwrap();
+            assert!(resp.progs.is_empty());
+            let resp = sync(&conf(&addr, "c"), HASH, &SyncRequest::default())
+                .await
+                .unwrap();
+            assert_eq!(resp.progs.len(), 3);
+            std::fs::remove_dir_all(dir).unwrap();
+        })
+    }
+
+    #[test]
+    fn report_crashes() {
+        run(async {
+            let (addr, hub, dir) = start("crash").await;
+            let crash = HubCrash {
+                title: "KASAN: use-after-free Read in foo".to_string(),
+                report: "BUG: KASAN: use-after-free in foo".to_string(),
+                prog: "foo();".to_string(),
+            };
+            let req = SyncRequest {
+                progs: Vec::new(),
+                crashes: vec![crash.clone(), crash],
+                ack: None,
+            };
+            sync(&conf(&addr, "a"), HASH, &req).await.unwrap();
+            sync(&conf(&addr, "b"), HASH, &req).await.unwrap();
+            assert_eq!(hub.len().await, (0, 1));
+
+            let path = dir.join("crashes/KASAN__use-after-free_Read_in_foo.json");
+            let r: CrashRecord = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+            assert_eq!(r.count, 4);
+            assert_eq!(r.reporters, vec!["a".to_string(), "b".to_string()]);
+            std::fs::remove_dir_all(dir).unwrap();
+        })
+    }
+
+    #[test]
+    fn split_request() {
+        let crash = HubCrash {
+            title: "foo".to_string(),
+            report: String::new(),
+            prog: String::new(),
+        };
+        let req = SyncRequest {
+            progs: (0..3).map(entry).collect(),
+            crashes: vec![crash.clone()],
+            ack: None,
+        };
+        let (first, rest) = req.split().unwrap();
+        assert_eq!(first.progs, vec![entry(0)]);
+        assert!(first.crashes.is_empty());
+        assert_eq!(rest.progs, vec![entry(1), entry(2)]);
+        assert_eq!(rest.crashes.len(), 1);
+
+        let req = SyncRequest {
+            progs: vec![entry(0)],
+            crashes: vec![crash],
+            ack: None,
+        };
+        let (first, rest) = req.split().unwrap();
+        assert_eq!((first.progs.len(), first.crashes.len()), (1, 0));
+        assert_eq!((rest.progs.len(), rest.crashes.len()), (0, 1));
+        assert!(first.split().is_none());
+        assert!(SyncRequest::default().split().is_none());
+    }
+
+    #[test]
+    fn reject() {
+        run(async {
+            let (addr, hub, dir) = start("reject").await;
+            let req = push(vec![entry(0)], None);
+            let mut bad_key = conf(&addr, "a");
+            bad_key.key = "other".to_string();
+            assert!(matches!(
+                sync(&bad_key, HASH, &req).await,
+                Err(Error::Rejected(_))
+            ));
+            let ret = sync(&conf(&addr, "a"), [2; 16], &req).await;
+            assert!(matches!(ret, Err(Error::Rejected(_))));
+            assert_eq!(hub.len().await, (0, 0));
+            std::fs::remove_dir_all(dir).unwrap();
+        })
+    }
+}
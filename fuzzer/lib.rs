@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::process::{exit, id};
 use std::sync::Arc;
@@ -10,35 +11,61 @@ extern crate serde;
 extern crate log;
 
 use regex::Regex;
-use tokio::fs::{create_dir_all, read};
+use tokio::fs::{create_dir_all, read, write};
+use tokio::net::TcpListener;
 use tokio::signal::ctrl_c;
 use tokio::sync::{broadcast, Barrier};
 use tokio::time::{delay_for, Duration, Instant};
 
+use core::analyze::disabled_dependents;
 use core::prog::Prog;
 use core::target::Target;
-use fots::types::Items;
+use executor::transport::Transport;
+use fots::types::{FnId, Items};
 
+use crate::corpus::CorpusDB;
+use crate::dashboard::{Dashboard, DashboardConf};
+use crate::directed::DirectedConf;
 use crate::exec::{Executor, ExecutorConf};
+use crate::filter::CoverFilterConf;
 use crate::fuzzer::Fuzzer;
-use crate::guest::{GuestConf, QemuConf, SSHConf};
+use crate::guest::{GuestConf, LocalConf, QemuConf, SSHConf};
+use crate::hub::HubConf;
 #[cfg(feature = "mail")]
 use crate::mail::MailConf;
-use crate::stats::SamplerConf;
+use crate::repro::ReproConf;
+use crate::schedule::ScheduleConf;
+use crate::select::{is_available, trim, SelectConf};
+use crate::stats::{SamplerConf, VmStat};
 
 #[macro_use]
 #[allow(dead_code)]
 mod utils;
+pub mod classify;
 pub mod corpus;
+mod dashboard;
+pub mod directed;
 mod exec;
 pub mod feedback;
+pub mod filter;
 mod fuzzer;
 mod guest;
+pub mod hub;
 #[cfg(feature = "mail")]
 mod mail;
+mod metrics;
+pub mod oops;
 pub mod report;
+pub mod repro;
+pub mod schedule;
+pub mod select;
 mod stats;
 
+/// Append-only corpus store in working directory, see `corpus::CorpusDB`.
+pub const CORPUS_DB: &str = "./corpus.db";
+/// Calls disabled at startup with reason, see `DisabledCall`.
+pub const DISABLED_CALLS: &str = "./disabled_calls.json";
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub fots_bin: PathBuf,
@@ -46,11 +73,34 @@ pub struct Config {
     pub vm_num: usize,
     pub suppressions: Option<Vec<String>>,
     pub ignores: Option<Vec<String>>,
+    /// Times a prog with new coverage is re-executed to drop flaky coverage, 1 by default
+    pub triage_runs: Option<usize>,
+    /// Seed choice and generate/mutate ratio, power schedule and adaptive ratio if not set
+    pub schedule: Option<ScheduleConf>,
+    /// `enable_calls`, `disable_calls`, `enable_groups` and `disable_groups`, all calls enabled if not set
+    #[serde(flatten)]
+    pub select: SelectConf,
+    /// Quarantine calls whose progs failed to build this many times and never built, disabled if not set
+    pub quarantine: Option<usize>,
     pub guest: GuestConf,
-    pub qemu: QemuConf,
-    pub ssh: SSHConf,
+    /// Required by qemu platform
+    pub qemu: Option<QemuConf>,
+    /// Required by qemu platform
+    pub ssh: Option<SSHConf>,
+    /// Used by local platform
+    pub local: Option<LocalConf>,
     pub executor: ExecutorConf,
     pub sampler: Option<SamplerConf>,
+    /// Crash reproduction, enabled with default history if not set
+    pub repro: Option<ReproConf>,
+    /// Http dashboard, disabled if not set
+    pub dashboard: Option<DashboardConf>,
+    /// Corpus sync hub, disabled if not set
+    pub hub: Option<HubConf>,
+    /// Focus on coverage of some functions, files or ranges, disabled if not set
+    pub coverage_filter: Option<CoverFilterConf>,
+    /// Direct fuzzing toward one interface, disabled if not set
+    pub directed: Option<DirectedConf>,
 
     #[cfg(feature = "mail")]
     pub mail: Option<MailConf>,
@@ -111,6 +161,44 @@ impl Config {
             sampler.check()
         }
 
+        if let Some(schedule) = self.schedule.as_ref() {
+            schedule.check()
+        }
+
+        self.select.check();
+
+        if self.quarantine == Some(0) {
+            eprintln!("Config Error: quarantine threshold should be positive");
+            exit(exitcode::CONFIG)
+        }
+
+        if let Some(runs) = self.triage_runs {
+            if !(1..=16).contains(&runs) {
+                eprintln!("Config Error: triage runs should be in [1, 16]");
+                exit(exitcode::CONFIG)
+            }
+        }
+
+        if let Some(repro) = self.repro.as_ref() {
+            repro.check()
+        }
+
+        if let Some(dashboard) = self.dashboard.as_ref() {
+            dashboard.check()
+        }
+
+        if let Some(hub) = self.hub.as_ref() {
+            hub.check()
+        }
+
+        if let Some(filter) = self.coverage_filter.as_ref() {
+            filter.check()
+        }
+
+        if let Some(directed) = self.directed.as_ref() {
+            directed.check()
+        }
+
         #[cfg(feature = "mail")]
         if let Some(mail) = mail.as_ref() {
             mail.check()
@@ -118,8 +206,39 @@ impl Config {
 
         self.guest.check();
         self.executor.check();
-        self.qemu.check();
-        self.ssh.check();
+        match self.guest.platform.as_str() {
+            "qemu" => match (self.qemu.as_ref(), self.ssh.as_ref()) {
+                (Some(qemu), Some(ssh)) => {
+                    qemu.check();
+                    ssh.check();
+                    if self.executor.transport == Transport::Unix {
+                        eprintln!(
+                            "Config Error: unix transport is only supported by local platform"
+                        );
+                        exit(exitcode::CONFIG)
+                    }
+                }
+                _ => {
+                    eprintln!("Config Error: qemu and ssh are required by qemu platform");
+                    exit(exitcode::CONFIG)
+                }
+            },
+            "local" => {
+                let local = self.local.clone().unwrap_or_default();
+                local.check();
+                if local.has_namespace("net") && self.executor.transport == Transport::Tcp {
+                    eprintln!("Config Error: tcp transport is unreachable in net namespace, use stdio or unix");
+                    exit(exitcode::CONFIG)
+                }
+                if self.executor.transport == Transport::VirtioSerial {
+                    eprintln!(
+                        "Config Error: virtio-serial transport is not supported by local platform"
+                    );
+                    exit(exitcode::CONFIG)
+                }
+            }
+            _ => unreachable!(),
+        }
     }
 }
 
@@ -127,6 +246,54 @@ pub async fn fuzz(cfg: Config) {
     let cfg = Arc::new(cfg);
     let (target, corpus) = tokio::join!(load_target(&cfg), load_corpus(&cfg.curpus));
     check_corpus(&target, &corpus);
+
+    let (db, entries) = CorpusDB::open(CORPUS_DB).await.unwrap_or_else(|e| {
+        exits!(
+            exitcode::DATAERR,
+            "Fail to open corpus db {}: {}",
+            CORPUS_DB,
+            e
+        )
+    });
+    let progs = entries.iter().map(|e| e.prog.clone()).collect::<Vec<_>>();
+    check_corpus(&target, &progs);
+
+    let mut disabled = Vec::new();
+    let deselected = cfg.select.deselect(&target);
+    let target = disable(target, deselected, &mut disabled);
+    let vms = (0..cfg.vm_num)
+        .map(|_| Arc::new(VmStat::default()))
+        .collect::<Vec<_>>();
+    let (target, first) = if cfg.executor.no_probe {
+        (target, None)
+    } else {
+        info!("Probing calls on vm 0 ...");
+        let mut executor = Executor::new(&cfg, vms[0].clone());
+        let target = probe_target(target, &mut executor, &mut disabled).await;
+        (target, Some(executor))
+    };
+    persist_disabled(&disabled).await;
+
+    // Progs from db calling disabled calls are executed again as candidates
+    // after trimmed, coverage recorded with them is stale.
+    let n = corpus.len() + entries.len();
+    let (entries, stale): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .partition(|e| is_available(&target, &e.prog));
+    let unavailable = stale.len() + corpus.iter().filter(|p| !is_available(&target, p)).count();
+    let corpus = corpus
+        .into_iter()
+        .chain(stale.into_iter().map(|e| e.prog))
+        .filter_map(|p| trim(&target, p))
+        .collect::<Vec<_>>();
+    if unavailable != 0 {
+        let dropped = n - corpus.len() - entries.len();
+        warn!(
+            "Progs calling disabled calls: {} trimmed, {} dropped",
+            unavailable - dropped,
+            dropped
+        );
+    }
     info!("Corpus: {}", corpus.len());
     info!(
         "Syscalls: {}  Groups: {}",
@@ -134,30 +301,75 @@ pub async fn fuzz(cfg: Config) {
         target.groups.len()
     );
 
-    let fuzzer = Fuzzer::new(target, corpus, &cfg);
+    let fuzzer = Fuzzer::new(target, corpus, db, vms, &cfg);
+    if let Some(conf) = cfg.directed.as_ref() {
+        info!("Directed to {}", conf.target);
+    }
+    match cfg.schedule.as_ref().and_then(|s| s.mutate_ratio) {
+        Some(ratio) => info!("Mutate ratio: {}", ratio),
+        None => info!("Mutate ratio: adaptive"),
+    }
+    if let Some(filter) = fuzzer.filter.as_ref() {
+        info!(
+            "Coverage filter: {} ranges, {}",
+            filter.ranges().len(),
+            match filter.weight() {
+                Some(w) => format!("focused progs weighted {}", w),
+                None => "signal outside dropped".to_string(),
+            }
+        );
+    }
+    if !entries.is_empty() {
+        let n = entries.len();
+        fuzzer.restore(entries).await;
+        let (blocks, branches) = fuzzer.feedback.len();
+        info!(
+            "Restored {} progs from {}, blocks {}, branches {}",
+            n, CORPUS_DB, blocks, branches
+        );
+    }
     info!(
         "Booting {} {}/{} on {} ...",
         cfg.vm_num, cfg.guest.os, cfg.guest.arch, cfg.guest.platform
     );
     let now = std::time::Instant::now();
-    let shutdown = start_fuzz(fuzzer.clone(), cfg.clone()).await;
+    let shutdown = start_fuzz(fuzzer.clone(), first, cfg.clone()).await;
     info!("Boot finished, cost {}s.", now.elapsed().as_secs());
+    if let Some(conf) = cfg.dashboard.as_ref() {
+        start_dashboard(&fuzzer, conf, shutdown.subscribe()).await;
+    }
+    if let Some(conf) = cfg.hub.as_ref() {
+        start_sync(&fuzzer, conf, &cfg.fots_bin, shutdown.subscribe()).await;
+    }
 
     wait_exit_signal(fuzzer, shutdown).await
 }
 
-async fn start_fuzz(fuzzer: Fuzzer, cfg: Arc<Config>) -> broadcast::Sender<()> {
+/// Start fuzzing on every vm, `first` is executor of vm 0 already started
+/// for probing, if any.
+async fn start_fuzz(
+    fuzzer: Fuzzer,
+    mut first: Option<Executor>,
+    cfg: Arc<Config>,
+) -> broadcast::Sender<()> {
     let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
     let barrier = Arc::new(Barrier::new(cfg.vm_num + 1));
-    for _ in 0..cfg.vm_num {
+    for i in 0..cfg.vm_num {
         let cfg = cfg.clone();
         let fuzzer = fuzzer.clone();
         let barrier = barrier.clone();
         let shutdown = shutdown_tx.subscribe();
+        let started = first.take();
 
         tokio::spawn(async move {
-            let mut executor = Executor::new(&cfg);
-            executor.start().await;
+            let executor = match started {
+                Some(executor) => executor,
+                None => {
+                    let mut executor = Executor::new(&cfg, fuzzer.vms[i].clone());
+                    executor.start().await;
+                    executor
+                }
+            };
             barrier.wait().await;
             fuzzer.fuzz(executor, shutdown).await;
         });
@@ -172,6 +384,48 @@ async fn start_fuzz(fuzzer: Fuzzer, cfg: Arc<Config>) -> broadcast::Sender<()> {
     shutdown_tx
 }
 
+async fn start_dashboard(fuzzer: &Fuzzer, conf: &DashboardConf, shutdown: broadcast::Receiver<()>) {
+    let listener = TcpListener::bind(conf.addr.as_str())
+        .await
+        .unwrap_or_else(|e| {
+            exits!(
+                exitcode::OSERR,
+                "Fail to listen dashboard on {}: {}",
+                conf.addr,
+                e
+            )
+        });
+    let dashboard = Dashboard::new(fuzzer.stats(), fuzzer.target.clone(), fuzzer.rt.clone());
+    tokio::spawn(Arc::new(dashboard).serve(listener, shutdown));
+    info!("Dashboard: http://{}", conf.addr);
+}
+
+async fn start_sync(
+    fuzzer: &Fuzzer,
+    conf: &HubConf,
+    fots_bin: &PathBuf,
+    shutdown: broadcast::Receiver<()>,
+) {
+    let items = read(fots_bin).await.unwrap_or_else(|e| {
+        exits!(
+            exitcode::IOERR,
+            "Fail to read {}: {}",
+            fots_bin.display(),
+            e
+        )
+    });
+    let hash = executor::transfer::target_hash(&items);
+    let pending = fuzzer.hub.clone().unwrap();
+    tokio::spawn(hub::sync_loop(
+        conf.clone(),
+        hash,
+        pending,
+        fuzzer.candidates.clone(),
+        shutdown,
+    ));
+    info!("Hub: syncing with {} as {}", conf.addr, conf.name);
+}
+
 async fn wait_exit_signal(fuzzer: Fuzzer, shutdown: broadcast::Sender<()>) {
     if cfg!(unix) {
         use tokio::signal::unix::{signal, SignalKind};
@@ -214,6 +468,94 @@ async fn wait_exit_signal(fuzzer: Fuzzer, shutdown: broadcast::Sender<()>) {
     exit(exitcode::OK);
 }
 
+/// Boot vm with executor probing calls, disable unavailable calls.
+async fn probe_target(
+    target: Target,
+    executor: &mut Executor,
+    disabled: &mut Vec<DisabledCall>,
+) -> Target {
+    let probe = match executor.start_probe().await {
+        Some(probe) => probe,
+        None => {
+            warn!("Probe is not supported by script mode, all calls enabled");
+            return target;
+        }
+    };
+    let unavailable = probe
+        .unavailable
+        .into_iter()
+        .filter(|(fid, _)| target.fns.contains_key(fid))
+        .collect::<HashMap<_, _>>();
+    if unavailable.is_empty() {
+        info!("Probe: all {} calls available", target.fns.len());
+    }
+    disable(target, unavailable, disabled)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DisabledCall {
+    pub group: String,
+    pub call: String,
+    pub reason: String,
+}
+
+/// Remove `disabled` calls and calls depending on them from target, each
+/// one is logged with reason and added to `report`.
+fn disable(
+    target: Target,
+    mut disabled: HashMap<FnId, String>,
+    report: &mut Vec<DisabledCall>,
+) -> Target {
+    if disabled.is_empty() {
+        return target;
+    }
+    let ids = disabled.keys().copied().collect::<HashSet<_>>();
+    let dependents = disabled_dependents(&target, &ids);
+    let n = disabled.len();
+    disabled.extend(dependents);
+
+    let mut calls = disabled
+        .iter()
+        .map(|(&fid, reason)| {
+            let f = target.fn_of(fid);
+            DisabledCall {
+                group: target.group_name_of(f.gid).to_string(),
+                call: f.dec_name.clone(),
+                reason: reason.clone(),
+            }
+        })
+        .collect::<Vec<_>>();
+    calls.sort_unstable();
+    for c in calls.iter() {
+        info!("Disabled {}::{}: {}", c.group, c.call, c.reason);
+    }
+    report.extend(calls);
+    warn!(
+        "Disabled {} calls, {} calls depending on them",
+        n,
+        disabled.len() - n
+    );
+
+    let target = target.without(&disabled.keys().copied().collect());
+    if target.fns.is_empty() {
+        exits!(exitcode::CONFIG, "All calls are disabled");
+    }
+    target
+}
+
+/// Write disabled calls to `DISABLED_CALLS`, next to failed case report.
+async fn persist_disabled(disabled: &[DisabledCall]) {
+    let report = serde_json::to_string_pretty(disabled).unwrap();
+    write(DISABLED_CALLS, report).await.unwrap_or_else(|e| {
+        exits!(
+            exitcode::IOERR,
+            "Fail to persist disabled calls to {} : {}",
+            DISABLED_CALLS,
+            e
+        )
+    })
+}
+
 fn check_corpus(t: &Target, corpus: &[Prog]) {
     for p in corpus.iter() {
         if !t.groups.contains_key(&p.gid) {
@@ -0,0 +1,285 @@
+//! Metrics in prometheus text exposition format, served at `/metrics` by dashboard.
+
+use crate::schedule::{GroupStat, StrategyStat};
+use crate::stats::{Stats, VmInfo};
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+
+/// Snapshot of everything exported.
+pub struct Metrics {
+    pub uptime: u64,
+    pub exec_rate: f64,
+    pub stats: Stats,
+    pub vms: Vec<VmInfo>,
+    /// Crash count of each title
+    pub crashes: HashMap<String, usize>,
+}
+
+impl Metrics {
+    pub fn render(&self) -> String {
+        let mut buf = String::new();
+        let s = &self.stats;
+        gauge(
+            &mut buf,
+            "uptime_seconds",
+            "Seconds since fuzzer started.",
+            self.uptime,
+        );
+        counter(
+            &mut buf,
+            "exec_total",
+            "Number of executed programs.",
+            s.exec,
+        );
+        gauge(
+            &mut buf,
+            "exec_per_second",
+            "Executed programs per second over last minute.",
+            self.exec_rate,
+        );
+        gauge(&mut buf, "blocks", "Number of covered blocks.", s.blocks);
+        gauge(
+            &mut buf,
+            "branches",
+            "Number of covered branches.",
+            s.branches,
+        );
+        gauge(
+            &mut buf,
+            "corpus",
+            "Number of programs in corpus.",
+            s.corpus,
+        );
+        gauge(
+            &mut buf,
+            "candidates",
+            "Number of programs waiting to be executed.",
+            s.candidates,
+        );
+        counter(
+            &mut buf,
+            "failed_total",
+            "Number of failed cases.",
+            s.failed_case,
+        );
+
+        self.per_strategy(
+            &mut buf,
+            "strategy_execs_total",
+            "counter",
+            "Number of programs from each strategy.",
+            |x| x.execs.to_string(),
+        );
+        self.per_strategy(
+            &mut buf,
+            "strategy_found_total",
+            "counter",
+            "Number of programs from each strategy finding new signal.",
+            |x| x.found.to_string(),
+        );
+        self.per_strategy(
+            &mut buf,
+            "strategy_probability",
+            "gauge",
+            "Probability of choosing each strategy for next program.",
+            |x| x.prob.to_string(),
+        );
+
+        self.per_group(
+            &mut buf,
+            "group_execs_total",
+            "counter",
+            "Number of programs of each group.",
+            |g| g.execs.to_string(),
+        );
+        self.per_group(
+            &mut buf,
+            "group_blocks_total",
+            "counter",
+            "Number of new blocks found by programs of each group.",
+            |g| g.blocks.to_string(),
+        );
+        self.per_group(
+            &mut buf,
+            "group_probability",
+            "gauge",
+            "Probability of generating programs of each group.",
+            |g| g.prob.to_string(),
+        );
+
+        header(
+            &mut buf,
+            "crashes_total",
+            "counter",
+            "Number of crashes of each title.",
+        );
+        let mut crashes = self.crashes.iter().collect::<Vec<_>>();
+        crashes.sort();
+        for (title, n) in crashes {
+            let title = escape(title);
+            writeln!(buf, "healer_crashes_total{{title=\"{}\"}} {}", title, n).unwrap();
+        }
+
+        self.per_vm(
+            &mut buf,
+            "vm_restarts_total",
+            "Number of guest restarts of each vm.",
+            |vm| vm.boots.saturating_sub(1),
+        );
+        self.per_vm(
+            &mut buf,
+            "vm_crashes_total",
+            "Number of crashes of each vm.",
+            |vm| vm.crashes,
+        );
+        self.per_vm(
+            &mut buf,
+            "executor_reconnects_total",
+            "Number of executor restarts without rebooting guest.",
+            |vm| vm.reconnects,
+        );
+        buf
+    }
+
+    fn per_strategy<F: Fn(&StrategyStat) -> String>(
+        &self,
+        buf: &mut String,
+        name: &str,
+        kind: &str,
+        help: &str,
+        f: F,
+    ) {
+        header(buf, name, kind, help);
+        for x in self.stats.strategies.iter() {
+            writeln!(buf, "healer_{}{{strategy=\"{}\"}} {}", name, x.name, f(x)).unwrap();
+        }
+    }
+
+    fn per_group<F: Fn(&GroupStat) -> String>(
+        &self,
+        buf: &mut String,
+        name: &str,
+        kind: &str,
+        help: &str,
+        f: F,
+    ) {
+        header(buf, name, kind, help);
+        for g in self.stats.groups.iter() {
+            let group = escape(&g.name);
+            writeln!(buf, "healer_{}{{group=\"{}\"}} {}", name, group, f(g)).unwrap();
+        }
+    }
+
+    fn per_vm<F: Fn(&VmInfo) -> usize>(&self, buf: &mut String, name: &str, help: &str, f: F) {
+        header(buf, name, "counter", help);
+        for vm in self.vms.iter() {
+            writeln!(buf, "healer_{}{{vm=\"{}\"}} {}", name, vm.id, f(vm)).unwrap();
+        }
+    }
+}
+
+fn gauge<T: Display>(buf: &mut String, name: &str, help: &str, val: T) {
+    header(buf, name, "gauge", help);
+    writeln!(buf, "healer_{} {}", name, val).unwrap();
+}
+
+fn counter<T: Display>(buf: &mut String, name: &str, help: &str, val: T) {
+    header(buf, name, "counter", help);
+    writeln!(buf, "healer_{} {}", name, val).unwrap();
+}
+
+fn header(buf: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(buf, "# HELP healer_{} {}", name, help).unwrap();
+    writeln!(buf, "# TYPE healer_{} {}", name, kind).unwrap();
+}
+
+/// Escape label value: backslash, double-quote and line feed.
+fn escape(val: &str) -> String {
+    let mut ret = String::with_capacity(val.len());
+    for c in val.chars() {
+        match c {
+            '\\' => ret.push_str("\\\\"),
+            '"' => ret.push_str("\\\""),
+            '\n' => ret.push_str("\\n"),
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::VmState;
+    use chrono::Local;
+
+    #[test]
+    fn render() {
+        let stats = Stats {
+            corpus: 3,
+            blocks: 100,
+            branches: 120,
+            exec: 4096,
+            candidates: 7,
+            normal_case: 3,
+            failed_case: 2,
+            crashed_case: 3,
+            strategies: vec![StrategyStat {
+                name: "seq_reuse".to_string(),
+                execs: 100,
+                found: 5,
+                rate: 0.05,
+                prob: 0.25,
+            }],
+            groups: vec![GroupStat {
+                name: "Foo".to_string(),
+                execs: 200,
+                found: 4,
+                blocks: 12,
+                idle: Some(30),
+                prob: 0.5,
+            }],
+        };
+        let vm = VmInfo {
+            id: 1,
+            state: VmState::Fuzzing,
+            since: Local::now(),
+            exec: 2048,
+            crashes: 3,
+            boots: 4,
+            reconnects: 2,
+        };
+        let mut crashes = HashMap::new();
+        crashes.insert("KASAN: use-after-free Read in foo".to_string(), 2);
+        crashes.insert("WARNING in \"bar\"\\baz".to_string(), 1);
+        let m = Metrics {
+            uptime: 60,
+            exec_rate: 68.5,
+            stats,
+            vms: vec![vm],
+            crashes,
+        };
+        let text = m.render();
+
+        assert!(text.contains("# TYPE healer_exec_total counter\nhealer_exec_total 4096\n"));
+        assert!(text.contains("healer_exec_per_second 68.5\n"));
+        assert!(text.contains("healer_blocks 100\n"));
+        assert!(text.contains("healer_candidates 7\n"));
+        assert!(
+            text.contains("healer_crashes_total{title=\"KASAN: use-after-free Read in foo\"} 2\n")
+        );
+        assert!(text.contains("healer_crashes_total{title=\"WARNING in \\\"bar\\\"\\\\baz\"} 1\n"));
+        assert!(text.contains("healer_strategy_found_total{strategy=\"seq_reuse\"} 5\n"));
+        assert!(text.contains("healer_strategy_probability{strategy=\"seq_reuse\"} 0.25\n"));
+        assert!(text.contains("healer_group_blocks_total{group=\"Foo\"} 12\n"));
+        assert!(text.contains("healer_vm_restarts_total{vm=\"1\"} 3\n"));
+        assert!(text.contains("healer_executor_reconnects_total{vm=\"1\"} 2\n"));
+        for line in text.lines() {
+            assert!(
+                line.starts_with("# HELP healer_")
+                    || line.starts_with("# TYPE healer_")
+                    || line.starts_with("healer_")
+            );
+        }
+    }
+}
@@ -0,0 +1,491 @@
+//! Parser of kernel oops in console log.
+//!
+//! Extracts a stable title from report, e.g. "KASAN: use-after-free Read in foo",
+//! so that the same bug with different timestamps or addresses can be recognized.
+use regex::Regex;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BugType {
+    Kasan,
+    Kcsan,
+    Bug,
+    Warning,
+    Gpf,
+    Lockdep,
+    HungTask,
+    Panic,
+}
+
+impl fmt::Display for BugType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BugType::Kasan => "KASAN",
+            BugType::Kcsan => "KCSAN",
+            BugType::Bug => "BUG",
+            BugType::Warning => "WARNING",
+            BugType::Gpf => "GPF",
+            BugType::Lockdep => "LOCKDEP",
+            BugType::HungTask => "HUNG-TASK",
+            BugType::Panic => "PANIC",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Bad memory access reported by KASAN or KCSAN.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Access {
+    /// Read or Write
+    pub kind: String,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Oops {
+    pub title: String,
+    pub bug_type: BugType,
+    pub access: Option<Access>,
+    /// Functions of call trace, without offset, module and unreliable entries.
+    pub frames: Vec<String>,
+}
+
+/// Frames of reporting or scheduling code, never used in title.
+const SKIP_FRAMES: &[&str] = &[
+    "dump_stack",
+    "__dump_stack",
+    "show_stack",
+    "print_address_description",
+    "print_report",
+    "kasan_",
+    "__kasan_",
+    "__asan_",
+    "check_memory_region",
+    "kcsan_",
+    "__kcsan_",
+    "__tsan_",
+    "__warn",
+    "warn_slowpath",
+    "report_bug",
+    "handle_bug",
+    "exc_invalid_op",
+    "asm_exc_invalid_op",
+    "do_error_trap",
+    "do_invalid_op",
+    "invalid_op",
+    "panic",
+    "__might_sleep",
+    "___might_sleep",
+    "__might_fault",
+    "__schedule",
+    "schedule",
+    "io_schedule",
+    "preempt_schedule",
+    "context_switch",
+    "__mutex_lock",
+    "__lock_sock",
+    "mutex_lock",
+    "rwsem_down",
+    "down_read",
+    "down_write",
+    "wait_for_completion",
+    "lock_acquire",
+    "__lock_acquire",
+    "check_noncircular",
+    "lockdep_",
+];
+
+lazy_static! {
+    static ref PREFIX: Regex = Regex::new(r"^\[\s*\d+\.\d+\]\s*(\[\s*[TC]\d+\]\s*)?").unwrap();
+    static ref FRAME: Regex =
+        Regex::new(r"^(?:\[<[0-9a-f]+>\]\s*)?([A-Za-z0-9_.$]+)(?:\+0x[0-9a-f]+/0x[0-9a-f]+| \S+:\d+ \[inline\])").unwrap();
+    static ref RIP: Regex =
+        Regex::new(r"RIP: [0-9a-f]{4}:(?:\[<[0-9a-f]+>\]\s*)?([A-Za-z0-9_.$]+)\+0x").unwrap();
+    static ref FN_SUFFIX: Regex = Regex::new(r"\.(isra|constprop|part|cold|llvm)(\.\d+)?").unwrap();
+    static ref KASAN: Regex = Regex::new(r"BUG: KASAN: ([a-z\-]+) in ([A-Za-z0-9_.$]+)").unwrap();
+    static ref KASAN_ACCESS: Regex = Regex::new(r"(Read|Write) of size (\d+)").unwrap();
+    static ref KCSAN: Regex = Regex::new(r"BUG: KCSAN: ([a-z\-]+) in (.+)$").unwrap();
+    static ref KCSAN_ACCESS: Regex =
+        Regex::new(r"^(read|write)(?:-write)?(?: \(marked\))? to 0x[0-9a-f]+ of (\d+) bytes").unwrap();
+    static ref LOCKDEP: Regex = Regex::new(
+        r"WARNING: (possible circular locking dependency|possible recursive locking|possible irq lock inversion dependency|inconsistent lock state)"
+    )
+    .unwrap();
+    static ref LOCK_AT: Regex = Regex::new(r"at: ([A-Za-z0-9_.$]+)\+0x").unwrap();
+    static ref RCU: Regex = Regex::new(r"WARNING: suspicious RCU usage").unwrap();
+    static ref RCU_AT: Regex = Regex::new(r"^(\S+:\d+) suspicious").unwrap();
+    static ref HUNG_TASK: Regex = Regex::new(r"INFO: task .+ blocked for more than \d+ seconds").unwrap();
+    static ref GPF: Regex = Regex::new(r"general protection fault").unwrap();
+    static ref WARNING: Regex =
+        Regex::new(r"WARNING: (?:CPU: \d+ PID: \d+ )?at \S+ ([A-Za-z0-9_.$]+)").unwrap();
+    static ref KERNEL_BUG: Regex = Regex::new(r"kernel BUG at \S+").unwrap();
+    static ref BUG: Regex = Regex::new(r"BUG: (.+)$").unwrap();
+    static ref PANIC: Regex = Regex::new(r"Kernel panic - not syncing: (.+)$").unwrap();
+}
+
+/// Parse first oops in console log, None if there isn't any.
+pub fn parse(log: &str) -> Option<Oops> {
+    let lines = log
+        .lines()
+        .map(|l| PREFIX.replace(l.trim_end(), "").to_string())
+        .collect::<Vec<_>>();
+
+    for (i, l) in lines.iter().enumerate() {
+        if let Some(oops) = parse_header(&lines, i, l) {
+            return Some(oops);
+        }
+    }
+    // Panic follows other reports, e.g. panic_on_warn, so it's the last choice.
+    for (i, l) in lines.iter().enumerate() {
+        if let Some(cap) = PANIC.captures(l) {
+            let frames = frames_after(&lines, i);
+            return Some(Oops {
+                title: format!("kernel panic: {}", cap[1].trim_end_matches('!')),
+                bug_type: BugType::Panic,
+                access: None,
+                frames,
+            });
+        }
+    }
+    None
+}
+
+fn parse_header(lines: &[String], i: usize, l: &str) -> Option<Oops> {
+    let frames = || frames_after(lines, i);
+
+    if let Some(cap) = KASAN.captures(l) {
+        let access = lines[i + 1..]
+            .iter()
+            .take(4)
+            .find_map(|l| KASAN_ACCESS.captures(l))
+            .and_then(|cap| {
+                Some(Access {
+                    kind: cap[1].to_string(),
+                    size: cap[2].parse().ok()?,
+                })
+            });
+        let title = match access.as_ref() {
+            Some(a) => format!("KASAN: {} {} in {}", &cap[1], a.kind, clean_fn(&cap[2])),
+            None => format!("KASAN: {} in {}", &cap[1], clean_fn(&cap[2])),
+        };
+        return Some(Oops {
+            title,
+            bug_type: BugType::Kasan,
+            access,
+            frames: frames(),
+        });
+    }
+
+    if let Some(cap) = KCSAN.captures(l) {
+        let fns = cap[2]
+            .split('/')
+            .map(|f| clean_fn(f.trim().split('+').next().unwrap()))
+            .collect::<Vec<_>>()
+            .join(" / ");
+        let access = lines[i + 1..]
+            .iter()
+            .take(8)
+            .find_map(|l| KCSAN_ACCESS.captures(l))
+            .and_then(|cap| {
+                Some(Access {
+                    kind: if &cap[1] == "read" { "Read" } else { "Write" }.to_string(),
+                    size: cap[2].parse().ok()?,
+                })
+            });
+        return Some(Oops {
+            title: format!("KCSAN: {} in {}", &cap[1], fns),
+            bug_type: BugType::Kcsan,
+            access,
+            frames: frames(),
+        });
+    }
+
+    if let Some(cap) = LOCKDEP.captures(l) {
+        let kind = if &cap[1] == "inconsistent lock state" {
+            "inconsistent lock state"
+        } else {
+            "possible deadlock"
+        };
+        let frames = frames();
+        let at = lines[i + 1..]
+            .iter()
+            .take(16)
+            .find_map(|l| LOCK_AT.captures(l))
+            .map(|cap| clean_fn(&cap[1]))
+            .or_else(|| title_frame(&frames));
+        return Some(Oops {
+            title: with_location(kind, at),
+            bug_type: BugType::Lockdep,
+            access: None,
+            frames,
+        });
+    }
+
+    if RCU.is_match(l) {
+        let at = lines[i + 1..]
+            .iter()
+            .take(4)
+            .find_map(|l| RCU_AT.captures(l))
+            .map(|cap| cap[1].to_string());
+        let title = match at {
+            Some(at) => format!("suspicious RCU usage at {}", at),
+            None => "suspicious RCU usage".to_string(),
+        };
+        return Some(Oops {
+            title,
+            bug_type: BugType::Lockdep,
+            access: None,
+            frames: frames(),
+        });
+    }
+
+    if HUNG_TASK.is_match(l) {
+        let frames = frames();
+        return Some(Oops {
+            title: with_location("INFO: task hung", title_frame(&frames)),
+            bug_type: BugType::HungTask,
+            access: None,
+            frames,
+        });
+    }
+
+    if GPF.is_match(l) {
+        let frames = frames();
+        return Some(Oops {
+            title: with_location("general protection fault", location(lines, i, &frames)),
+            bug_type: BugType::Gpf,
+            access: None,
+            frames,
+        });
+    }
+
+    if let Some(cap) = WARNING.captures(l) {
+        return Some(Oops {
+            title: format!("WARNING in {}", clean_fn(&cap[1])),
+            bug_type: BugType::Warning,
+            access: None,
+            frames: frames(),
+        });
+    }
+
+    if KERNEL_BUG.is_match(l) {
+        let frames = frames();
+        return Some(Oops {
+            title: with_location("kernel BUG", location(lines, i, &frames)),
+            bug_type: BugType::Bug,
+            access: None,
+            frames,
+        });
+    }
+
+    if let Some(cap) = BUG.captures(l) {
+        let desc = bug_desc(&cap[1]);
+        let frames = frames();
+        return Some(Oops {
+            title: with_location(&format!("BUG: {}", desc), location(lines, i, &frames)),
+            bug_type: BugType::Bug,
+            access: None,
+            frames,
+        });
+    }
+    None
+}
+
+/// Description of `BUG: ...` without address, task or source location.
+fn bug_desc(s: &str) -> &str {
+    let mut end = s.len();
+    for sep in &[" at ", " for ", ", ", ": ", " - "] {
+        if let Some(i) = s.find(sep) {
+            end = end.min(i);
+        }
+    }
+    s[..end].trim_end_matches('!')
+}
+
+fn with_location(what: &str, at: Option<String>) -> String {
+    match at {
+        Some(at) => format!("{} in {}", what, at),
+        None => what.to_string(),
+    }
+}
+
+/// Faulting function: RIP after header, or first interesting frame.
+fn location(lines: &[String], i: usize, frames: &[String]) -> Option<String> {
+    lines[i..]
+        .iter()
+        .take(64)
+        .find_map(|l| RIP.captures(l))
+        .map(|cap| clean_fn(&cap[1]))
+        .filter(|f| !skip(f))
+        .or_else(|| title_frame(frames))
+}
+
+fn title_frame(frames: &[String]) -> Option<String> {
+    frames.iter().find(|f| !skip(f)).cloned()
+}
+
+fn skip(f: &str) -> bool {
+    SKIP_FRAMES.iter().any(|s| f.starts_with(s))
+}
+
+/// Frames of first call trace after line `i`.
+fn frames_after(lines: &[String], i: usize) -> Vec<String> {
+    let mut frames = Vec::new();
+    let start = match lines[i..].iter().position(|l| l.trim() == "Call Trace:") {
+        Some(n) => i + n + 1,
+        None => return frames,
+    };
+
+    for l in lines[start..].iter().map(|l| l.trim()) {
+        if l.starts_with('<') && l.ends_with('>') && !l.contains(' ') {
+            // <IRQ>, </IRQ>, <TASK> ...
+            continue;
+        }
+        if l.starts_with("? ") {
+            continue;
+        }
+        match FRAME.captures(l) {
+            Some(cap) => frames.push(clean_fn(&cap[1])),
+            None => break,
+        }
+    }
+    frames
+}
+
+/// Remove compiler generated suffix, e.g. `foo.isra.0` -> `foo`.
+fn clean_fn(f: &str) -> String {
+    FN_SUFFIX.replace_all(f, "").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(log: &str, title: &str, bug_type: BugType, size: Option<usize>, frame: &str) {
+        let oops = parse(log).unwrap();
+        assert_eq!(oops.title, title);
+        assert_eq!(oops.bug_type, bug_type);
+        assert_eq!(oops.access.map(|a| a.size), size);
+        assert_eq!(oops.frames.first().map(String::as_str), Some(frame));
+    }
+
+    #[test]
+    fn kasan() {
+        check(
+            include_str!("testdata/oops/kasan_uaf.log"),
+            "KASAN: use-after-free Read in tcp_retransmit_timer",
+            BugType::Kasan,
+            Some(8),
+            "dump_stack",
+        );
+        check(
+            include_str!("testdata/oops/kasan_oob.log"),
+            "KASAN: slab-out-of-bounds Write in hci_sock_sendmsg",
+            BugType::Kasan,
+            Some(4),
+            "dump_stack_lvl",
+        );
+    }
+
+    #[test]
+    fn oversized_access() {
+        let log = "BUG: KASAN: use-after-free in foo+0x10/0x20\n\
+                   Read of size 99999999999999999999999 at addr ffff888000000000\n";
+        let oops = parse(log).unwrap();
+        assert_eq!(oops.title, "KASAN: use-after-free in foo");
+        assert!(oops.access.is_none());
+
+        let log = "BUG: KCSAN: data-race in foo / bar\n\
+                   \n\
+                   write to 0xffff888000000000 of 99999999999999999999999 bytes by task 1 on cpu 0:\n";
+        assert!(parse(log).unwrap().access.is_none());
+    }
+
+    #[test]
+    fn kcsan() {
+        check(
+            include_str!("testdata/oops/kcsan.log"),
+            "KCSAN: data-race in fsnotify / fsnotify",
+            BugType::Kcsan,
+            Some(4),
+            "fsnotify",
+        );
+    }
+
+    #[test]
+    fn bug() {
+        check(
+            include_str!("testdata/oops/bug_null_deref.log"),
+            "BUG: kernel NULL pointer dereference in vcs_scr_readw",
+            BugType::Bug,
+            None,
+            "vcs_read",
+        );
+        check(
+            include_str!("testdata/oops/kernel_bug.log"),
+            "kernel BUG in ext4_es_cache_extent",
+            BugType::Bug,
+            None,
+            "ext4_es_cache_extent",
+        );
+    }
+
+    #[test]
+    fn warning() {
+        check(
+            include_str!("testdata/oops/warning.log"),
+            "WARNING in __alloc_pages_nodemask",
+            BugType::Warning,
+            None,
+            "dump_stack",
+        );
+    }
+
+    #[test]
+    fn gpf() {
+        check(
+            include_str!("testdata/oops/gpf.log"),
+            "general protection fault in qdisc_hash_add",
+            BugType::Gpf,
+            None,
+            "tc_modify_qdisc",
+        );
+    }
+
+    #[test]
+    fn lockdep() {
+        check(
+            include_str!("testdata/oops/lockdep.log"),
+            "possible deadlock in blkdev_put",
+            BugType::Lockdep,
+            None,
+            "dump_stack",
+        );
+    }
+
+    #[test]
+    fn hung_task() {
+        check(
+            include_str!("testdata/oops/hung_task.log"),
+            "INFO: task hung in lock_sock_nested",
+            BugType::HungTask,
+            None,
+            "context_switch",
+        );
+    }
+
+    #[test]
+    fn no_oops() {
+        assert!(parse(include_str!("testdata/oops/normal.log")).is_none());
+    }
+
+    #[test]
+    fn stable_title() {
+        let log = include_str!("testdata/oops/kasan_uaf.log");
+        let other = log
+            .replace("[  102.", "[ 7355.")
+            .replace("ffff88806a1b2c40", "ffff888012345678")
+            .replace("syz-executor.3/9876", "syz-executor.0/123");
+        assert_eq!(parse(log).unwrap().title, parse(&other).unwrap().title);
+    }
+}
@@ -1,7 +1,9 @@
+use crate::classify::Analysis;
 use crate::feedback::{Block, Branch};
 use crate::guest::Crash;
 #[cfg(feature = "mail")]
 use crate::mail;
+use crate::oops::{self, Oops};
 use chrono::prelude::*;
 use chrono::DateTime;
 use circular_queue::CircularQueue;
@@ -12,15 +14,22 @@ use executor::Reason;
 #[cfg(feature = "mail")]
 use lettre_email::EmailBuilder;
 use serde::Serialize;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::fs::write;
 use tokio::sync::Mutex;
 
+/// Title for counting crashes whose report can't be parsed.
+pub const UNKNOWN_TITLE: &str = "unknown";
+
 pub struct TestCaseRecord {
     normal: Mutex<CircularQueue<ExecutedCase>>,
     failed: Mutex<CircularQueue<FailedCase>>,
     crash: Mutex<CircularQueue<CrashedCase>>,
+    /// Number of crashes of each oops title, `UNKNOWN_TITLE` if not parsed.
+    crash_counts: Mutex<HashMap<String, usize>>,
+    /// All failed cases classified, kept after dropped from `failed`.
+    analysis: Mutex<Analysis>,
 
     target: Arc<Target>,
     id_n: Mutex<usize>,
@@ -57,6 +66,9 @@ pub struct FailedCase {
     pub meta: TestCase,
     pub p: String,
     pub reason: String,
+    /// Declared name of each call of prog.
+    #[serde(default)]
+    pub calls: Vec<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -65,6 +77,34 @@ pub struct CrashedCase {
     pub p: String,
     pub repo: bool,
     pub crash: Crash,
+    /// Parsed report of crash, title of it is used as title of case.
+    #[serde(default)]
+    pub oops: Option<Oops>,
+}
+
+impl CrashedCase {
+    /// Title may contain '/' or spaces, use this to name files.
+    pub fn file_name(&self) -> String {
+        if self.oops.is_some() {
+            format!("{}_{}", sanitize_title(&self.meta.title), self.meta.id)
+        } else {
+            self.meta.title.clone()
+        }
+    }
+}
+
+/// Replace chars other than alphanumeric and '-' in title with '_'.
+pub fn sanitize_title(title: &str) -> String {
+    title
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
 }
 
 #[allow(clippy::len_without_is_empty)]
@@ -74,6 +114,8 @@ impl TestCaseRecord {
             normal: Mutex::new(CircularQueue::with_capacity(1024 * 64)),
             failed: Mutex::new(CircularQueue::with_capacity(1024 * 64)),
             crash: Mutex::new(CircularQueue::with_capacity(1024)),
+            crash_counts: Mutex::new(HashMap::new()),
+            analysis: Mutex::new(Analysis::default()),
             target: t,
 
             id_n: Mutex::new(0),
@@ -119,21 +161,37 @@ impl TestCaseRecord {
         }
     }
 
-    pub async fn insert_crash(&self, p: Prog, crash: Crash, repo: bool) {
+    /// Record crash, return name of persisted file in `./crashes`.
+    pub async fn insert_crash(&self, p: Prog, crash: Crash, repo: bool) -> String {
         let id = self.next_id().await;
         let stmts = to_script(&p, &self.target);
+        let oops = oops::parse(&crash.inner);
+        let title = match oops.as_ref() {
+            Some(oops) => oops.title.clone(),
+            None => self.title_of(&p, id),
+        };
+        {
+            let mut counts = self.crash_counts.lock().await;
+            let key = match oops.as_ref() {
+                Some(oops) => oops.title.clone(),
+                None => UNKNOWN_TITLE.to_string(),
+            };
+            *counts.entry(key).or_insert(0) += 1;
+        }
         let case = CrashedCase {
             meta: TestCase {
                 id,
-                title: self.title_of(&p, id),
+                title,
                 test_time: Local::now(),
             },
             p: stmts.to_string(),
             crash,
             repo,
+            oops,
         };
 
         self.persist_crash_case(&case).await;
+        let name = case.file_name();
 
         {
             let mut crashes = self.crash.lock().await;
@@ -143,11 +201,22 @@ impl TestCaseRecord {
             let mut crashed_num = self.crashed_num.lock().await;
             *crashed_num += 1;
         }
+        name
     }
 
     pub async fn insert_failed(&self, p: Prog, reason: Reason) {
         let id = self.next_id().await;
         let stmts = to_script(&p, &self.target);
+        let calls = p
+            .calls
+            .iter()
+            .map(|c| self.target.fn_of(c.fid).dec_name.clone())
+            .collect::<Vec<_>>();
+        let reason = reason.to_string();
+        {
+            let mut analysis = self.analysis.lock().await;
+            analysis.add(&calls, &reason, Some(&self.target));
+        }
 
         let case = FailedCase {
             meta: TestCase {
@@ -156,7 +225,8 @@ impl TestCaseRecord {
                 test_time: Local::now(),
             },
             p: stmts.to_string(),
-            reason: reason.to_string(),
+            reason,
+            calls,
         };
         {
             let mut failed_cases = self.failed.lock().await;
@@ -168,8 +238,34 @@ impl TestCaseRecord {
         }
     }
 
+    /// Recorded crashes, oldest first.
+    pub async fn crashes(&self) -> Vec<CrashedCase> {
+        let cases = self.crash.lock().await;
+        cases.asc_iter().cloned().collect()
+    }
+
+    pub async fn crash_counts(&self) -> HashMap<String, usize> {
+        let counts = self.crash_counts.lock().await;
+        counts.clone()
+    }
+
+    /// Last `n` failed cases, newest first.
+    pub async fn recent_failed(&self, n: usize) -> Vec<FailedCase> {
+        let cases = self.failed.lock().await;
+        cases.iter().take(n).cloned().collect()
+    }
+
+    pub async fn analysis(&self) -> Analysis {
+        let analysis = self.analysis.lock().await;
+        analysis.clone()
+    }
+
     pub async fn psersist(&self) {
-        tokio::join!(self.persist_normal_case(), self.persist_failed_case());
+        tokio::join!(
+            self.persist_normal_case(),
+            self.persist_failed_case(),
+            self.persist_analysis()
+        );
     }
 
     pub async fn len(&self) -> (usize, usize, usize) {
@@ -227,8 +323,25 @@ impl TestCaseRecord {
         })
     }
 
+    async fn persist_analysis(&self) {
+        let analysis = self.analysis.lock().await;
+        if analysis.total == 0 {
+            return;
+        }
+        let path = "./failed_analysis.json";
+        let report = serde_json::to_string_pretty(&*analysis).unwrap();
+        write(&path, report).await.unwrap_or_else(|e| {
+            exits!(
+                exitcode::IOERR,
+                "Fail to persist failed case analysis to {} : {}",
+                path,
+                e
+            )
+        })
+    }
+
     async fn persist_crash_case(&self, case: &CrashedCase) {
-        let path = format!("./crashes/{}", &case.meta.title);
+        let path = format!("./crashes/{}", case.file_name());
         let crash = serde_json::to_string_pretty(case).unwrap();
 
         #[cfg(feature = "mail")]
@@ -0,0 +1,353 @@
+//! Reproduce crash with programs executed before it.
+//!
+//! Each vm keeps last `history` programs it executed. After a crash, these
+//! programs are replayed on guest, chunks of them are removed as long as the
+//! same crash title is still triggered, then the last program is minimized.
+//! Relative order of remaining programs is always kept. Guest is restarted
+//! before every attempt, so no state is left by programs of the last one.
+
+use crate::exec::Executor;
+use crate::stats::VmState;
+use core::c::to_script;
+use core::minimize::remove;
+use core::prog::Prog;
+use core::target::Target;
+use std::future::Future;
+use std::pin::Pin;
+use std::process::exit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::fs::write;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReproConf {
+    /// Number of programs kept for each vm
+    pub history: usize,
+}
+
+impl Default for ReproConf {
+    fn default() -> Self {
+        Self { history: 16 }
+    }
+}
+
+impl ReproConf {
+    pub fn check(&self) {
+        if self.history == 0 || self.history > 1024 {
+            eprintln!(
+                "Config Error: invalid repro history {}, history must between (0,1024]",
+                self.history
+            );
+            exit(exitcode::CONFIG)
+        }
+    }
+}
+
+/// Programs that trigger crash with `title` when executed in order on a
+/// fresh guest.
+pub struct Repro {
+    pub title: String,
+    pub progs: Vec<Prog>,
+}
+
+/// Readable form of `Repro`, persisted as `{crash}.repro`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReproCase {
+    pub title: String,
+    pub progs: Vec<String>,
+}
+
+impl Repro {
+    /// Save next to crash `name`: `{name}.repro` for human and
+    /// `{name}.repro.bin` for replaying.
+    pub async fn persist(&self, name: &str, target: &Target) {
+        let case = ReproCase {
+            title: self.title.clone(),
+            progs: self
+                .progs
+                .iter()
+                .map(|p| to_script(p, target).to_string())
+                .collect(),
+        };
+        let path = format!("./crashes/{}.repro", name);
+        let text = serde_json::to_string_pretty(&case).unwrap();
+        write(&path, text).await.unwrap_or_else(|e| {
+            exits!(exitcode::IOERR, "Fail to persist repro to {} : {}", path, e)
+        });
+
+        let path = format!("./crashes/{}.repro.bin", name);
+        let bin = bincode::serialize(&self.progs).unwrap();
+        write(&path, bin).await.unwrap_or_else(|e| {
+            exits!(exitcode::IOERR, "Fail to persist repro to {} : {}", path, e)
+        });
+    }
+}
+
+/// Try to reproduce crash with `title` with `history`, last of which
+/// triggered the crash. Guest is restarted before every attempt.
+pub async fn repro(
+    executor: &mut Executor,
+    target: &Target,
+    history: Vec<Prog>,
+    title: &str,
+    exec_cnt: &AtomicUsize,
+) -> Option<Repro> {
+    let mut r = Reproducer {
+        executor,
+        target,
+        title,
+        exec_cnt,
+    };
+    let progs = reduce(&mut r, history).await?;
+    Some(Repro {
+        title: title.to_string(),
+        progs,
+    })
+}
+
+/// Replays programs and tells number of them executed until the expected
+/// crash, if it happened. Any `FnMut(&[Prog]) -> Option<usize>` is one,
+/// which needs no restart.
+trait Oracle {
+    /// Bring guest back to fresh state.
+    fn restart<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+    fn replay<'a>(
+        &'a mut self,
+        progs: &'a [Prog],
+    ) -> Pin<Box<dyn Future<Output = Option<usize>> + Send + 'a>>;
+}
+
+impl<F: FnMut(&[Prog]) -> Option<usize>> Oracle for F {
+    fn restart<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async {})
+    }
+
+    fn replay<'a>(
+        &'a mut self,
+        progs: &'a [Prog],
+    ) -> Pin<Box<dyn Future<Output = Option<usize>> + Send + 'a>> {
+        let n = self(progs);
+        Box::pin(async move { n })
+    }
+}
+
+struct Reproducer<'a> {
+    executor: &'a mut Executor,
+    target: &'a Target,
+    title: &'a str,
+    exec_cnt: &'a AtomicUsize,
+}
+
+impl<'a> Reproducer<'a> {
+    async fn replay_on_guest(&mut self, progs: &[Prog]) -> Option<usize> {
+        self.executor.stat().set_state(VmState::Reproducing);
+        for (i, p) in progs.iter().enumerate() {
+            self.exec_cnt.fetch_add(1, Ordering::SeqCst);
+            if let Err(crash) = self.executor.exec(p, self.target).await {
+                let crash = crash.unwrap_or_default();
+                return if crash.title() == self.title {
+                    Some(i + 1)
+                } else {
+                    None
+                };
+            }
+        }
+        None
+    }
+}
+
+impl<'r> Oracle for Reproducer<'r> {
+    fn restart<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(self.executor.start())
+    }
+
+    fn replay<'a>(
+        &'a mut self,
+        progs: &'a [Prog],
+    ) -> Pin<Box<dyn Future<Output = Option<usize>> + Send + 'a>> {
+        Box::pin(self.replay_on_guest(progs))
+    }
+}
+
+/// Replay `progs` on a freshly restarted guest.
+async fn attempt<O: Oracle>(oracle: &mut O, progs: &[Prog]) -> Option<usize> {
+    oracle.restart().await;
+    oracle.replay(progs).await
+}
+
+/// Programs of `history` still crashing, see `bisect` and `minimize`.
+async fn reduce<O: Oracle>(oracle: &mut O, history: Vec<Prog>) -> Option<Vec<Prog>> {
+    let last = history.last()?.clone();
+    let mut progs = if attempt(oracle, std::slice::from_ref(&last)).await.is_some() {
+        vec![last]
+    } else {
+        let n = attempt(oracle, &history).await?;
+        history[..n].to_vec()
+    };
+    progs = bisect(oracle, progs).await;
+    let last = progs.pop().unwrap();
+    let last = minimize(oracle, &progs, last).await;
+    progs.push(last);
+    Some(progs)
+}
+
+/// Remove chunks of programs before the last one, halve size of chunk
+/// until single program.
+async fn bisect<O: Oracle>(oracle: &mut O, mut progs: Vec<Prog>) -> Vec<Prog> {
+    let mut chunk = progs.len() / 2;
+    while chunk != 0 {
+        let mut start = 0;
+        while start < progs.len() - 1 {
+            let end = usize::min(start + chunk, progs.len() - 1);
+            let mut candidate = progs[..start].to_vec();
+            candidate.extend_from_slice(&progs[end..]);
+            if let Some(n) = attempt(oracle, &candidate).await {
+                candidate.truncate(n);
+                progs = candidate;
+            } else {
+                start = end;
+            }
+        }
+        chunk /= 2;
+    }
+    progs
+}
+
+/// Remove calls of `p` while `prefix` followed by `p` still crashes.
+async fn minimize<O: Oracle>(oracle: &mut O, prefix: &[Prog], mut p: Prog) -> Prog {
+    let mut i = 0;
+    while p.len() > 1 && i != p.len() - 1 {
+        let p_orig = p.clone();
+        if !remove(&mut p, i) {
+            i += 1;
+            continue;
+        }
+        let mut progs = prefix.to_vec();
+        progs.push(p.clone());
+        if attempt(oracle, &progs).await != Some(progs.len()) {
+            p = p_orig;
+            i += 1;
+        }
+    }
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::prog::Call;
+    use tokio::runtime::Runtime;
+
+    fn run<F: Future>(f: F) -> F::Output {
+        Runtime::new().unwrap().block_on(f)
+    }
+
+    fn gids(progs: &[Prog]) -> Vec<usize> {
+        progs.iter().map(|p| p.gid).collect()
+    }
+
+    /// Crash at `last` if `needed` were executed before it.
+    fn oracle(needed: &'static [usize], last: usize) -> impl FnMut(&[Prog]) -> Option<usize> {
+        move |progs| {
+            let i = progs.iter().position(|p| p.gid == last)?;
+            let before = gids(&progs[..i]);
+            if needed.iter().all(|g| before.contains(g)) {
+                Some(i + 1)
+            } else {
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn bisect_history() {
+        let history = (0..10).map(Prog::new).collect::<Vec<_>>();
+        let mut o = oracle(&[2, 6], 9);
+        let progs = run(bisect(&mut o, history));
+        assert_eq!(gids(&progs), [2, 6, 9]);
+    }
+
+    #[test]
+    fn reduce_history() {
+        // crash triggered by program in the middle, the rest is dropped
+        let history = (0..10).map(Prog::new).collect::<Vec<_>>();
+        let mut o = oracle(&[3], 5);
+        let progs = run(reduce(&mut o, history.clone())).unwrap();
+        assert_eq!(gids(&progs), [3, 5]);
+
+        let mut o = oracle(&[], 9);
+        let progs = run(reduce(&mut o, history.clone())).unwrap();
+        assert_eq!(gids(&progs), [9]);
+
+        let mut o = |_: &[Prog]| None;
+        assert!(run(reduce(&mut o, history)).is_none());
+    }
+
+    /// Counts restarts, panics if programs run on a used guest.
+    struct Guest<F> {
+        oracle: F,
+        fresh: bool,
+        restarts: usize,
+        replays: usize,
+    }
+
+    impl<F: FnMut(&[Prog]) -> Option<usize> + Send> Oracle for Guest<F> {
+        fn restart<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+            self.fresh = true;
+            self.restarts += 1;
+            Box::pin(async {})
+        }
+
+        fn replay<'a>(
+            &'a mut self,
+            progs: &'a [Prog],
+        ) -> Pin<Box<dyn Future<Output = Option<usize>> + Send + 'a>> {
+            assert!(self.fresh, "replay on used guest");
+            self.fresh = false;
+            self.replays += 1;
+            let n = (self.oracle)(progs);
+            Box::pin(async move { n })
+        }
+    }
+
+    #[test]
+    fn restart_per_attempt() {
+        let history = (0..10).map(Prog::new).collect::<Vec<_>>();
+        let mut g = Guest {
+            oracle: oracle(&[2, 6], 9),
+            fresh: false,
+            restarts: 0,
+            replays: 0,
+        };
+        let progs = run(reduce(&mut g, history)).unwrap();
+        assert_eq!(gids(&progs), [2, 6, 9]);
+        assert!(g.replays > 1);
+        assert_eq!(g.restarts, g.replays);
+    }
+
+    #[test]
+    fn minimize_last() {
+        let mut p = Prog::new(0);
+        for fid in 0..6 {
+            p.add_call(Call::new(fid));
+        }
+        let prefix = vec![Prog::new(1)];
+        let mut o = |progs: &[Prog]| {
+            let fids = progs
+                .last()
+                .unwrap()
+                .calls
+                .iter()
+                .map(|c| c.fid)
+                .collect::<Vec<_>>();
+            if progs.len() == 2 && fids.contains(&1) && fids.contains(&3) {
+                Some(progs.len())
+            } else {
+                None
+            }
+        };
+        let p = run(minimize(&mut o, &prefix, p));
+        let fids = p.calls.iter().map(|c| c.fid).collect::<Vec<_>>();
+        assert_eq!(fids, [1, 3, 5]);
+    }
+}
@@ -0,0 +1,737 @@
+//! Scheduling of fuzzing, which prog to mutate, whether to generate or
+//! mutate next prog and which mutation operator to use.
+//!
+//! Seeds are chosen with probability proportional to their energy, which is
+//! computed from metadata of each prog like the power schedule of AFL: fast,
+//! short progs finding much signal get more energy, fresh progs get a bonus
+//! and energy decays with times a prog has been mutated. With directed
+//! fuzzing, energy halves for each step the prog is away from target.
+//! Energy is cached and sampled from a Fenwick tree, a mutated seed updates
+//! its own energy, an admitted one or `ENERGY_REFRESH` execs recompute all.
+//!
+//! Generation and each mutation operator are arms of a bandit, every prog
+//! records whether the strategy it came from found new signal. Without a
+//! fixed `mutate_ratio`, the ratio of mutation follows success rate of
+//! generation and mutation within `[MIN_MUTATE_RATIO, MAX_MUTATE_RATIO]`.
+//! Operators are chosen with probability proportional to their success rate,
+//! except for `OP_EXPLORE` of choices that are uniform, so no operator
+//! starves, like MOpt that moves probabilities toward efficient operators.
+//! Groups to generate progs of are chosen the same way by recent yield of
+//! their progs, with `GROUP_EXPLORE` of choices uniform.
+
+use crate::corpus::{SeedMeta, Seeds};
+use core::mutate::OPERATORS;
+use fots::types::GroupId;
+use rand::prelude::*;
+use std::collections::BTreeMap;
+use std::process::exit;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Mutations of each freshly admitted prog.
+pub const DEFAULT_SMASH: usize = 32;
+const MAX_SMASH: usize = 1024;
+/// Admitted progs waiting to be smashed at most, later ones are not smashed.
+pub const MAX_SMASH_PENDING: usize = 64;
+/// Share of progs smashed while any is waiting, the rest are generated or
+/// mutated as usual.
+const SMASH_SHARE: f64 = 0.5;
+/// Execs after admission during which a prog counts as fresh.
+const FRESH_EXECS: usize = 10_000;
+const FRESH_BONUS: f64 = 2.0;
+const MIN_MUTATE_RATIO: f64 = 0.1;
+const MAX_MUTATE_RATIO: f64 = 0.9;
+/// Ratio of uniform choices of mutation operator.
+const OP_EXPLORE: f64 = 0.1;
+/// Ratio of uniform choices of group, so exhausted groups still run.
+const GROUP_EXPLORE: f64 = 0.2;
+/// Counts of arms are halved after this many execs, so scheduling follows
+/// recent progress.
+const YIELD_WINDOW: usize = 10_000;
+/// Cached energy is recomputed after this many execs, as fresh bonus expires.
+const ENERGY_REFRESH: usize = 1_000;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleConf {
+    /// Choose seed by energy, uniformly if false, true by default
+    pub power: Option<bool>,
+    /// Fixed ratio of mutated progs, adapted to success rate if not set
+    pub mutate_ratio: Option<f64>,
+    /// Choose mutation operator by success rate, uniformly if false, true by default
+    pub bandit: Option<bool>,
+    /// Mutations of each prog right after it's admitted, 0 disables, 32 by default
+    pub smash: Option<usize>,
+}
+
+impl ScheduleConf {
+    pub fn check(&self) {
+        if let Some(ratio) = self.mutate_ratio {
+            if !(0.0..=1.0).contains(&ratio) {
+                eprintln!("Config Error: mutate ratio should be in [0, 1]");
+                exit(exitcode::CONFIG)
+            }
+        }
+        if self.smash.is_some_and(|n| n > MAX_SMASH) {
+            eprintln!(
+                "Config Error: smash should not be larger than {}",
+                MAX_SMASH
+            );
+            exit(exitcode::CONFIG)
+        }
+    }
+}
+
+/// Where a prog comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Candidate,
+    Gen,
+    /// Mutation with operator `OPERATORS[op]`
+    Mutate(usize),
+    /// Mutation of freshly admitted prog with operator `OPERATORS[op]`, not
+    /// counted in operator or mutate ratio choices.
+    Smash(usize),
+}
+
+/// New signal admitted to corpus from one prog.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Found {
+    pub blocks: usize,
+    pub branches: usize,
+}
+
+impl Found {
+    pub fn any(&self) -> bool {
+        self.blocks != 0 || self.branches != 0
+    }
+}
+
+/// Success rate of one strategy.
+#[derive(Debug, Clone, Serialize)]
+pub struct StrategyStat {
+    pub name: String,
+    pub execs: usize,
+    /// Execs finding new signal
+    pub found: usize,
+    pub rate: f64,
+    /// Current probability of choosing the strategy for next prog, for smash
+    /// the share it takes while any prog is waiting
+    pub prob: f64,
+}
+
+/// Progress of one group.
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupStat {
+    pub name: String,
+    pub execs: usize,
+    /// Execs finding new signal
+    pub found: usize,
+    /// New blocks found by progs of the group
+    pub blocks: usize,
+    /// Seconds since last discovery, `None` if nothing found yet
+    pub idle: Option<u64>,
+    /// Current probability of generating progs of the group
+    pub prob: f64,
+}
+
+#[derive(Debug)]
+struct GroupArm {
+    name: String,
+    arm: Arm,
+    blocks: usize,
+    last_found: Option<Instant>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Arm {
+    /// Decayed counts used for scheduling.
+    execs: usize,
+    found: usize,
+    /// Counts since start, for stats.
+    total_execs: usize,
+    total_found: usize,
+}
+
+impl Arm {
+    fn record(&mut self, found: bool) {
+        self.execs += 1;
+        self.total_execs += 1;
+        if found {
+            self.found += 1;
+            self.total_found += 1;
+        }
+    }
+
+    fn decay(&mut self) {
+        self.execs /= 2;
+        self.found /= 2;
+    }
+
+    /// Laplace smoothing, untried arms start at 0.5.
+    fn rate(&self) -> f64 {
+        (self.found + 1) as f64 / (self.execs + 2) as f64
+    }
+}
+
+#[derive(Debug)]
+struct Arms {
+    gen: Arm,
+    ops: Vec<Arm>,
+    smash: Arm,
+}
+
+impl Default for Arms {
+    fn default() -> Self {
+        Self {
+            gen: Arm::default(),
+            ops: vec![Arm::default(); OPERATORS.len()],
+            smash: Arm::default(),
+        }
+    }
+}
+
+impl Arms {
+    fn mutate_ratio(&self) -> f64 {
+        let execs = self.ops.iter().map(|a| a.execs).sum::<usize>();
+        let found = self.ops.iter().map(|a| a.found).sum::<usize>();
+        let gen = self.gen.rate();
+        let mutate = (found + 1) as f64 / (execs + 2) as f64;
+        (mutate / (gen + mutate)).clamp(MIN_MUTATE_RATIO, MAX_MUTATE_RATIO)
+    }
+
+    fn op_probs(&self, bandit: bool) -> Vec<f64> {
+        let explore = if bandit { OP_EXPLORE } else { 1.0 };
+        probs(self.ops.iter(), explore)
+    }
+}
+
+/// Probability of each arm, proportional to success rate except for
+/// `explore` share that is uniform.
+fn probs<'a, I: ExactSizeIterator<Item = &'a Arm>>(arms: I, explore: f64) -> Vec<f64> {
+    let n = arms.len() as f64;
+    let rates = arms.map(Arm::rate).collect::<Vec<_>>();
+    let total = rates.iter().sum::<f64>();
+    rates
+        .into_iter()
+        .map(|r| (1.0 - explore) * r / total + explore / n)
+        .collect()
+}
+
+/// Index chosen with probability `probs[i]`.
+fn choose_index(probs: &[f64]) -> usize {
+    let mut x = thread_rng().gen::<f64>();
+    for (i, p) in probs.iter().enumerate() {
+        if x < *p {
+            return i;
+        }
+        x -= p;
+    }
+    probs.len() - 1
+}
+
+/// Cached energy of seeds, see `Schedule::choose`.
+#[derive(Debug, Default)]
+struct Energy {
+    energy: Vec<f64>,
+    /// Fenwick tree of `energy`, for sampling and updating in log time.
+    tree: Vec<f64>,
+    total: f64,
+    avg: Option<Avg>,
+    /// Exec count when computed.
+    at: usize,
+}
+
+impl Energy {
+    fn is_stale(&self, seeds: &Seeds, now: usize) -> bool {
+        self.avg.is_none()
+            || self.energy.len() != seeds.len()
+            || now.saturating_sub(self.at) >= ENERGY_REFRESH
+    }
+
+    fn rebuild(&mut self, energy: Vec<f64>, avg: Avg, now: usize) {
+        let n = energy.len();
+        let mut tree = energy.clone();
+        for i in 1..=n {
+            let j = i + (i & i.wrapping_neg());
+            if j <= n {
+                tree[j - 1] += tree[i - 1];
+            }
+        }
+        self.total = energy.iter().sum();
+        self.energy = energy;
+        self.tree = tree;
+        self.avg = Some(avg);
+        self.at = now;
+    }
+
+    fn update(&mut self, i: usize, e: f64) {
+        let delta = e - self.energy[i];
+        self.energy[i] = e;
+        self.total += delta;
+        let mut j = i + 1;
+        while j <= self.tree.len() {
+            self.tree[j - 1] += delta;
+            j += j & j.wrapping_neg();
+        }
+    }
+
+    /// Index of seed where cumulative energy exceeds `x`.
+    fn find(&self, mut x: f64) -> usize {
+        let n = self.tree.len();
+        let mut pos = 0;
+        let mut mask = n.next_power_of_two();
+        while mask != 0 {
+            let next = pos + mask;
+            if next <= n && self.tree[next - 1] <= x {
+                x -= self.tree[next - 1];
+                pos = next;
+            }
+            mask >>= 1;
+        }
+        pos.min(n - 1)
+    }
+}
+
+pub struct Schedule {
+    power: bool,
+    bandit: bool,
+    mutate_ratio: Option<f64>,
+    arms: Mutex<Arms>,
+    groups: Mutex<BTreeMap<GroupId, GroupArm>>,
+    energy: Mutex<Energy>,
+}
+
+impl Schedule {
+    /// Schedule generating progs of `groups`, id and name of each group.
+    pub fn new<I>(conf: Option<&ScheduleConf>, groups: I) -> Self
+    where
+        I: IntoIterator<Item = (GroupId, String)>,
+    {
+        let groups = groups
+            .into_iter()
+            .map(|(gid, name)| {
+                let g = GroupArm {
+                    name,
+                    arm: Arm::default(),
+                    blocks: 0,
+                    last_found: None,
+                };
+                (gid, g)
+            })
+            .collect();
+        Self {
+            power: conf.and_then(|c| c.power).unwrap_or(true),
+            bandit: conf.and_then(|c| c.bandit).unwrap_or(true),
+            mutate_ratio: conf.and_then(|c| c.mutate_ratio),
+            arms: Mutex::default(),
+            groups: Mutex::new(groups),
+            energy: Mutex::default(),
+        }
+    }
+
+    /// Current ratio of mutated progs.
+    pub fn mutate_ratio(&self) -> f64 {
+        match self.mutate_ratio {
+            Some(ratio) => ratio,
+            None => self.arms.lock().unwrap().mutate_ratio(),
+        }
+    }
+
+    /// Whether next prog should be mutated from corpus.
+    pub fn should_mutate(&self) -> bool {
+        thread_rng().gen::<f64>() < self.mutate_ratio()
+    }
+
+    /// Whether next prog should be smashed, if any is waiting.
+    pub fn should_smash(&self) -> bool {
+        thread_rng().gen::<f64>() < SMASH_SHARE
+    }
+
+    /// Choose index of mutation operator in `OPERATORS`.
+    pub fn choose_operator(&self) -> usize {
+        let probs = self.arms.lock().unwrap().op_probs(self.bandit);
+        choose_index(&probs)
+    }
+
+    /// Choose group to generate prog of, `None` if there is no group.
+    pub fn choose_group(&self) -> Option<GroupId> {
+        let groups = self.groups.lock().unwrap();
+        if groups.is_empty() {
+            return None;
+        }
+        let probs = probs(groups.values().map(|g| &g.arm), GROUP_EXPLORE);
+        groups.keys().nth(choose_index(&probs)).copied()
+    }
+
+    /// Record what a prog of group `gid` from `src` found.
+    pub fn record(&self, src: Source, gid: GroupId, found: Found) {
+        self.record_group(gid, found);
+        let mut arms = self.arms.lock().unwrap();
+        match src {
+            Source::Gen => arms.gen.record(found.any()),
+            Source::Mutate(op) => arms.ops[op].record(found.any()),
+            Source::Smash(_) => return arms.smash.record(found.any()),
+            Source::Candidate => return,
+        }
+        let execs = arms.gen.execs + arms.ops.iter().map(|a| a.execs).sum::<usize>();
+        if execs >= YIELD_WINDOW {
+            arms.gen.decay();
+            arms.ops.iter_mut().for_each(Arm::decay);
+        }
+    }
+
+    fn record_group(&self, gid: GroupId, found: Found) {
+        let mut groups = self.groups.lock().unwrap();
+        let g = match groups.get_mut(&gid) {
+            Some(g) => g,
+            None => return,
+        };
+        g.arm.record(found.any());
+        g.blocks += found.blocks;
+        if found.any() {
+            g.last_found = Some(Instant::now());
+        }
+        if groups.values().map(|g| g.arm.execs).sum::<usize>() >= YIELD_WINDOW {
+            groups.values_mut().for_each(|g| g.arm.decay());
+        }
+    }
+
+    /// Progress and current probability of each group.
+    pub fn groups(&self) -> Vec<GroupStat> {
+        let groups = self.groups.lock().unwrap();
+        let probs = probs(groups.values().map(|g| &g.arm), GROUP_EXPLORE);
+        groups
+            .values()
+            .zip(probs)
+            .map(|(g, prob)| GroupStat {
+                name: g.name.clone(),
+                execs: g.arm.total_execs,
+                found: g.arm.total_found,
+                blocks: g.blocks,
+                idle: g.last_found.map(|t| t.elapsed().as_secs()),
+                prob,
+            })
+            .collect()
+    }
+
+    /// Success rate and current probability of generation and each operator.
+    pub fn strategies(&self) -> Vec<StrategyStat> {
+        let ratio = self.mutate_ratio();
+        let arms = self.arms.lock().unwrap();
+        let stat = |name: &str, a: &Arm, prob: f64| StrategyStat {
+            name: name.to_string(),
+            execs: a.total_execs,
+            found: a.total_found,
+            rate: if a.total_execs == 0 {
+                0.0
+            } else {
+                a.total_found as f64 / a.total_execs as f64
+            },
+            prob,
+        };
+        let mut stats = vec![stat("generate", &arms.gen, 1.0 - ratio)];
+        let probs = arms.op_probs(self.bandit);
+        for ((name, a), p) in OPERATORS.iter().zip(arms.ops.iter()).zip(probs) {
+            stats.push(stat(name, a, ratio * p));
+        }
+        stats.push(stat("smash", &arms.smash, SMASH_SHARE));
+        stats
+    }
+
+    /// Choose index of seed from `seeds` by energy, `now` is current exec
+    /// count. Without power schedule, only distance to target of directed
+    /// fuzzing counts.
+    pub fn choose(&self, seeds: &Seeds, now: usize) -> Option<usize> {
+        if seeds.is_empty() {
+            return None;
+        }
+        let mut energy = self.energy.lock().unwrap();
+        if energy.is_stale(seeds, now) {
+            let avg = Avg::of(seeds);
+            let e = seeds
+                .progs
+                .iter()
+                .zip(seeds.meta.iter())
+                .map(|(p, m)| self.energy_of(m, p.len(), &avg, now))
+                .collect();
+            energy.rebuild(e, avg, now);
+        }
+        let x = thread_rng().gen::<f64>() * energy.total;
+        Some(energy.find(x))
+    }
+
+    /// Count a mutation of seed `i`, its cached energy decays.
+    pub fn mutated(&self, seeds: &mut Seeds, i: usize, now: usize) {
+        seeds.meta[i].mutated += 1;
+        let mut energy = self.energy.lock().unwrap();
+        if energy.is_stale(seeds, now) {
+            return;
+        }
+        let e = self.energy_of(
+            &seeds.meta[i],
+            seeds.progs[i].len(),
+            energy.avg.as_ref().unwrap(),
+            now,
+        );
+        energy.update(i, e);
+    }
+
+    fn energy_of(&self, m: &SeedMeta, len: usize, avg: &Avg, now: usize) -> f64 {
+        if self.power {
+            energy(m, len, avg, now)
+        } else {
+            closeness(m)
+        }
+    }
+}
+
+/// Averages of corpus, energy of prog is relative to them.
+#[derive(Debug)]
+struct Avg {
+    signal: f64,
+    exec_us: f64,
+    len: f64,
+}
+
+impl Avg {
+    fn of(seeds: &Seeds) -> Self {
+        let n = seeds.len().max(1) as f64;
+        let timed = seeds.meta.iter().filter(|m| m.exec_us != 0);
+        let (timed_n, timed_sum) = timed.fold((0, 0), |(n, sum), m| (n + 1, sum + m.exec_us));
+        Self {
+            signal: seeds.meta.iter().map(|m| m.signal).sum::<usize>() as f64 / n,
+            exec_us: timed_sum as f64 / (timed_n as f64).max(1.0),
+            len: seeds.progs.iter().map(|p| p.len()).sum::<usize>() as f64 / n,
+        }
+    }
+}
+
+fn energy(m: &SeedMeta, len: usize, avg: &Avg, now: usize) -> f64 {
+    let speed = if m.exec_us == 0 || avg.exec_us == 0.0 {
+        1.0
+    } else {
+        (avg.exec_us / m.exec_us as f64).clamp(0.25, 3.0)
+    };
+    let signal = ((m.signal as f64 + 1.0) / (avg.signal + 1.0)).clamp(0.25, 3.0);
+    let length = (avg.len / len.max(1) as f64).clamp(0.5, 2.0);
+    let fresh = if now.saturating_sub(m.found_at) < FRESH_EXECS {
+        FRESH_BONUS
+    } else {
+        1.0
+    };
+    let fuzzed = 1.0 / (1.0 + (m.mutated as f64 + 1.0).log2());
+    speed * signal * length * fresh * fuzzed * closeness(m)
+}
+
+/// Halves for each step away from target of directed fuzzing.
+fn closeness(m: &SeedMeta) -> f64 {
+    m.distance.map_or(1.0, |d| 0.5f64.powi(d as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::prog::{Call, Prog};
+    use std::collections::HashMap;
+
+    fn found(any: bool) -> Found {
+        Found {
+            blocks: any as usize,
+            branches: 0,
+        }
+    }
+
+    fn prog(len: usize, id: usize) -> Prog {
+        let mut p = Prog::new(0);
+        for _ in 0..len {
+            p.add_call(Call::new(id));
+        }
+        p
+    }
+
+    #[test]
+    fn energy() {
+        let mut seeds = Seeds::default();
+        let meta = |signal, mutated, exec_us| SeedMeta {
+            signal,
+            found_at: 0,
+            mutated,
+            exec_us,
+            distance: None,
+        };
+        seeds.insert(prog(2, 0), meta(100, 0, 100));
+        seeds.insert(prog(8, 1), meta(10, 0, 1000));
+        seeds.insert(prog(2, 2), meta(100, 1000, 100));
+        assert_eq!(seeds.insert(prog(2, 0), meta(0, 0, 0)), None);
+
+        let avg = Avg::of(&seeds);
+        let e = seeds
+            .progs
+            .iter()
+            .zip(seeds.meta.iter())
+            .map(|(p, m)| super::energy(m, p.len(), &avg, FRESH_EXECS))
+            .collect::<Vec<_>>();
+        assert!(e[0] > e[1] && e[0] > e[2]);
+        let fresh = super::energy(&seeds.meta[0], 2, &avg, 0);
+        assert_eq!(fresh, e[0] * FRESH_BONUS);
+
+        let s = Schedule::new(None, Vec::new());
+        let mut hits = [0; 3];
+        for _ in 0..1000 {
+            hits[s.choose(&seeds, FRESH_EXECS).unwrap()] += 1;
+        }
+        assert!(hits[0] > hits[1] && hits[0] > hits[2]);
+        assert_eq!(s.choose(&Seeds::default(), 0), None);
+    }
+
+    #[test]
+    fn cached_energy() {
+        let s = Schedule::new(None, Vec::new());
+        let mut seeds = Seeds::default();
+        for i in 0..5 {
+            let m = SeedMeta {
+                signal: 10,
+                exec_us: 100,
+                distance: Some(if i == 0 { 0 } else { 8 }),
+                ..SeedMeta::default()
+            };
+            seeds.insert(prog(2, i), m);
+        }
+        let mut hits = [0; 5];
+        for _ in 0..1000 {
+            hits[s.choose(&seeds, FRESH_EXECS).unwrap()] += 1;
+        }
+        assert!(hits[0] > 900);
+
+        // mutations decay cached energy of seed
+        let before = s.energy.lock().unwrap().energy[0];
+        for _ in 0..1000 {
+            s.mutated(&mut seeds, 0, FRESH_EXECS);
+        }
+        assert_eq!(seeds.meta[0].mutated, 1000);
+        let cached = s.energy.lock().unwrap().energy.clone();
+        let avg = Avg::of(&seeds);
+        let e = super::energy(&seeds.meta[0], 2, &avg, FRESH_EXECS);
+        assert!((cached[0] - e).abs() < 1e-9);
+        assert!(cached[0] < before);
+
+        // admitted seed is sampled after cache is rebuilt
+        seeds.insert(prog(2, 9), SeedMeta::default());
+        let mut hits = [0; 6];
+        for _ in 0..1000 {
+            hits[s.choose(&seeds, FRESH_EXECS).unwrap()] += 1;
+        }
+        assert!(hits[5] > 0);
+    }
+
+    #[test]
+    fn adaptive_ratio() {
+        let s = Schedule::new(None, Vec::new());
+        assert_eq!(s.mutate_ratio(), 0.5);
+        for i in 0..100 {
+            s.record(Source::Gen, 0, found(i % 50 == 0));
+            s.record(Source::Mutate(i % 2), 0, found(i % 5 == 0));
+            s.record(Source::Candidate, 0, found(true));
+            s.record(Source::Smash(0), 0, found(false));
+        }
+        assert!(s.mutate_ratio() > 0.8);
+        for _ in 0..YIELD_WINDOW * 2 {
+            s.record(Source::Mutate(0), 0, found(false));
+        }
+        assert_eq!(s.mutate_ratio(), MIN_MUTATE_RATIO);
+
+        let conf = ScheduleConf {
+            power: Some(false),
+            mutate_ratio: Some(0.3),
+            bandit: None,
+            smash: None,
+        };
+        let s = Schedule::new(Some(&conf), Vec::new());
+        s.record(Source::Mutate(0), 0, found(true));
+        assert_eq!(s.mutate_ratio(), 0.3);
+    }
+
+    #[test]
+    fn bandit() {
+        let s = Schedule::new(None, Vec::new());
+        for i in 0..1000 {
+            s.record(Source::Mutate(0), 0, found(i % 2 == 0));
+            s.record(Source::Mutate(1), 0, found(i % 100 == 0));
+        }
+        let mut hits = [0; 2];
+        for _ in 0..1000 {
+            hits[s.choose_operator()] += 1;
+        }
+        assert!(hits[0] > hits[1] * 3);
+        // Exploration keeps the worse operator alive.
+        assert!(hits[1] > 0);
+
+        // Smash is not counted in operators.
+        for _ in 0..1000 {
+            s.record(Source::Smash(1), 0, found(true));
+        }
+        let mut hits = [0; 2];
+        for _ in 0..1000 {
+            hits[s.choose_operator()] += 1;
+        }
+        assert!(hits[0] > hits[1] * 3);
+
+        let stats = s.strategies();
+        assert_eq!(stats.len(), OPERATORS.len() + 2);
+        assert_eq!(stats[0].name, "generate");
+        assert_eq!(stats[0].execs, 0);
+        assert_eq!((stats[1].execs, stats[1].found), (1000, 500));
+        assert_eq!(stats[1].rate, 0.5);
+        let smash = stats.last().unwrap();
+        assert_eq!((smash.name.as_str(), smash.execs), ("smash", 1000));
+        let total = stats[..=OPERATORS.len()]
+            .iter()
+            .map(|s| s.prob)
+            .sum::<f64>();
+        assert!((total - 1.0).abs() < 1e-9);
+
+        let conf = ScheduleConf {
+            power: None,
+            mutate_ratio: None,
+            bandit: Some(false),
+            smash: None,
+        };
+        let s = Schedule::new(Some(&conf), Vec::new());
+        s.record(Source::Mutate(0), 0, found(true));
+        let stats = s.strategies();
+        assert_eq!(stats[1].prob, stats[2].prob);
+    }
+
+    #[test]
+    fn groups() {
+        let names = vec![(1, "Foo".to_string()), (2, "Bar".to_string())];
+        let s = Schedule::new(None, names);
+        let f = |blocks| Found {
+            blocks,
+            branches: 0,
+        };
+        for i in 0..1000 {
+            s.record(Source::Gen, 1, f(if i % 2 == 0 { 3 } else { 0 }));
+            s.record(Source::Gen, 2, f(0));
+        }
+        // Unknown group is ignored.
+        s.record(Source::Gen, 3, f(1));
+        let mut hits = HashMap::new();
+        for _ in 0..1000 {
+            *hits.entry(s.choose_group().unwrap()).or_insert(0) += 1;
+        }
+        assert!(hits[&1] > hits[&2] * 3);
+        // Minimum exploration share.
+        assert!(hits[&2] > 50);
+
+        let stats = s.groups();
+        assert_eq!(stats.len(), 2);
+        let foo = stats.iter().find(|g| g.name == "Foo").unwrap();
+        assert_eq!((foo.execs, foo.found, foo.blocks), (1000, 500, 1500));
+        assert_eq!(foo.idle, Some(0));
+        let bar = stats.iter().find(|g| g.name == "Bar").unwrap();
+        assert_eq!((bar.execs, bar.found, bar.idle), (1000, 0, None));
+        assert!(bar.prob >= GROUP_EXPLORE / 2.0);
+        assert_eq!(Schedule::new(None, Vec::new()).choose_group(), None);
+    }
+}
@@ -0,0 +1,177 @@
+//! Restrict fuzzing to a subset of calls of target.
+//!
+//! `enable_calls`/`disable_calls` of config match declared name of calls and
+//! `enable_groups`/`disable_groups` match name of groups, with glob patterns
+//! like `open@*`. Deselected calls are removed when building target and
+//! relation tables, progs calling them are trimmed, see `trim`.
+
+use crate::utils::glob;
+use core::minimize::remove;
+use core::prog::Prog;
+use core::target::Target;
+use fots::types::FnId;
+use std::collections::HashMap;
+use std::process::exit;
+
+/// Enable and disable lists of calls and groups, flattened into `Config`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SelectConf {
+    /// Only fuzz calls matching these patterns, all calls if not set
+    pub enable_calls: Option<Vec<String>>,
+    /// Never fuzz calls matching these patterns
+    pub disable_calls: Option<Vec<String>>,
+    /// Only fuzz groups matching these patterns, all groups if not set
+    pub enable_groups: Option<Vec<String>>,
+    /// Never fuzz groups matching these patterns
+    pub disable_groups: Option<Vec<String>>,
+}
+
+impl SelectConf {
+    pub fn check(&self) {
+        check_patterns("enable_calls", &self.enable_calls);
+        check_patterns("disable_calls", &self.disable_calls);
+        check_patterns("enable_groups", &self.enable_groups);
+        check_patterns("disable_groups", &self.disable_groups);
+    }
+
+    /// Calls deselected by lists, with reason.
+    pub fn deselect(&self, t: &Target) -> HashMap<FnId, String> {
+        let mut deselected = HashMap::new();
+        for g in t.iter_group() {
+            let group_selected = is_selected(&g.ident, &self.enable_groups, &self.disable_groups);
+            for f in g.iter_fn() {
+                if !group_selected {
+                    deselected.insert(f.id, "group deselected by config".to_string());
+                } else if !is_selected(&f.dec_name, &self.enable_calls, &self.disable_calls) {
+                    deselected.insert(f.id, "deselected by config".to_string());
+                }
+            }
+        }
+        deselected
+    }
+}
+
+fn check_patterns(name: &str, patterns: &Option<Vec<String>>) {
+    if let Some(patterns) = patterns.as_ref() {
+        if patterns.is_empty() {
+            eprintln!("Config Error: {} is empty", name);
+            exit(exitcode::CONFIG)
+        }
+        if patterns.iter().any(|p| p.is_empty()) {
+            eprintln!("Config Error: {} contains empty pattern", name);
+            exit(exitcode::CONFIG)
+        }
+    }
+}
+
+/// Whether `name` matches some pattern of `enable`, if set, and none of `disable`.
+fn is_selected(name: &str, enable: &Option<Vec<String>>, disable: &Option<Vec<String>>) -> bool {
+    let matches = |patterns: &Vec<String>| patterns.iter().any(|p| glob::matches(p, name));
+    enable.as_ref().is_none_or(matches) && !disable.as_ref().is_some_and(matches)
+}
+
+/// Whether every call of `p` is in target, calls disabled by config or probe are not.
+pub fn is_available(t: &Target, p: &Prog) -> bool {
+    t.groups.contains_key(&p.gid) && p.calls.iter().all(|c| t.fns.contains_key(&c.fid))
+}
+
+/// Remove calls missing in target from `p`, with calls using their results.
+/// `None` if the last call has to be removed, it's what the prog is for.
+pub fn trim(t: &Target, p: Prog) -> Option<Prog> {
+    if !t.groups.contains_key(&p.gid) {
+        return None;
+    }
+    trim_with(p, |fid| t.fns.contains_key(&fid))
+}
+
+/// Remove calls not kept by `keep` from `p`, see `trim`.
+pub fn trim_with<F: Fn(FnId) -> bool>(mut p: Prog, keep: F) -> Option<Prog> {
+    let mut i = 0;
+    while i < p.len() {
+        if keep(p.calls[i].fid) {
+            i += 1;
+        } else if i == p.len() - 1 || !remove(&mut p, i) {
+            return None;
+        }
+    }
+    Some(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::gen::gen_seq;
+    use core::test_util::target;
+    use std::collections::HashSet;
+
+    #[test]
+    fn select() {
+        let patterns = |ps: &[&str]| Some(ps.iter().map(|p| p.to_string()).collect::<Vec<_>>());
+        assert!(is_selected("open@foo", &None, &None));
+        assert!(is_selected("open@foo", &patterns(&["open@*"]), &None));
+        assert!(!is_selected("read@foo", &patterns(&["open@*"]), &None));
+        assert!(!is_selected("open@foo", &None, &patterns(&["*@foo"])));
+        assert!(!is_selected(
+            "open@foo",
+            &patterns(&["open@*"]),
+            &patterns(&["*@foo"])
+        ));
+
+        let t = target();
+        let name = |fid| t.fn_of(fid).dec_name.as_str();
+        let conf = SelectConf {
+            disable_calls: patterns(&["*_read"]),
+            disable_groups: patterns(&["Bar"]),
+            ..SelectConf::default()
+        };
+        let deselected = conf.deselect(&t);
+        let mut names = deselected.keys().map(|&fid| name(fid)).collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, ["bar_ioctl", "bar_open", "bar_stat", "foo_read"]);
+    }
+
+    #[test]
+    fn trim_prog() {
+        let t = target();
+        let g = t.iter_group().find(|g| g.ident == "Foo").unwrap();
+        let index = |name| g.index_by_name(name).unwrap();
+        let without = |name| {
+            let disabled = [g.fns[index(name)].id]
+                .iter()
+                .copied()
+                .collect::<HashSet<_>>();
+            t.without(&disabled)
+        };
+        let seq = [index("foo_open"), index("foo_read"), index("foo_stat")];
+
+        for _ in 0..16 {
+            let p = gen_seq(&seq, g.id, &t, &Default::default());
+            assert_eq!(trim(&t, p.clone()).unwrap().len(), 3);
+            assert!(trim(&without("foo_stat"), p.clone()).is_none());
+
+            let t = without("foo_read");
+            let trimmed = trim(&t, p.clone()).unwrap();
+            assert_eq!(trimmed.len(), 2);
+            assert!(is_available(&t, &trimmed));
+
+            let t = without("foo_open");
+            let trimmed = trim(&t, p).unwrap();
+            assert!(is_available(&t, &trimmed));
+            assert_eq!(
+                trimmed.calls.last().unwrap().fid,
+                g.fns[index("foo_stat")].id
+            );
+        }
+
+        // disabled call ahead of the producer used by the last call
+        let seq = [index("foo_stat"), index("foo_open"), index("foo_read")];
+        let t_without = without("foo_stat");
+        for _ in 0..16 {
+            let p = gen_seq(&seq, g.id, &t, &Default::default());
+            let trimmed = trim(&t_without, p).unwrap();
+            assert_eq!(trimmed.len(), 2);
+            assert!(is_available(&t_without, &trimmed));
+            core::c::to_prog(&trimmed, &t_without);
+        }
+    }
+}
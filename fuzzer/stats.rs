@@ -3,15 +3,18 @@ use crate::feedback::FeedBack;
 #[cfg(feature = "mail")]
 use crate::mail;
 use crate::report::TestCaseRecord;
+use crate::schedule::{GroupStat, Schedule, StrategyStat};
 use crate::utils::queue::CQueue;
 #[cfg(feature = "mail")]
 use lettre_email::EmailBuilder;
 
+use chrono::prelude::*;
+use chrono::DateTime;
 use circular_queue::CircularQueue;
 use core::prog::Prog;
 use std::process::exit;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::fs::write;
 use tokio::sync::broadcast;
 use tokio::time;
@@ -23,6 +26,100 @@ pub struct StatSource {
     pub candidates: Arc<CQueue<Prog>>,
     pub record: Arc<TestCaseRecord>,
     pub exec: Arc<AtomicUsize>,
+    pub vms: Arc<Vec<Arc<VmStat>>>,
+    pub schedule: Arc<Schedule>,
+}
+
+impl StatSource {
+    pub async fn stats(&self) -> Stats {
+        let (corpus, candidates, (normal_case, failed_case, crashed_case)) =
+            tokio::join!(self.corpus.len(), self.candidates.len(), self.record.len());
+        let (blocks, branches) = self.feedback.len();
+        Stats {
+            exec: self.exec.load(Ordering::SeqCst),
+            corpus,
+            blocks,
+            branches,
+            candidates,
+            normal_case,
+            failed_case,
+            crashed_case,
+            strategies: self.schedule.strategies(),
+            groups: self.schedule.groups(),
+        }
+    }
+
+    pub fn vms(&self) -> Vec<VmInfo> {
+        self.vms
+            .iter()
+            .enumerate()
+            .map(|(id, vm)| vm.info(id))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VmState {
+    Booting,
+    Fuzzing,
+    Reproducing,
+}
+
+/// Live state of one vm, updated by its executor.
+pub struct VmStat {
+    state: Mutex<(VmState, DateTime<Local>)>,
+    pub exec: AtomicUsize,
+    pub crashes: AtomicUsize,
+    pub boots: AtomicUsize,
+    /// Executor restarted without rebooting guest after losing connection
+    pub reconnects: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VmInfo {
+    pub id: usize,
+    pub state: VmState,
+    /// Time of entering current state
+    pub since: DateTime<Local>,
+    pub exec: usize,
+    pub crashes: usize,
+    pub boots: usize,
+    pub reconnects: usize,
+}
+
+impl Default for VmStat {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new((VmState::Booting, Local::now())),
+            exec: AtomicUsize::new(0),
+            crashes: AtomicUsize::new(0),
+            boots: AtomicUsize::new(0),
+            reconnects: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl VmStat {
+    pub fn set_state(&self, state: VmState) {
+        let mut s = self.state.lock().unwrap();
+        if s.0 != state {
+            *s = (state, Local::now());
+        }
+    }
+
+    pub fn info(&self, id: usize) -> VmInfo {
+        let (state, since) = *self.state.lock().unwrap();
+        VmInfo {
+            id,
+            state,
+            since,
+            exec: self.exec.load(Ordering::SeqCst),
+            crashes: self.crashes.load(Ordering::SeqCst),
+            boots: self.boots.load(Ordering::SeqCst),
+            reconnects: self.reconnects.load(Ordering::SeqCst),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -37,6 +134,10 @@ pub struct Stats {
     pub normal_case: usize,
     pub failed_case: usize,
     pub crashed_case: usize,
+    /// Success rate of generation and each mutation operator
+    pub strategies: Vec<StrategyStat>,
+    /// Execs, new blocks and time since last discovery of each group
+    pub groups: Vec<GroupStat>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -110,24 +211,7 @@ impl Sampler {
             time::delay_for(sample_interval).await;
             last_report += sample_interval;
 
-            let (corpus, (blocks, branches), candidates, (normal_case, failed_case, crashed_case)) = tokio::join!(
-                self.source.corpus.len(),
-                self.source.feedback.len(),
-                self.source.candidates.len(),
-                self.source.record.len()
-            );
-            let exec = self.source.exec.load(Ordering::SeqCst);
-
-            let stat = Stats {
-                exec,
-                corpus,
-                blocks,
-                branches,
-                candidates,
-                normal_case,
-                failed_case,
-                crashed_case,
-            };
+            let stat = self.source.stats().await;
 
             if report_interval <= last_report {
                 #[cfg(feature = "mail")]
@@ -135,11 +219,11 @@ impl Sampler {
                 last_report = Duration::new(0, 0);
             }
 
-            self.stats.push(stat);
             info!(
                 "exec {}, blocks {}, branches {}, failed {}, crashed {}",
-                exec, blocks, branches, failed_case, crashed_case
+                stat.exec, stat.blocks, stat.branches, stat.failed_case, stat.crashed_case
             );
+            self.stats.push(stat);
         }
     }
 
@@ -0,0 +1,47 @@
+/// Match `s` against shell style pattern, `*` matches any sequence of
+/// characters (including `/`) and `?` matches any single character.
+pub fn matches(pattern: &str, s: &str) -> bool {
+    let p = pattern.chars().collect::<Vec<_>>();
+    let s = s.chars().collect::<Vec<_>>();
+    let (mut i, mut j) = (0, 0);
+    // Position after last `*` in pattern and position in `s` it matched to.
+    let mut star = None;
+    while j < s.len() {
+        if i < p.len() && (p[i] == '?' || p[i] == s[j]) {
+            i += 1;
+            j += 1;
+        } else if i < p.len() && p[i] == '*' {
+            star = Some((i + 1, j));
+            i += 1;
+        } else if let Some((si, sj)) = star {
+            // Let last `*` eat one more character.
+            i = si;
+            j = sj + 1;
+            star = Some((si, sj + 1));
+        } else {
+            return false;
+        }
+    }
+    p[i..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn glob() {
+        assert!(matches("tcp_*", "tcp_sendmsg"));
+        assert!(matches("tcp_*", "tcp_"));
+        assert!(!matches("tcp_*", "udp_sendmsg"));
+        assert!(matches("*_sendmsg", "udp_sendmsg"));
+        assert!(matches("net/*/tcp?.c", "net/ipv4/tcp4.c"));
+        assert!(matches("net/*", "net/ipv4/tcp.c"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+        assert!(matches("*", ""));
+        assert!(!matches("?", ""));
+        assert!(matches("foo", "foo"));
+        assert!(!matches("foo", "foobar"));
+    }
+}
@@ -0,0 +1,5 @@
+fn main() {
+    // `exec` runs progs of user-space targets in-process, libraries
+    // instrumented with SanitizerCoverage resolve their callbacks from it.
+    println!("cargo:rustc-link-arg-bins=-Wl,--export-dynamic");
+}
@@ -1,39 +1,51 @@
+use fuzzer::classify::{classify, Analysis};
 use fuzzer::report::FailedCase;
-use std::collections::HashMap;
-use std::env;
 use std::fs::read;
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+use tools::load_target;
 
-fn main() {
-    let f = env::args().nth(1).unwrap();
-    let cases = read(&f).unwrap();
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "case_analyzer",
+    about = "Group failed cases by error and function, blame FOTS items responsible",
+    version = "0.1.0"
+)]
+struct Settings {
+    /// Failed cases persisted by fuzzer, e.g. failed_case.json
+    #[structopt(long, short = "f", default_value = "failed_case.json")]
+    failed: PathBuf,
 
-    let cases: Vec<FailedCase> = serde_json::from_slice(&cases).unwrap();
-    let mut reasons = HashMap::new();
+    /// Fots bin fuzzer used, quoted names of errors are resolved to types with it
+    #[structopt(long, short = "t")]
+    items: Option<PathBuf>,
 
-    for case in cases.into_iter() {
-        let r = case.reason.split(':').collect::<Vec<_>>();
-        if !r.is_empty() {
-            let reason = String::from(r.last().unwrap().trim());
-            if reason.contains("unknown type size") {
-                println!("{}", case.p);
-            }
-            if reason.contains("too many arguments to function") && !case.p.contains("setpgrp") {
-                println!("{}", case.p);
-            }
-            if reason.contains("too few arguments to function") {
-                println!("{}", case.p);
+    /// Also print progs whose normalized error contains this
+    #[structopt(long, short = "e")]
+    error: Option<String>,
+}
+
+fn main() {
+    let settings = Settings::from_args();
+    let cases = read(&settings.failed).unwrap_or_else(|e| {
+        eprintln!("Fail to read {}: {}", settings.failed.display(), e);
+        exit(1)
+    });
+    let cases: Vec<FailedCase> = serde_json::from_slice(&cases).unwrap_or_else(|e| {
+        eprintln!("Fail to parse {}: {}", settings.failed.display(), e);
+        exit(1)
+    });
+    let target = settings.items.as_ref().map(load_target);
+
+    let mut analysis = Analysis::default();
+    for case in cases.iter() {
+        analysis.add(&case.calls, &case.reason, target.as_ref());
+        if let Some(error) = settings.error.as_ref() {
+            if classify(&case.reason).error.contains(error.as_str()) {
+                println!("{}\n", case.p);
             }
-            let e = reasons.entry(reason).or_insert(0);
-            *e += 1;
         }
     }
-
-    let mut total = 0;
-    let mut reasons = reasons.into_iter().collect::<Vec<_>>();
-    reasons.sort();
-    for (e, count) in reasons.into_iter() {
-        total += count;
-        println!("{} : {}", e, count);
-    }
-    println!("===== Total {}", total);
+    print!("{}", analysis.report());
 }
@@ -0,0 +1,118 @@
+use fuzzer::corpus::read_db;
+use std::collections::HashSet;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use structopt::StructOpt;
+use tools::cover::{percent, Coverage, Image};
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "cover",
+    about = "Symbolize covered PCs, write lcov and html report",
+    version = "0.1.0"
+)]
+struct Settings {
+    /// Kernel image with debug info, e.g. vmlinux
+    #[structopt(long, short = "e")]
+    elf: PathBuf,
+
+    /// Corpus db of fuzzer
+    #[structopt(long, short = "d", required_unless = "pcs")]
+    db: Option<PathBuf>,
+
+    /// Text file of covered PCs in hex, one per line, used instead of db
+    #[structopt(long, short = "p")]
+    pcs: Option<PathBuf>,
+
+    /// Directory of kernel source, relative paths of debug info are resolved from it
+    #[structopt(long, short = "s", default_value = ".")]
+    src: PathBuf,
+
+    /// Directory to write coverage.info and coverage.html
+    #[structopt(long, short = "o", default_value = "cover")]
+    out: PathBuf,
+}
+
+fn main() {
+    let settings = Settings::from_args();
+    let pcs = match (settings.pcs.as_ref(), settings.db.as_ref()) {
+        (Some(pcs), _) => read_pcs(pcs),
+        (None, Some(db)) => db_pcs(db),
+        (None, None) => unreachable!(),
+    };
+
+    let image = Image::open(&settings.elf).unwrap_or_else(|e| {
+        eprintln!("Fail to load {}: {}", settings.elf.display(), e);
+        exit(exitcode::DATAERR)
+    });
+    let cover = Coverage::build(&image, &pcs).unwrap_or_else(|e| {
+        eprintln!("Fail to symbolize: {}", e);
+        exit(exitcode::SOFTWARE)
+    });
+
+    create_dir_all(&settings.out).unwrap_or_else(|e| {
+        eprintln!("Fail to create {}: {}", settings.out.display(), e);
+        exit(exitcode::IOERR)
+    });
+    let reports = [
+        ("coverage.info", cover.to_lcov()),
+        ("coverage.html", cover.to_html(&settings.src)),
+    ];
+    for (name, content) in reports.iter() {
+        let path = settings.out.join(name);
+        write(&path, content).unwrap_or_else(|e| {
+            eprintln!("Fail to write {}: {}", path.display(), e);
+            exit(exitcode::IOERR)
+        });
+    }
+
+    let (covered, total) = cover.total();
+    println!(
+        "PCs: {}, coverable: {}{}",
+        pcs.len(),
+        image.coverable().len(),
+        if image.kcov() {
+            ""
+        } else {
+            " (no kcov, line table used)"
+        }
+    );
+    println!(
+        "Lines: {}/{} ({}) in {} files",
+        covered,
+        total,
+        percent(covered, total),
+        cover.files.len()
+    );
+}
+
+fn db_pcs(db: &Path) -> Vec<usize> {
+    let entries = read_db(db).unwrap_or_else(|e| {
+        eprintln!("Fail to read {}: {}", db.display(), e);
+        exit(exitcode::DATAERR)
+    });
+    let pcs = entries
+        .into_iter()
+        .flat_map(|e| e.blocks)
+        .map(usize::from)
+        .collect::<HashSet<_>>();
+    pcs.into_iter().collect()
+}
+
+fn read_pcs(path: &Path) -> Vec<usize> {
+    let text = read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Fail to read {}: {}", path.display(), e);
+        exit(exitcode::NOINPUT)
+    });
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(|l| {
+            usize::from_str_radix(l.trim_start_matches("0x"), 16).unwrap_or_else(|e| {
+                eprintln!("Bad pc `{}`: {}", l, e);
+                exit(exitcode::DATAERR)
+            })
+        })
+        .collect()
+}
@@ -19,6 +19,10 @@ struct Settings {
     memleak_check: bool,
     #[structopt(short = "c", long)]
     concurrency: bool,
+    #[structopt(short = "s", long)]
+    sancov: bool,
+    #[structopt(short = "L", long = "lib-path")]
+    lib_paths: Vec<PathBuf>,
 }
 
 fn main() {
@@ -38,6 +42,9 @@ fn main() {
     let conf = Config {
         memleak_check: settings.memleak_check,
         concurrency: settings.concurrency,
+        sancov: settings.sancov,
+        lib_paths: settings.lib_paths,
+        compress: false,
     };
     match fork_exec(p, &target, &conf) {
         ExecResult::Ok(covs) => {
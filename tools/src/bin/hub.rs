@@ -0,0 +1,83 @@
+use executor::transfer::target_hash;
+use fuzzer::hub::Hub;
+use log::info;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::Arc;
+use structopt::StructOpt;
+use tokio::fs::read;
+use tokio::net::TcpListener;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "hub",
+    about = "Share corpus and crashes between fuzzers",
+    version = "0.1.0"
+)]
+struct Settings {
+    /// Address to listen on
+    #[structopt(long, short = "a", default_value = "127.0.0.1:7070")]
+    addr: String,
+
+    /// Fots target, fuzzers must use the same one
+    #[structopt(long, short = "t", default_value = "syscalls")]
+    target: PathBuf,
+
+    /// Key shared with fuzzers
+    #[structopt(long, short = "k", default_value = "")]
+    key: String,
+
+    /// Working directory of hub, keeps corpus.db and crashes
+    #[structopt(long, short = "d", default_value = "hub")]
+    dir: PathBuf,
+}
+
+#[tokio::main]
+async fn main() {
+    let settings = Settings::from_args();
+    init_logger();
+
+    let items = read(&settings.target).await.unwrap_or_else(|e| {
+        eprintln!("Fail to read {}: {}", settings.target.display(), e);
+        exit(exitcode::IOERR)
+    });
+    let hub = Hub::new(
+        settings.dir.clone(),
+        target_hash(&items),
+        settings.key.clone(),
+    )
+    .await
+    .unwrap_or_else(|e| {
+        eprintln!("Fail to load hub from {}: {}", settings.dir.display(), e);
+        exit(exitcode::IOERR)
+    });
+    let (progs, crashes) = hub.len().await;
+    info!("Loaded {} progs, {} crashes", progs, crashes);
+
+    let listener = TcpListener::bind(settings.addr.as_str())
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Fail to listen on {}: {}", settings.addr, e);
+            exit(exitcode::OSERR)
+        });
+    info!("Listening on {}", settings.addr);
+    Arc::new(hub).serve(listener).await
+}
+
+fn init_logger() {
+    use log::LevelFilter;
+    use log4rs::append::console::ConsoleAppender;
+    use log4rs::config::{Appender, Config, Root};
+    use log4rs::encode::pattern::PatternEncoder;
+
+    let stdout = ConsoleAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(
+            "{d(%Y-%m-%d %H:%M:%S)} {h({l})} - {m}{n}",
+        )))
+        .build();
+    let config = Config::builder()
+        .appender(Appender::builder().build("stdout", Box::new(stdout)))
+        .build(Root::builder().appender("stdout").build(LevelFilter::Info))
+        .unwrap();
+    log4rs::init_config(config).unwrap();
+}
@@ -1,11 +1,15 @@
+use fuzzer::corpus::read_db;
 use fuzzer::report::*;
+use fuzzer::repro::ReproCase;
+use std::collections::HashSet;
 use std::fmt::Write;
 use std::fs::create_dir_all;
 use std::fs::read;
 use std::fs::write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use structopt::StructOpt;
+use tools::cover::{percent, ratio, Coverage, Image};
 
 const BOOK_TOML: &str = r#"[book]
 title = "Healer Report"
@@ -28,6 +32,12 @@ struct Settings {
     failed: Option<PathBuf>,
     #[structopt(short = "o", long = "out")]
     out: PathBuf,
+    /// Kernel image with debug info, coverage section is added with `db`
+    #[structopt(short = "e", long = "elf")]
+    elf: Option<PathBuf>,
+    /// Corpus db of fuzzer
+    #[structopt(short = "d", long = "db")]
+    db: Option<PathBuf>,
 }
 
 fn main() {
@@ -47,17 +57,22 @@ fn main() {
         } else {
             50
         };
-        for crash in crashes.into_iter().take(len) {
-            let crash = read(&crash).unwrap_or_else(|e| {
-                eprintln!("Fail to read {:?}: {}", crash, e);
+        let crashes = crashes.into_iter().filter(|c| {
+            let name = c.to_str().unwrap_or_default();
+            !name.ends_with(".repro") && !name.ends_with(".repro.bin")
+        });
+        for crash_path in crashes.take(len) {
+            let crash = read(&crash_path).unwrap_or_else(|e| {
+                eprintln!("Fail to read {:?}: {}", crash_path, e);
                 exit(1);
             });
             let crash: CrashedCase = serde_json::from_slice(&crash).unwrap_or_else(|e| {
                 eprintln!("Fail to deserialize: {}", e);
                 exit(1);
             });
-            let crash_md = report_crash(&crash);
-            let path = format!("{}.md", crash.meta.title);
+            let repro = read_repro(&crash_path);
+            let crash_md = report_crash(&crash, repro.as_ref());
+            let path = format!("{}.md", crash.file_name());
 
             writeln!(summary, "    - [{}](crash/{})", crash.meta.title, path).unwrap();
             crashes_mds.push((path, crash_md));
@@ -113,11 +128,24 @@ fn main() {
         }
     }
 
+    let mut coverage_md = None;
+    if let (Some(elf), Some(db)) = (settings.elf.as_ref(), settings.db.as_ref()) {
+        writeln!(summary, "- [Coverage](coverage/coverage.md)").unwrap();
+        coverage_md = Some(report_coverage(elf, db));
+    }
+
     let mut out: PathBuf = settings.out;
     out.push("src");
     create_dir_all(&mut out).unwrap();
     persist(&out, "SUMMARY.md", summary);
 
+    if let Some(coverage_md) = coverage_md {
+        out.push("coverage");
+        create_dir_all(&out).unwrap();
+        persist(&out, "coverage.md", coverage_md);
+        out.pop();
+    }
+
     out.push("crash");
     create_dir_all(&out).unwrap();
     for (f_name, crash) in crashes_mds.into_iter() {
@@ -144,18 +172,50 @@ fn main() {
     write(&out, BOOK_TOML).unwrap();
 }
 
-fn report_crash(crash: &CrashedCase) -> String {
+/// Repro saved next to crash by fuzzer, if there is one.
+fn read_repro(crash: &Path) -> Option<ReproCase> {
+    let mut path = crash.as_os_str().to_owned();
+    path.push(".repro");
+    let repro = read(&path).ok()?;
+    serde_json::from_slice(&repro).ok()
+}
+
+fn report_crash(crash: &CrashedCase, repro: Option<&ReproCase>) -> String {
     let mut buf = String::new();
     writeln!(buf, "# {}", crash.meta.title).unwrap();
     writeln!(buf, "**Id**:   {}</br>", crash.meta.id).unwrap();
     writeln!(buf, "**Repo**: {}</br>", crash.repo).unwrap();
     writeln!(buf, "**Test Time**: {}</br>", crash.meta.test_time).unwrap();
+    if let Some(oops) = crash.oops.as_ref() {
+        writeln!(buf, "**Type**: {}</br>", oops.bug_type).unwrap();
+        if let Some(access) = oops.access.as_ref() {
+            writeln!(
+                buf,
+                "**Access**: {} of size {}</br>",
+                access.kind, access.size
+            )
+            .unwrap();
+        }
+        writeln!(buf, "**Frames**: {}</br>", oops.frames.join(" <- ")).unwrap();
+    }
     writeln!(buf, "## Prog").unwrap();
     writeln!(buf, "``` c").unwrap();
     for line in crash.p.lines() {
         writeln!(buf, "{}", line).unwrap();
     }
     writeln!(buf, "```").unwrap();
+    if let Some(repro) = repro {
+        writeln!(buf, "## Repro").unwrap();
+        writeln!(buf, "Execute in order on fresh guest:").unwrap();
+        for (i, p) in repro.progs.iter().enumerate() {
+            writeln!(buf, "### Prog {}", i).unwrap();
+            writeln!(buf, "``` c").unwrap();
+            for line in p.lines() {
+                writeln!(buf, "{}", line).unwrap();
+            }
+            writeln!(buf, "```").unwrap();
+        }
+    }
     writeln!(buf, "## *Crash*").unwrap();
     for line in crash.crash.to_string().lines() {
         writeln!(buf, "{}</br>", line).unwrap();
@@ -163,6 +223,78 @@ fn report_crash(crash: &CrashedCase) -> String {
     buf
 }
 
+fn report_coverage(elf: &Path, db: &Path) -> String {
+    let entries = read_db(db).unwrap_or_else(|e| {
+        eprintln!("Fail to read {:?}: {}", db, e);
+        exit(1);
+    });
+    let pcs = entries
+        .into_iter()
+        .flat_map(|e| e.blocks)
+        .map(usize::from)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let image = Image::open(elf).unwrap_or_else(|e| {
+        eprintln!("Fail to load {:?}: {}", elf, e);
+        exit(1);
+    });
+    let cover = Coverage::build(&image, &pcs).unwrap_or_else(|e| {
+        eprintln!("Fail to symbolize: {}", e);
+        exit(1);
+    });
+
+    let mut buf = String::new();
+    let (covered, total) = cover.total();
+    writeln!(buf, "# Coverage").unwrap();
+    writeln!(
+        buf,
+        "**Lines**: {}/{} ({})</br>",
+        covered,
+        total,
+        percent(covered, total)
+    )
+    .unwrap();
+    writeln!(buf, "## Files").unwrap();
+    writeln!(buf, "| File | Lines | % |").unwrap();
+    writeln!(buf, "| --- | --- | --- |").unwrap();
+    for (path, file) in cover.files.iter() {
+        let (covered, total) = ratio(&file.lines);
+        if covered != 0 {
+            writeln!(
+                buf,
+                "| {} | {}/{} | {} |",
+                path,
+                covered,
+                total,
+                percent(covered, total)
+            )
+            .unwrap();
+        }
+    }
+    writeln!(buf, "## Functions").unwrap();
+    writeln!(buf, "| Function | File | Lines | % |").unwrap();
+    writeln!(buf, "| --- | --- | --- | --- |").unwrap();
+    for (path, file) in cover.files.iter() {
+        for (name, lines) in file.funcs.iter() {
+            let (covered, total) = ratio(lines);
+            if covered != 0 {
+                writeln!(
+                    buf,
+                    "| {} | {} | {}/{} | {} |",
+                    name,
+                    path,
+                    covered,
+                    total,
+                    percent(covered, total)
+                )
+                .unwrap();
+            }
+        }
+    }
+    buf
+}
+
 fn report_failed(failed: &FailedCase) -> String {
     let mut buf = String::new();
     writeln!(buf, "# {}", failed.meta.title).unwrap();
@@ -0,0 +1,90 @@
+use core::c;
+use core::gen::gen_seq;
+use executor::exec::jit::compile;
+use executor::Config;
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+use tools::load_target;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "selftest",
+    about = "Check that progs of every described call translate to C that compiles",
+    version = "0.1.0"
+)]
+struct Settings {
+    /// Fots target
+    #[structopt(long, short = "i", default_value = "syscalls")]
+    items: PathBuf,
+
+    /// Progs generated for each call
+    #[structopt(long, short = "n", default_value = "8")]
+    num: usize,
+
+    /// Link compiled progs with libc and libraries of group as well
+    #[structopt(long, short = "l")]
+    link: bool,
+
+    /// Extra paths to search libraries declared with `#[lib(...)]`
+    #[structopt(long = "lib-path", short = "L")]
+    lib_paths: Vec<PathBuf>,
+
+    /// Don't print C of failed progs
+    #[structopt(long, short = "q")]
+    quiet: bool,
+}
+
+fn main() {
+    let settings = Settings::from_args();
+    if settings.num == 0 {
+        eprintln!("Number of progs should be positive");
+        exit(exitcode::USAGE)
+    }
+    let target = load_target(&settings.items);
+    let conf = Config {
+        memleak_check: false,
+        concurrency: false,
+        sancov: false,
+        lib_paths: settings.lib_paths.clone(),
+        compress: false,
+    };
+
+    let mut fns = target
+        .iter_group()
+        .flat_map(|g| g.iter_fn().enumerate().map(move |(i, f)| (g, i, f)))
+        .collect::<Vec<_>>();
+    fns.sort_by(|a, b| (&a.0.ident, &a.2.dec_name).cmp(&(&b.0.ident, &b.2.dec_name)));
+
+    let mut failed = 0;
+    for (g, i, f) in fns.iter() {
+        let mut errors = 0;
+        let mut first = None;
+        for _ in 0..settings.num {
+            let p = gen_seq(&[*i], g.id, &target, &Default::default());
+            if let Err(e) = compile(&p, &target, &conf, settings.link) {
+                errors += 1;
+                first.get_or_insert((p, e));
+            }
+        }
+        if let Some((p, e)) = first {
+            failed += 1;
+            println!(
+                "FAIL {}::{} ({}/{}): {}",
+                g.ident, f.dec_name, errors, settings.num, e
+            );
+            if !settings.quiet {
+                println!("{}\n", c::to_prog(&p, &target));
+            }
+        }
+    }
+    println!(
+        "===== {}/{} calls compiled, {} failed",
+        fns.len() - failed,
+        fns.len(),
+        failed
+    );
+    if failed != 0 {
+        exit(1)
+    }
+}
@@ -0,0 +1,571 @@
+//! Source level coverage from raw PCs.
+//!
+//! PCs recorded by kcov are return addresses of `__sanitizer_cov_trace_pc`
+//! calls, so address before each of them is mapped to function, file and line
+//! with `llvm-symbolizer`, or `addr2line` if it is not installed. Coverable PCs
+//! are the call sites found by disassembling the same image with `objdump` of
+//! binutils. Images not built with kcov, e.g. a local test binary with debug
+//! info, fall back to rows of DWARF line table: their addresses are coverable
+//! PCs and covered PCs are taken as instruction addresses.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::fs::read_to_string;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+const KCOV_CALLBACK: &str = "<__sanitizer_cov_trace_pc>";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO:{0}")]
+    IO(#[from] io::Error),
+    #[error("{0}: {1}")]
+    Tool(&'static str, String),
+}
+
+/// Source location of a PC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub func: String,
+    pub file: String,
+    pub line: u32,
+}
+
+/// ELF image with debug info, e.g. vmlinux.
+pub struct Image {
+    path: PathBuf,
+    kcov: bool,
+    coverable: Vec<usize>,
+}
+
+impl Image {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut coverable = kcov_pcs(&path)?;
+        let kcov = !coverable.is_empty();
+        if !kcov {
+            coverable = line_table(&path)?;
+        }
+        coverable.sort_unstable();
+        coverable.dedup();
+        Ok(Self {
+            path,
+            kcov,
+            coverable,
+        })
+    }
+
+    /// Whether image is instrumented by kcov.
+    pub fn kcov(&self) -> bool {
+        self.kcov
+    }
+
+    pub fn coverable(&self) -> &[usize] {
+        &self.coverable
+    }
+
+    /// Map each pc to its source location, `None` for pc without debug info.
+    pub fn symbolize(&self, pcs: &[usize]) -> Result<Vec<Option<Frame>>, Error> {
+        let addrs = pcs
+            .iter()
+            .map(|&pc| if self.kcov { pc.saturating_sub(1) } else { pc })
+            .collect::<Vec<_>>();
+        addr2line(&self.path, &addrs)
+    }
+}
+
+/// Return addresses of kcov callback calls in image.
+fn kcov_pcs(path: &Path) -> Result<Vec<usize>, Error> {
+    let mut objdump = spawn(
+        "objdump",
+        Command::new("objdump")
+            .arg("-d")
+            .arg("--no-show-raw-insn")
+            .arg(path),
+    )?;
+    let out = BufReader::new(objdump.stdout.take().unwrap());
+    let mut pcs = Vec::new();
+    let mut after_call = false;
+    for line in out.lines() {
+        let line = line?;
+        if let Some((addr, insn)) = parse_insn(&line) {
+            if after_call {
+                pcs.push(addr);
+            }
+            after_call = insn.starts_with("call") && insn.ends_with(KCOV_CALLBACK);
+        }
+    }
+    wait("objdump", objdump)?;
+    Ok(pcs)
+}
+
+/// Parse disassembled instruction like `ffffffff81000a4b:\tcall   ffffffff811f2b40 <foo>`.
+fn parse_insn(line: &str) -> Option<(usize, &str)> {
+    let (addr, insn) = line.trim_start().split_once(":\t")?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    Some((addr, insn.trim()))
+}
+
+/// Start addresses of rows of DWARF line table.
+fn line_table(path: &Path) -> Result<Vec<usize>, Error> {
+    let mut objdump = spawn(
+        "objdump",
+        Command::new("objdump").arg("--dwarf=decodedline").arg(path),
+    )?;
+    let out = BufReader::new(objdump.stdout.take().unwrap());
+    let mut pcs = Vec::new();
+    for line in out.lines() {
+        if let Some(pc) = parse_row(&line?) {
+            pcs.push(pc);
+        }
+    }
+    wait("objdump", objdump)?;
+    Ok(pcs)
+}
+
+/// Parse row like `a.c    12    0x1149    x`, end of sequence has `-` as line.
+fn parse_row(line: &str) -> Option<usize> {
+    let cols = line.split_whitespace().collect::<Vec<_>>();
+    let i = cols.iter().position(|c| c.starts_with("0x"))?;
+    if i < 2 || cols[i - 1].parse::<u32>().map_or(true, |l| l == 0) {
+        return None;
+    }
+    usize::from_str_radix(&cols[i][2..], 16).ok()
+}
+
+/// Prefer llvm-symbolizer, addr2line of some binutils releases resolves file
+/// names of DWARF 5 line tables wrongly.
+fn symbolizer() -> &'static str {
+    let llvm = Command::new("llvm-symbolizer")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .status();
+    if llvm.is_ok_and(|s| s.success()) {
+        "llvm-symbolizer"
+    } else {
+        "addr2line"
+    }
+}
+
+fn addr2line(path: &Path, addrs: &[usize]) -> Result<Vec<Option<Frame>>, Error> {
+    let tool = symbolizer();
+    let llvm = tool == "llvm-symbolizer";
+    let mut cmd = Command::new(tool);
+    if llvm {
+        cmd.arg("--obj").arg(path);
+    } else {
+        cmd.arg("-f").arg("-e").arg(path);
+    }
+    let mut child = spawn(tool, cmd.stdin(Stdio::piped()))?;
+    // Feed addresses from another thread, otherwise both pipes may fill up.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = addrs
+        .iter()
+        .fold(String::new(), |mut buf, addr| {
+            writeln!(buf, "0x{:x}", addr).unwrap();
+            buf
+        })
+        .into_bytes();
+    let feeder = thread::spawn(move || stdin.write_all(&input));
+
+    let mut out = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut frames = Vec::with_capacity(addrs.len());
+    for _ in addrs {
+        let (func, loc) = match (out.next(), out.next()) {
+            (Some(func), Some(loc)) => (func?, loc?),
+            _ => return Err(Error::Tool(tool, "unexpected end of output".into())),
+        };
+        // llvm-symbolizer prints frames of inlined calls, innermost first,
+        // then an empty line.
+        if llvm {
+            for line in &mut out {
+                if line?.is_empty() {
+                    break;
+                }
+            }
+        }
+        frames.push(parse_frame(func, &loc, llvm));
+    }
+    feeder.join().unwrap()?;
+    wait(tool, child)?;
+    Ok(frames)
+}
+
+/// Parse function name and location, which is `file:line (discriminator n)`
+/// from addr2line or `file:line:column` from llvm-symbolizer.
+fn parse_frame(func: String, loc: &str, llvm: bool) -> Option<Frame> {
+    let mut loc = loc.split(" (").next().unwrap();
+    if llvm {
+        loc = loc.rsplit_once(':')?.0;
+    }
+    let (file, line) = loc.rsplit_once(':')?;
+    let line = line.parse::<u32>().ok()?;
+    if file == "??" || line == 0 {
+        return None;
+    }
+    Some(Frame {
+        func,
+        file: file.to_string(),
+        line,
+    })
+}
+
+fn spawn(tool: &'static str, cmd: &mut Command) -> Result<Child, Error> {
+    cmd.stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Tool(tool, e.to_string()))
+}
+
+fn wait(tool: &'static str, child: Child) -> Result<(), Error> {
+    let out = child.wait_with_output()?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        let err = String::from_utf8_lossy(&out.stderr);
+        Err(Error::Tool(tool, err.trim().to_string()))
+    }
+}
+
+/// Covered and coverable lines of one source file, `true` if covered.
+#[derive(Debug, Default)]
+pub struct FileCover {
+    pub lines: BTreeMap<u32, bool>,
+    pub funcs: BTreeMap<String, BTreeMap<u32, bool>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Coverage {
+    pub files: BTreeMap<String, FileCover>,
+}
+
+impl Coverage {
+    /// Symbolize coverable PCs of image and `covered` PCs.
+    pub fn build(image: &Image, covered: &[usize]) -> Result<Self, Error> {
+        let covered_set = covered.iter().copied().collect::<HashSet<_>>();
+        let mut pcs = image.coverable().to_vec();
+        pcs.extend(
+            covered_set
+                .iter()
+                .filter(|pc| image.coverable.binary_search(pc).is_err()),
+        );
+        let frames = image.symbolize(&pcs)?;
+        let frames = pcs
+            .iter()
+            .zip(frames)
+            .filter_map(|(pc, f)| f.map(|f| (f, covered_set.contains(pc))));
+        Ok(Self::from_frames(frames))
+    }
+
+    pub fn from_frames<I: IntoIterator<Item = (Frame, bool)>>(frames: I) -> Self {
+        let mut cover = Coverage::default();
+        for (f, covered) in frames {
+            let file = cover.files.entry(f.file).or_default();
+            *file.lines.entry(f.line).or_default() |= covered;
+            let func = file.funcs.entry(f.func).or_default();
+            *func.entry(f.line).or_default() |= covered;
+        }
+        cover
+    }
+
+    /// Covered and coverable lines of all files.
+    pub fn total(&self) -> (usize, usize) {
+        self.files.values().fold((0, 0), |(c, t), f| {
+            let (fc, ft) = ratio(&f.lines);
+            (c + fc, t + ft)
+        })
+    }
+
+    /// Trace file of lcov, lines are hit once if covered.
+    pub fn to_lcov(&self) -> String {
+        let mut buf = String::new();
+        writeln!(buf, "TN:healer").unwrap();
+        for (path, file) in self.files.iter() {
+            writeln!(buf, "SF:{}", path).unwrap();
+            for (name, lines) in file.funcs.iter() {
+                writeln!(buf, "FN:{},{}", lines.keys().next().unwrap(), name).unwrap();
+            }
+            for (name, lines) in file.funcs.iter() {
+                let hit = lines.values().any(|&c| c) as usize;
+                writeln!(buf, "FNDA:{},{}", hit, name).unwrap();
+            }
+            let hit = file.funcs.values().filter(|l| l.values().any(|&c| c));
+            writeln!(buf, "FNF:{}", file.funcs.len()).unwrap();
+            writeln!(buf, "FNH:{}", hit.count()).unwrap();
+            for (line, &covered) in file.lines.iter() {
+                writeln!(buf, "DA:{},{}", line, covered as usize).unwrap();
+            }
+            let (covered, total) = ratio(&file.lines);
+            writeln!(buf, "LF:{}", total).unwrap();
+            writeln!(buf, "LH:{}", covered).unwrap();
+            writeln!(buf, "end_of_record").unwrap();
+        }
+        buf
+    }
+
+    /// Single page with coverage of each file and function, and source of
+    /// covered files found under `src` with covered lines highlighted.
+    pub fn to_html(&self, src: &Path) -> String {
+        let mut buf = String::new();
+        buf.push_str(HTML_HEAD);
+        let (covered, total) = self.total();
+        writeln!(
+            buf,
+            "<h1>Coverage</h1><p>{} of {} lines ({})</p>",
+            covered,
+            total,
+            percent(covered, total)
+        )
+        .unwrap();
+
+        writeln!(buf, "<table><tr><th>File</th><th>Lines</th><th>%</th></tr>").unwrap();
+        for (i, (path, file)) in self.files.iter().enumerate() {
+            let (covered, total) = ratio(&file.lines);
+            writeln!(
+                buf,
+                "<tr><td><a href=\"#f{}\">{}</a></td><td>{}/{}</td><td>{}</td></tr>",
+                i,
+                escape(path),
+                covered,
+                total,
+                percent(covered, total)
+            )
+            .unwrap();
+        }
+        writeln!(buf, "</table>").unwrap();
+
+        for (i, (path, file)) in self.files.iter().enumerate() {
+            let (covered, total) = ratio(&file.lines);
+            writeln!(
+                buf,
+                "<h2 id=\"f{}\">{} ({})</h2>",
+                i,
+                escape(path),
+                percent(covered, total)
+            )
+            .unwrap();
+            writeln!(
+                buf,
+                "<table><tr><th>Function</th><th>Lines</th><th>%</th></tr>"
+            )
+            .unwrap();
+            for (name, lines) in file.funcs.iter() {
+                let (covered, total) = ratio(lines);
+                writeln!(
+                    buf,
+                    "<tr><td>{}</td><td>{}/{}</td><td>{}</td></tr>",
+                    escape(name),
+                    covered,
+                    total,
+                    percent(covered, total)
+                )
+                .unwrap();
+            }
+            writeln!(buf, "</table>").unwrap();
+            if covered != 0 {
+                if let Ok(text) = read_to_string(src.join(path)) {
+                    source(&mut buf, &text, &file.lines);
+                }
+            }
+        }
+        buf.push_str("</body></html>\n");
+        buf
+    }
+}
+
+/// Covered and coverable lines.
+pub fn ratio(lines: &BTreeMap<u32, bool>) -> (usize, usize) {
+    (lines.values().filter(|&&c| c).count(), lines.len())
+}
+
+pub fn percent(covered: usize, total: usize) -> String {
+    if total == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.1}%", covered as f64 * 100.0 / total as f64)
+    }
+}
+
+fn source(buf: &mut String, text: &str, lines: &BTreeMap<u32, bool>) {
+    writeln!(buf, "<pre>").unwrap();
+    for (i, line) in text.lines().enumerate() {
+        let class = match lines.get(&(i as u32 + 1)) {
+            Some(true) => "c",
+            Some(false) => "u",
+            None => "n",
+        };
+        writeln!(
+            buf,
+            "<span class=\"{}\">{:>6} {}</span>",
+            class,
+            i + 1,
+            escape(line)
+        )
+        .unwrap();
+    }
+    writeln!(buf, "</pre>").unwrap();
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>Healer Coverage</title>
+<style>
+body { font-family: sans-serif; margin: 1em 2em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 2px 8px; text-align: left; }
+pre { font-size: 12px; }
+.c { background: #c8f0c8; }
+.u { background: #f8d0d0; }
+</style></head><body>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, write};
+
+    const SRC: &str = "int foo(int x) {
+    if (x > 3)
+        return x * 2;
+    return x + 1;
+}
+
+int main(int argc, char **argv) {
+    return foo(argc);
+}
+";
+
+    /// Callback of kcov, only call sites of it matter.
+    const STUB: &str =
+        "__attribute__((no_sanitize_coverage)) void __sanitizer_cov_trace_pc(void) {}\n";
+
+    #[test]
+    fn parse_objdump() {
+        let line = "ffffffff81000a4b:\tcall   ffffffff811f2b40 <__sanitizer_cov_trace_pc>";
+        let (addr, insn) = parse_insn(line).unwrap();
+        assert_eq!(addr, 0xffff_ffff_8100_0a4b);
+        assert!(insn.ends_with(KCOV_CALLBACK));
+        assert_eq!(parse_insn("Disassembly of section .text:"), None);
+
+        assert_eq!(parse_row("a.c      12      0x1149       x"), Some(0x1149));
+        assert_eq!(parse_row("a.c      12      0x1149   1"), Some(0x1149));
+        assert_eq!(parse_row("a.c       -      0x1177"), None);
+        assert_eq!(
+            parse_row("File name    Line number    Starting address"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_addr2line() {
+        let f = parse_frame("foo".into(), "/src/a.c:12 (discriminator 3)", false).unwrap();
+        assert_eq!(f.file, "/src/a.c");
+        assert_eq!(f.line, 12);
+        assert_eq!(parse_frame("??".into(), "??:0", false), None);
+        let f = parse_frame("foo".into(), "/src/a.c:12:7", true).unwrap();
+        assert_eq!((f.file.as_str(), f.line), ("/src/a.c", 12));
+        assert_eq!(parse_frame("??".into(), "??:0:0", true), None);
+    }
+
+    #[test]
+    fn lcov() {
+        let frame = |func: &str, line| Frame {
+            func: func.to_string(),
+            file: "a.c".to_string(),
+            line,
+        };
+        let cover = Coverage::from_frames(vec![
+            (frame("foo", 1), true),
+            (frame("foo", 2), false),
+            (frame("main", 7), false),
+            (frame("main", 7), true),
+        ]);
+        assert_eq!(cover.total(), (2, 3));
+        let lcov = cover.to_lcov();
+        assert!(lcov.contains("SF:a.c\nFN:1,foo\nFN:7,main\nFNDA:1,foo\nFNDA:1,main\n"));
+        assert!(lcov.contains("DA:1,1\nDA:2,0\nDA:7,1\nLF:3\nLH:2\nend_of_record\n"));
+    }
+
+    /// Write `SRC` to a temp dir and build it with debug info and `flags`.
+    fn build(name: &str, flags: &[&str]) -> (PathBuf, PathBuf) {
+        let dir = temp_dir().join(format!("healer-cover-{}-{}", std::process::id(), name));
+        create_dir_all(&dir).unwrap();
+        let src = dir.join("a.c");
+        let bin = dir.join("a");
+        write(&src, SRC).unwrap();
+        write(dir.join("stub.c"), STUB).unwrap();
+        let status = Command::new("cc")
+            .args(["-g", "-O0", "-o"])
+            .arg(&bin)
+            .args(flags)
+            .arg(&src)
+            .arg(dir.join("stub.c"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        (dir, src)
+    }
+
+    /// Cover `foo` of a binary without kcov.
+    #[test]
+    fn local_binary() {
+        let (dir, src) = build("local", &[]);
+        let bin = dir.join("a");
+        let image = Image::open(&bin).unwrap();
+        assert!(!image.kcov());
+        let frames = image.symbolize(image.coverable()).unwrap();
+        let foo = image
+            .coverable()
+            .iter()
+            .zip(frames)
+            .filter(|(_, f)| f.as_ref().is_some_and(|f| f.func == "foo"))
+            .map(|(pc, _)| *pc)
+            .collect::<Vec<_>>();
+        assert!(!foo.is_empty());
+
+        let cover = Coverage::build(&image, &foo).unwrap();
+        let file = &cover.files[src.to_str().unwrap()];
+        assert!(file.funcs["foo"].values().all(|&c| c));
+        assert!(file.funcs["main"].values().all(|&c| !c));
+        assert_eq!(file.lines.get(&2), Some(&true));
+        assert_eq!(file.lines.get(&8), Some(&false));
+
+        let html = cover.to_html(Path::new("/"));
+        assert!(html.contains("<span class=\"c\">     2     if (x &gt; 3)</span>"));
+        assert!(html.contains("<span class=\"u\">     8     return foo(argc);</span>"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Cover `main` of a binary instrumented like kernel with kcov.
+    #[test]
+    fn kcov_binary() {
+        let (dir, src) = build("kcov", &["-fsanitize-coverage=trace-pc"]);
+        let image = Image::open(dir.join("a")).unwrap();
+        assert!(image.kcov());
+        let frames = image.symbolize(image.coverable()).unwrap();
+        let main = image
+            .coverable()
+            .iter()
+            .zip(frames)
+            .filter(|(_, f)| f.as_ref().is_some_and(|f| f.func == "main"))
+            .map(|(pc, _)| *pc)
+            .collect::<Vec<_>>();
+        assert!(!main.is_empty());
+
+        let cover = Coverage::build(&image, &main).unwrap();
+        let file = &cover.files[src.to_str().unwrap()];
+        assert!(file.funcs["main"].values().all(|&c| c));
+        assert!(file.funcs["foo"].values().all(|&c| !c));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
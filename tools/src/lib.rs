@@ -9,6 +9,7 @@ use std::fs::read;
 use std::path::PathBuf;
 use std::process::exit;
 
+pub mod cover;
 pub mod def2flag;
 
 pub fn load_target(items: &PathBuf) -> Target {